@@ -606,28 +606,28 @@ impl ModuleDef {
         self.out_expr = Some(Box::new(expr));
     }
 
-    pub fn imports_to_absolute(&mut self, base: PathBuf) {
-        &base;
+    /// Rewrites the relative import and include paths in this module to
+    /// absolute paths. Modules run in the caller's VM so the paths must be
+    /// resolved here against the module's own directory. A path that isn't
+    /// found there is looked up in the import paths and falls back to the
+    /// module's directory so a missing file is reported against it.
+    pub fn imports_to_absolute(&mut self, base: PathBuf, import_paths: &[PathBuf]) {
+        let make_absolute = |path: PathBuf| -> String {
+            let abs = std::iter::once(&base)
+                .chain(import_paths.iter())
+                .map(|dir| dir.join(&path))
+                .find(|candidate| candidate.exists())
+                .unwrap_or_else(|| base.join(&path));
+            #[cfg(not(windows))]
+            let abs = abs.canonicalize().unwrap_or(abs);
+            abs.to_string_lossy().to_string()
+        };
         let rewrite_import = |e: &mut Expression| {
             let main_separator = format!("{}", std::path::MAIN_SEPARATOR);
             if let Expression::Include(ref mut def) = e {
                 let path = PathBuf::from(&def.path.fragment);
-                #[cfg(not(windows))]
-                {
-                    if path.is_relative() {
-                        def.path.fragment = base
-                            .join(path)
-                            .canonicalize()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string();
-                    }
-                }
-                #[cfg(windows)]
-                {
-                    if path.is_relative() {
-                        def.path.fragment = base.join(path).to_string_lossy().to_string();
-                    }
+                if path.is_relative() {
+                    def.path.fragment = make_absolute(path);
                 }
             }
             if let Expression::Import(ref mut def) = e {
@@ -641,22 +641,8 @@ impl ModuleDef {
                 if path.starts_with(format!("std{}", main_separator)) {
                     return;
                 }
-                #[cfg(not(windows))]
-                {
-                    if path.is_relative() {
-                        def.path.fragment = base
-                            .join(path)
-                            .canonicalize()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string();
-                    }
-                }
-                #[cfg(windows)]
-                {
-                    if path.is_relative() {
-                        def.path.fragment = base.join(path).to_string_lossy().to_string();
-                    }
+                if path.is_relative() {
+                    def.path.fragment = make_absolute(path);
                 }
             }
         };
@@ -664,6 +650,9 @@ impl ModuleDef {
         for stmt in self.statements.iter_mut() {
             walker.walk_statement(stmt);
         }
+        if let Some(ref mut expr) = self.out_expr {
+            walker.walk_expression(expr);
+        }
     }
 }
 
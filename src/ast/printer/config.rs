@@ -0,0 +1,203 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatter configuration read from `.ucgfmt.toml` files.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use simple_error::SimpleError;
+
+/// The name of the file `ucg fmt` looks for its configuration in.
+pub const CONFIG_FILE_NAME: &str = ".ucgfmt.toml";
+
+/// How blank lines between statements are handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlankLines {
+    /// Every statement is separated from the one before it by a blank line.
+    Always,
+    /// Statements are separated by a blank line only where the source had at
+    /// least one. Runs of blank lines are collapsed into one.
+    Preserve,
+}
+
+/// The configuration for an AstPrinter.
+///
+/// A `.ucgfmt.toml` file looks like this. Every key is optional.
+///
+/// ```toml
+/// indent = 2
+/// max_width = 100
+/// trailing_commas = false
+/// blank_lines = "preserve"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmtConfig {
+    pub indent: usize,
+    pub max_width: Option<usize>,
+    /// Whether the last item of a tuple, list or argument list that is broken
+    /// across lines gets a comma.
+    pub trailing_commas: bool,
+    pub blank_lines: BlankLines,
+}
+
+impl Default for FmtConfig {
+    fn default() -> Self {
+        FmtConfig {
+            indent: 4,
+            max_width: None,
+            trailing_commas: true,
+            blank_lines: BlankLines::Always,
+        }
+    }
+}
+
+fn config_error(msg: String) -> Box<dyn Error> {
+    Box::new(SimpleError::new(msg))
+}
+
+fn positive_int(key: &str, val: &toml::Value) -> Result<usize, Box<dyn Error>> {
+    match val.as_integer() {
+        Some(i) if i > 0 => Ok(i as usize),
+        _ => Err(config_error(format!(
+            "{} must be a positive integer but was {}",
+            key, val
+        ))),
+    }
+}
+
+impl FmtConfig {
+    /// Parses the contents of a `.ucgfmt.toml` file.
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let val = contents.parse::<toml::Value>()?;
+        let table = match val.as_table() {
+            Some(t) => t,
+            None => return Err(config_error("Expected a table".to_string())),
+        };
+        let mut config = Self::default();
+        for (key, val) in table.iter() {
+            match key.as_str() {
+                "indent" => config.indent = positive_int(key, val)?,
+                "max_width" => config.max_width = Some(positive_int(key, val)?),
+                "trailing_commas" => match val.as_bool() {
+                    Some(b) => config.trailing_commas = b,
+                    None => {
+                        return Err(config_error(format!(
+                            "trailing_commas must be true or false but was {}",
+                            val
+                        )));
+                    }
+                },
+                "blank_lines" => match val.as_str() {
+                    Some("always") => config.blank_lines = BlankLines::Always,
+                    Some("preserve") => config.blank_lines = BlankLines::Preserve,
+                    _ => {
+                        return Err(config_error(format!(
+                            "blank_lines must be \"always\" or \"preserve\" but was {}",
+                            val
+                        )));
+                    }
+                },
+                _ => return Err(config_error(format!("Unknown setting {}", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Finds the `.ucgfmt.toml` that applies to a file by looking in the
+    /// file's directory and then each of its parents.
+    pub fn find(file: &Path) -> std::io::Result<Option<PathBuf>> {
+        let file = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        for dir in file.ancestors().skip(1) {
+            let candidate = dir.join(CONFIG_FILE_NAME);
+            if candidate.is_file() {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads and parses a `.ucgfmt.toml` file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents)
+            .map_err(|e| config_error(format!("Invalid config {}: {}", path.to_string_lossy(), e)))
+    }
+
+    /// Loads the configuration that applies to a file. Files without a
+    /// `.ucgfmt.toml` get the default configuration.
+    pub fn for_file(file: &Path) -> Result<Self, Box<dyn Error>> {
+        match Self::find(file)? {
+            Some(path) => Self::load(&path),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty_is_default() {
+        assert_eq!(FmtConfig::parse("").unwrap(), FmtConfig::default());
+    }
+
+    #[test]
+    fn test_parse_all_settings() {
+        let config = FmtConfig::parse(
+            "indent = 2\nmax_width = 100\ntrailing_commas = false\nblank_lines = \"preserve\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            FmtConfig {
+                indent: 2,
+                max_width: Some(100),
+                trailing_commas: false,
+                blank_lines: BlankLines::Preserve,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_settings() {
+        assert!(FmtConfig::parse("indent = 0").is_err());
+        assert!(FmtConfig::parse("max_width = \"wide\"").is_err());
+        assert!(FmtConfig::parse("trailing_commas = 1").is_err());
+        assert!(FmtConfig::parse("blank_lines = \"never\"").is_err());
+        assert!(FmtConfig::parse("indnet = 2").is_err());
+    }
+
+    #[test]
+    fn test_for_file_walks_up_to_the_config() {
+        let root = std::env::temp_dir().join(format!("ucgfmt-test-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(CONFIG_FILE_NAME), "indent = 3\n").unwrap();
+        let file = nested.join("foo.ucg");
+        assert_eq!(
+            FmtConfig::find(&file).unwrap(),
+            Some(root.join(CONFIG_FILE_NAME))
+        );
+        assert_eq!(FmtConfig::for_file(&file).unwrap().indent, 3);
+        fs::write(nested.join(CONFIG_FILE_NAME), "indent = 5\n").unwrap();
+        assert_eq!(FmtConfig::for_file(&file).unwrap().indent, 5);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -11,27 +11,63 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::collections::BTreeSet;
 use std::io::Write;
 
 use crate::ast::*;
 use crate::parse::CommentMap;
 
+pub mod config;
+
+pub use config::{BlankLines, FmtConfig};
+
+/// Keeps track of the column the next write will start at.
+struct ColumnWriter<W: Write> {
+    w: W,
+    col: usize,
+}
+
+impl<W: Write> Write for ColumnWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.w.write(buf)?;
+        for b in buf[..n].iter() {
+            if *b == b'\n' {
+                self.col = 0;
+            } else if *b & 0xC0 != 0x80 {
+                // Only count the first byte of a utf8 character.
+                self.col += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
+    }
+}
+
 pub struct AstPrinter<'a, W>
 where
     W: Write,
 {
     indent_size: usize,
     curr_indent: usize,
-    w: W,
+    w: ColumnWriter<W>,
     // Indexed by line that the comment was on.
     // We use this to determine when to print a comment in our AstPrinter
     comment_map: Option<&'a CommentMap>,
     last_line: usize,
     comment_group_lines: Vec<usize>,
+    // The line width we try to stay under if we have one.
+    max_width: Option<usize>,
+    // Whether we are rendering everything on one line.
+    flat: bool,
+    trailing_commas: bool,
+    blank_lines: BlankLines,
+    // The lines that were blank in the source we are formatting.
+    blank_source_lines: Option<BTreeSet<usize>>,
 }
 
-// TODO(jwall): At some point we probably want to be more aware of line length
-// in our formatting. But not at the moment.
 impl<'a, W> AstPrinter<'a, W>
 where
     W: Write,
@@ -41,12 +77,27 @@ where
             indent_size: indent,
             curr_indent: 0,
             comment_map: None,
-            w: w,
+            w: ColumnWriter { w: w, col: 0 },
             last_line: 0,
             comment_group_lines: Vec::new(),
+            max_width: None,
+            flat: false,
+            trailing_commas: true,
+            blank_lines: BlankLines::Always,
+            blank_source_lines: None,
         }
     }
 
+    /// Constructs an AstPrinter that lays things out the way the config
+    /// says to.
+    pub fn from_config(config: &FmtConfig, w: W) -> Self {
+        let mut printer = Self::new(config.indent, w);
+        printer.max_width = config.max_width;
+        printer.trailing_commas = config.trailing_commas;
+        printer.blank_lines = config.blank_lines;
+        printer
+    }
+
     pub fn with_comment_map(mut self, map: &'a CommentMap) -> Self {
         self.comment_group_lines = map.keys().cloned().collect();
         self.comment_group_lines.reverse();
@@ -54,6 +105,82 @@ where
         self
     }
 
+    /// Keeps lines under the width where we can.
+    ///
+    /// Tuples, lists, argument lists and binary operator chains are put on a
+    /// single line when they fit and broken across lines when they don't.
+    /// Without a max width tuples, lists and argument lists are always broken
+    /// and binary operator chains never are.
+    pub fn with_max_width(mut self, width: usize) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+
+    /// The source the statements were parsed from. `BlankLines::Preserve`
+    /// uses it to find the blank lines between statements. Without it every
+    /// statement is separated by a blank line.
+    pub fn with_source(mut self, src: &str) -> Self {
+        self.blank_source_lines = Some(
+            src.lines()
+                .enumerate()
+                .filter(|(_, l)| l.trim().is_empty())
+                .map(|(i, _)| i + 1)
+                .collect(),
+        );
+        self
+    }
+
+    /// Renders on a single line into a buffer. Returns None if that took more
+    /// than one line which is always the case when there are comments in the
+    /// way.
+    fn render_flat<F>(&self, render: F) -> std::io::Result<Option<String>>
+    where
+        F: FnOnce(&mut AstPrinter<'a, Vec<u8>>) -> std::io::Result<()>,
+    {
+        let mut printer = AstPrinter {
+            indent_size: self.indent_size,
+            curr_indent: self.curr_indent,
+            w: ColumnWriter {
+                w: Vec::new(),
+                col: self.w.col,
+            },
+            comment_map: self.comment_map,
+            last_line: self.last_line,
+            comment_group_lines: self.comment_group_lines.clone(),
+            max_width: None,
+            flat: true,
+            trailing_commas: self.trailing_commas,
+            blank_lines: BlankLines::Always,
+            blank_source_lines: None,
+        };
+        render(&mut printer)?;
+        let line = String::from_utf8_lossy(&printer.w.w).to_string();
+        if line.contains('\n') {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+
+    /// Writes what `render` renders on a single line if that fits in the max
+    /// width. Returns whether it did.
+    fn render_flat_if_fits<F>(&mut self, render: F) -> std::io::Result<bool>
+    where
+        F: FnOnce(&mut AstPrinter<'a, Vec<u8>>) -> std::io::Result<()>,
+    {
+        let width = match self.max_width {
+            Some(width) => width,
+            None => return Ok(false),
+        };
+        if let Some(line) = self.render_flat(render)? {
+            // Leave room for the , or ; that usually follows.
+            if self.w.col + line.chars().count() < width {
+                self.w.write_all(line.as_bytes())?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     fn make_indent(&self) -> String {
         // TODO(jwall): This is probably inefficient but we'll improve it after
         // we get it correct.
@@ -90,7 +217,9 @@ where
                     Some(c) => c,
                     None => '\0',
                 };
-                if !first_char.is_whitespace() {
+                if c.fragment.trim().is_empty() {
+                    write!(self.w, "{}//\n", indent)?;
+                } else if !first_char.is_whitespace() {
                     write!(self.w, "{}// {}\n", indent, c.fragment.trim_end())?;
                 } else {
                     write!(self.w, "{}//{}\n", indent, c.fragment.trim_end())?;
@@ -134,18 +263,32 @@ where
     }
 
     fn render_list_def(&mut self, def: &ListDef) -> std::io::Result<()> {
+        if self.render_flat_if_fits(|p| p.render_list_def(def))? {
+            return Ok(());
+        }
         write!(self.w, "[")?;
+        if self.flat {
+            let mut prefix = "";
+            for e in def.elems.iter() {
+                self.render_comment_if_needed(e.pos().line)?;
+                write!(self.w, "{}", prefix)?;
+                self.render_expr(e)?;
+                prefix = ", ";
+            }
+            write!(self.w, "]")?;
+            return Ok(());
+        }
         self.curr_indent += self.indent_size;
         let indent = self.make_indent();
         let has_fields = def.elems.len() > 0;
         if has_fields {
             write!(self.w, "\n")?;
         }
-        for e in def.elems.iter() {
+        for (i, e) in def.elems.iter().enumerate() {
             self.render_comment_if_needed(e.pos().line)?;
             write!(self.w, "{}", indent)?;
             self.render_expr(e)?;
-            write!(self.w, ",\n")?;
+            self.render_item_end(i == def.elems.len() - 1)?;
         }
         self.curr_indent -= self.indent_size;
         if has_fields {
@@ -156,7 +299,22 @@ where
     }
 
     fn render_tuple_def(&mut self, def: &Vec<(Token, Expression)>) -> std::io::Result<()> {
+        if self.render_flat_if_fits(|p| p.render_tuple_def(def))? {
+            return Ok(());
+        }
         self.w.write(&['{' as u8])?;
+        if self.flat {
+            let mut prefix = "";
+            for (t, expr) in def.iter() {
+                self.render_comment_if_needed(t.pos.line)?;
+                write!(self.w, "{}", prefix)?;
+                self.render_field_name(t)?;
+                self.render_expr(expr)?;
+                prefix = ", ";
+            }
+            write!(self.w, "}}")?;
+            return Ok(());
+        }
         // If the field list is just 1 we might be able to collapse the tuple.
         self.curr_indent += self.indent_size;
         let indent = self.make_indent();
@@ -164,7 +322,7 @@ where
         if has_fields {
             write!(self.w, "\n")?;
         }
-        for &(ref t, ref expr) in def.iter() {
+        for (i, &(ref t, ref expr)) in def.iter().enumerate() {
             let field_line = t.pos.line;
             let expr_line = expr.pos().line;
             self.render_comment_if_needed(field_line)?;
@@ -172,14 +330,9 @@ where
                 self.render_comment_if_needed(expr_line)?;
             }
             write!(self.w, "{}", indent)?;
-            if Self::is_bareword(&t.fragment) {
-                write!(&mut self.w, "{} = ", t.fragment)?;
-            } else {
-                write!(self.w, "\"{}\" = ", Self::escape_quotes(&t.fragment))?;
-            }
+            self.render_field_name(t)?;
             self.render_expr(expr)?;
-            write!(&mut self.w, ",")?;
-            write!(self.w, "\n")?;
+            self.render_item_end(i == def.len() - 1)?;
         }
         self.curr_indent -= self.indent_size;
         if has_fields {
@@ -189,6 +342,179 @@ where
         Ok(())
     }
 
+    // Ends an item of a tuple, list or argument list broken across lines.
+    fn render_item_end(&mut self, last: bool) -> std::io::Result<()> {
+        if !last || self.trailing_commas {
+            write!(self.w, ",")?;
+        }
+        writeln!(self.w)
+    }
+
+    fn render_field_name(&mut self, t: &Token) -> std::io::Result<()> {
+        if Self::is_bareword(&t.fragment) {
+            write!(&mut self.w, "{} = ", t.fragment)?;
+        } else {
+            write!(self.w, "\"{}\" = ", Self::escape_quotes(&t.fragment))?;
+        }
+        Ok(())
+    }
+
+    fn binary_op(kind: &BinaryExprType) -> &'static str {
+        match kind {
+            BinaryExprType::AND => " && ",
+            BinaryExprType::OR => " || ",
+            BinaryExprType::DOT => ".",
+            BinaryExprType::Equal => " == ",
+            BinaryExprType::NotEqual => " != ",
+            BinaryExprType::GTEqual => " >= ",
+            BinaryExprType::LTEqual => " <= ",
+            BinaryExprType::GT => " > ",
+            BinaryExprType::LT => " < ",
+            BinaryExprType::Add => " + ",
+            BinaryExprType::Sub => " - ",
+            BinaryExprType::Mul => " * ",
+            BinaryExprType::Div => " / ",
+            BinaryExprType::Mod => " %% ",
+            BinaryExprType::IN => " in ",
+            BinaryExprType::IS => " is ",
+            BinaryExprType::REMatch => " ~ ",
+            BinaryExprType::NotREMatch => " !~ ",
+        }
+    }
+
+    /// Collects the operands and operators of a chain of binary operators in
+    /// order. Selectors are kept whole.
+    fn binary_chain<'e>(
+        expr: &'e Expression,
+        operands: &mut Vec<&'e Expression>,
+        ops: &mut Vec<&'static str>,
+    ) {
+        if let Expression::Binary(def) = expr {
+            if def.kind != BinaryExprType::DOT {
+                Self::binary_chain(&def.left, operands, ops);
+                ops.push(Self::binary_op(&def.kind));
+                Self::binary_chain(&def.right, operands, ops);
+                return;
+            }
+        }
+        operands.push(expr);
+    }
+
+    fn render_binary_def(&mut self, def: &BinaryOpDef) -> std::io::Result<()> {
+        if self.max_width.is_some() && def.kind != BinaryExprType::DOT {
+            if self.render_flat_if_fits(|p| p.render_binary_def(def))? {
+                return Ok(());
+            }
+            // Break the chain after each operator.
+            let mut operands = Vec::new();
+            let mut ops = Vec::new();
+            Self::binary_chain(&def.left, &mut operands, &mut ops);
+            ops.push(Self::binary_op(&def.kind));
+            Self::binary_chain(&def.right, &mut operands, &mut ops);
+            self.render_expr(operands[0])?;
+            self.curr_indent += self.indent_size;
+            for (op, operand) in ops.iter().zip(operands[1..].iter()) {
+                writeln!(self.w, "{}", op.trim_end())?;
+                // render_expr indents after any comments it renders.
+                if !self.has_comment(operand.pos().line) {
+                    write!(self.w, "{}", self.make_indent())?;
+                }
+                self.render_expr(operand)?;
+            }
+            self.curr_indent -= self.indent_size;
+            return Ok(());
+        }
+        let right_line = def.right.pos().line;
+        self.render_expr(&def.left)?;
+        self.w.write_all(Self::binary_op(&def.kind).as_bytes())?;
+        if self.has_comment(right_line) {
+            // if we'll be rendering a comment then we should
+            // add a new line here
+            self.w.write_all("\n".as_bytes())?;
+        }
+        self.render_expr(&def.right)?;
+        Ok(())
+    }
+
+    fn render_call_def(&mut self, def: &CallDef) -> std::io::Result<()> {
+        if self.render_flat_if_fits(|p| p.render_call_def(def))? {
+            return Ok(());
+        }
+        self.render_value(&def.funcref)?;
+        self.w.write_all("(".as_bytes())?;
+        if self.flat {
+            let mut prefix = "";
+            for e in def.arglist.iter() {
+                self.render_comment_if_needed(e.pos().line)?;
+                write!(self.w, "{}", prefix)?;
+                self.render_expr(e)?;
+                prefix = ", ";
+            }
+            self.w.write_all(")".as_bytes())?;
+            return Ok(());
+        }
+        self.curr_indent += self.indent_size;
+        let indent = self.make_indent();
+        let has_args = def.arglist.len() > 1;
+        if has_args {
+            writeln!(self.w)?;
+        }
+        for (i, e) in def.arglist.iter().enumerate() {
+            self.render_comment_if_needed(e.pos().line)?;
+            if has_args {
+                write!(self.w, "{}", indent)?;
+            }
+            self.render_expr(e)?;
+            if has_args {
+                self.render_item_end(i == def.arglist.len() - 1)?;
+            }
+        }
+        self.curr_indent -= self.indent_size;
+        if has_args {
+            write!(self.w, "{}", self.make_indent())?;
+        }
+        self.w.write_all(")".as_bytes())?;
+        Ok(())
+    }
+
+    fn render_format_args(&mut self, es: &Vec<Expression>) -> std::io::Result<()> {
+        if self.render_flat_if_fits(|p| p.render_format_args(es))? {
+            return Ok(());
+        }
+        if self.flat {
+            self.w.write_all("(".as_bytes())?;
+            let mut prefix = "";
+            for e in es.iter() {
+                self.render_comment_if_needed(e.pos().line)?;
+                write!(self.w, "{}", prefix)?;
+                self.render_expr(e)?;
+                prefix = ", ";
+            }
+            self.w.write_all(")".as_bytes())?;
+            return Ok(());
+        }
+        self.w.write_all("(\n".as_bytes())?;
+        self.curr_indent += self.indent_size;
+        let indent = self.make_indent();
+        let mut prefix = if es
+            .first()
+            .and_then(|e| Some(self.has_comment(e.pos().line)))
+            .unwrap_or(false)
+        {
+            "\n"
+        } else {
+            ""
+        };
+        for e in es.iter() {
+            write!(self.w, "{}{}", prefix, indent)?;
+            self.render_expr(e)?;
+            prefix = ",\n";
+        }
+        self.curr_indent -= self.indent_size;
+        self.w.write_all(")".as_bytes())?;
+        Ok(())
+    }
+
     fn escape_quotes(s: &str) -> String {
         let mut escaped = String::new();
         for c in s.chars() {
@@ -208,7 +534,16 @@ where
             Value::Boolean(b) => write!(self.w, "{}", if b.val { "true" } else { "false" })?,
             Value::Empty(_) => write!(self.w, "NULL")?,
             // TODO(jwall): We should maintain precision for floats?
-            Value::Float(f) => write!(self.w, "{}", f.val)?,
+            Value::Float(f) => {
+                // Display never uses exponents which ucg can't parse but it
+                // drops the .0 that keeps whole floats from reading back as
+                // ints.
+                let mut num = f.val.to_string();
+                if !num.contains('.') {
+                    num.push_str(".0");
+                }
+                write!(self.w, "{}", num)?
+            }
             Value::Int(i) => write!(self.w, "{}", i.val)?,
             Value::Str(s) => write!(self.w, "\"{}\"", Self::escape_quotes(&s.val))?,
             Value::Symbol(s) => write!(self.w, "{}", s.val)?,
@@ -228,35 +563,7 @@ where
         let mut did_indent = false;
         match expr {
             Expression::Binary(_def) => {
-                let op = match _def.kind {
-                    BinaryExprType::AND => " && ",
-                    BinaryExprType::OR => " || ",
-                    BinaryExprType::DOT => ".",
-                    BinaryExprType::Equal => " == ",
-                    BinaryExprType::NotEqual => " != ",
-                    BinaryExprType::GTEqual => " >= ",
-                    BinaryExprType::LTEqual => " <= ",
-                    BinaryExprType::GT => " > ",
-                    BinaryExprType::LT => " < ",
-                    BinaryExprType::Add => " + ",
-                    BinaryExprType::Sub => " - ",
-                    BinaryExprType::Mul => " * ",
-                    BinaryExprType::Div => " / ",
-                    BinaryExprType::Mod => " %% ",
-                    BinaryExprType::IN => " in ",
-                    BinaryExprType::IS => " is ",
-                    BinaryExprType::REMatch => " ~ ",
-                    BinaryExprType::NotREMatch => " !~ ",
-                };
-                let right_line = _def.right.pos().line;
-                self.render_expr(&_def.left)?;
-                self.w.write(op.as_bytes())?;
-                if self.has_comment(right_line) {
-                    // if we'll be rendering a comment then we should
-                    // add a new line here
-                    self.w.write("\n".as_bytes())?;
-                }
-                self.render_expr(&_def.right)?;
+                self.render_binary_def(_def)?;
             }
             Expression::Cast(def) => {
                 self.w.write(format!("{}", def.cast_type).as_bytes())?;
@@ -266,29 +573,7 @@ where
                 self.w.write(")".as_bytes())?;
             }
             Expression::Call(_def) => {
-                self.render_value(&_def.funcref)?;
-                self.w.write("(".as_bytes())?;
-                self.curr_indent += self.indent_size;
-                let indent = self.make_indent();
-                let has_args = _def.arglist.len() > 1;
-                if has_args {
-                    write!(self.w, "\n")?;
-                }
-                for e in _def.arglist.iter() {
-                    self.render_comment_if_needed(e.pos().line)?;
-                    if has_args {
-                        write!(self.w, "{}", indent)?;
-                    }
-                    self.render_expr(e)?;
-                    if has_args {
-                        self.w.write(",\n".as_bytes())?;
-                    }
-                }
-                self.curr_indent -= self.indent_size;
-                if has_args {
-                    write!(self.w, "{}", self.make_indent())?;
-                }
-                self.w.write(")".as_bytes())?;
+                self.render_call_def(_def)?;
             }
             Expression::Copy(_def) => {
                 self.render_value(&_def.selector)?;
@@ -319,25 +604,7 @@ where
                         self.render_expr(e)?;
                     }
                     FormatArgs::List(ref es) => {
-                        self.w.write("(\n".as_bytes())?;
-                        self.curr_indent += self.indent_size;
-                        let indent = self.make_indent();
-                        let mut prefix = if es
-                            .first()
-                            .and_then(|e| Some(self.has_comment(e.pos().line)))
-                            .unwrap_or(false)
-                        {
-                            "\n"
-                        } else {
-                            ""
-                        };
-                        for e in es.iter() {
-                            write!(self.w, "{}{}", prefix, indent)?;
-                            self.render_expr(e)?;
-                            prefix = ",\n";
-                        }
-                        self.curr_indent -= self.indent_size;
-                        self.w.write(")".as_bytes())?;
+                        self.render_format_args(es)?;
                     }
                 }
             }
@@ -476,12 +743,9 @@ where
                 }
                 write!(self.w, "{{\n")?;
                 self.curr_indent += self.indent_size;
-                let indent = self.make_indent();
-                let mut prefix_newline = false;
-                for stmt in _def.statements.iter() {
-                    write!(self.w, "{}", indent)?;
+                for (i, stmt) in _def.statements.iter().enumerate() {
+                    let prefix_newline = i > 0 && self.needs_blank_line(stmt);
                     self.render_stmt(stmt, prefix_newline)?;
-                    prefix_newline = true;
                 }
                 self.curr_indent -= self.indent_size;
                 write!(self.w, "}}")?;
@@ -503,8 +767,8 @@ where
                 self.render_expr(&_def.start)?;
                 write!(self.w, ":")?;
                 if let Some(ref e) = _def.step {
-                    write!(self.w, ":")?;
                     self.render_expr(e)?;
+                    write!(self.w, ":")?;
                 }
                 self.render_expr(&_def.end)?;
             }
@@ -546,6 +810,7 @@ where
         }
         let line = stmt.pos().line;
         self.render_comment_if_needed(line)?;
+        write!(self.w, "{}", self.make_indent())?;
         match stmt {
             Statement::Let(def) => {
                 write!(&mut self.w, "let {} = ", def.name.fragment)?;
@@ -572,11 +837,31 @@ where
         Ok(())
     }
 
+    // Whether a statement should be separated from the one before it by a
+    // blank line.
+    fn needs_blank_line(&self, stmt: &Statement) -> bool {
+        let blank_source_lines = match self.blank_source_lines {
+            Some(ref lines) if self.blank_lines == BlankLines::Preserve => lines,
+            _ => return true,
+        };
+        // Any comments before the statement stay attached to it.
+        let mut first_line = stmt.pos().line;
+        if let (Some(map), Some(next_comment_line)) =
+            (self.comment_map, self.comment_group_lines.last())
+        {
+            if *next_comment_line < first_line {
+                if let Some(c) = map.get(next_comment_line).and_then(|cg| cg.first()) {
+                    first_line = c.pos.line;
+                }
+            }
+        }
+        blank_source_lines.contains(&(first_line - 1))
+    }
+
     pub fn render(&mut self, stmts: &Vec<Statement>) -> std::io::Result<()> {
-        let mut prefix_newline = false;
-        for v in stmts {
+        for (i, v) in stmts.iter().enumerate() {
+            let prefix_newline = i > 0 && self.needs_blank_line(v);
             self.render_stmt(v, prefix_newline)?;
-            prefix_newline = true;
         }
         let comment_line = self.comment_group_lines.first().cloned();
         if let Some(last_comment_line) = comment_line {
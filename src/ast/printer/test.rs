@@ -30,12 +30,38 @@ fn print_to_buffer(input: &str) -> String {
     String::from_utf8(buffer).unwrap()
 }
 
+fn print_to_buffer_with_width(input: &str, width: usize) -> String {
+    let mut comment_map = BTreeMap::new();
+    let stmts = assert_parse(input, Some(&mut comment_map));
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut printer = AstPrinter::new(2, &mut buffer)
+        .with_comment_map(&comment_map)
+        .with_max_width(width);
+    assert!(printer.render(&stmts).is_ok());
+    String::from_utf8(buffer).unwrap()
+}
+
 #[test]
 fn test_simple_value_printing() {
     let input = "1;";
     assert_eq!(print_to_buffer(input), format!("{}\n", input));
 }
 
+#[test]
+fn test_float_value_printing() {
+    let input = "1.0;";
+    assert_eq!(print_to_buffer(input), format!("{}\n", input));
+}
+
+#[test]
+fn test_tiny_and_huge_float_value_printing() {
+    for input in &["0.0000001;", "100000000000000000000.0;"] {
+        let output = print_to_buffer(input);
+        assert_eq!(output, format!("{}\n", input));
+        assert_parse(&output, None);
+    }
+}
+
 #[test]
 fn test_simple_selector_printing() {
     let input = "foo.bar.quux;";
@@ -177,6 +203,18 @@ fn test_trace_expr_printing() {
     assert_eq!(print_to_buffer(input), format!("{}\n", input));
 }
 
+#[test]
+fn test_range_expr_printing() {
+    let input = "1:10;";
+    assert_eq!(print_to_buffer(input), format!("{}\n", input));
+}
+
+#[test]
+fn test_range_expr_with_step_printing() {
+    let input = "1:2:10;";
+    assert_eq!(print_to_buffer(input), format!("{}\n", input));
+}
+
 #[test]
 fn test_module_no_out_expr_printing() {
     let input = "let m = module {
@@ -193,6 +231,12 @@ fn test_module_no_out_expr_printing() {
     assert_eq!(print_to_buffer(input), format!("{}\n", input));
 }
 
+#[test]
+fn test_module_multiple_statements_printing() {
+    let input = "let m = module {} => {\n  let d = 4;\n\n  // about e\n  let e = 5;\n};\n";
+    assert_eq!(print_to_buffer(input), input);
+}
+
 #[test]
 fn test_module_with_out_expr_printing() {
     let input = "let m = module {
@@ -248,6 +292,12 @@ fn test_statement_with_comment_printing() {
     assert_eq!(print_to_buffer(input), format!("{}\n", input));
 }
 
+#[test]
+fn test_statement_with_empty_comment_line_printing() {
+    let input = "// foo\n//\n// bar\n1;";
+    assert_eq!(print_to_buffer(input), format!("{}\n", input));
+}
+
 #[test]
 fn test_statement_with_comment_printing_groups() {
     let input = "// add 1\n// and 1\n1 + 1;";
@@ -389,3 +439,91 @@ fn test_grouped_expression_with_embedded_comment() {
     let output = print_to_buffer(input);
     assert_eq!(output, format!("{}\n", input.trim()));
 }
+
+#[test]
+fn test_width_collapses_what_fits() {
+    let input = "let t = {\n  a = 1,\n  b = [1, 2],\n};\n\nlet c = f(\n  a,\n  b,\n);\n\nlet s = \"%s %s\" % (a, b);\n";
+    let expected =
+        "let t = {a = 1, b = [1, 2]};\n\nlet c = f(a, b);\n\nlet s = \"%s %s\" % (a, b);\n";
+    assert_eq!(print_to_buffer_with_width(input, 80), expected);
+    assert_eq!(print_to_buffer_with_width(expected, 80), expected);
+}
+
+#[test]
+fn test_width_breaks_what_does_not_fit() {
+    let input = "let t = {a = \"aaaaaaaaaa\", b = [\"bbbbbbbbbb\", \"cccccccccc\"]};";
+    let expected =
+        "let t = {\n  a = \"aaaaaaaaaa\",\n  b = [\"bbbbbbbbbb\", \"cccccccccc\"],\n};\n";
+    assert_eq!(print_to_buffer_with_width(input, 40), expected);
+    assert_eq!(print_to_buffer_with_width(expected, 40), expected);
+    let input = "let c = some.call(\"aaaaaaaaaa\", \"bbbbbbbbbb\");";
+    let expected = "let c = some.call(\n  \"aaaaaaaaaa\",\n  \"bbbbbbbbbb\",\n);\n";
+    assert_eq!(print_to_buffer_with_width(input, 40), expected);
+}
+
+#[test]
+fn test_width_breaks_binary_operator_chains() {
+    let input = "let s = \"aaaaaaaaaa\" + foo.bar + \"bbbbbbbbbb\" + \"cccccccccc\";";
+    let expected = "let s = \"aaaaaaaaaa\" +\n  foo.bar +\n  \"bbbbbbbbbb\" +\n  \"cccccccccc\";\n";
+    assert_eq!(print_to_buffer_with_width(input, 40), expected);
+    assert_eq!(print_to_buffer_with_width(expected, 40), expected);
+    let input = "let b = 1 + 2 == 3 && true;";
+    assert_eq!(
+        print_to_buffer_with_width(input, 40),
+        format!("{}\n", input)
+    );
+}
+
+#[test]
+fn test_width_keeps_comments() {
+    let input = "let t = {\n  // a comment\n  a = 1,\n};\n\nlet s = \"aaaaaaaaaa\" +\n  // the b's\n  \"bbbbbbbbbb\" +\n  \"cccccccccc\";\n";
+    assert_eq!(print_to_buffer_with_width(input, 40), input);
+}
+
+fn print_to_buffer_with_config(input: &str, config: &FmtConfig) -> String {
+    let mut comment_map = BTreeMap::new();
+    let stmts = assert_parse(input, Some(&mut comment_map));
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut printer = AstPrinter::from_config(config, &mut buffer)
+        .with_comment_map(&comment_map)
+        .with_source(input);
+    assert!(printer.render(&stmts).is_ok());
+    String::from_utf8(buffer).unwrap()
+}
+
+#[test]
+fn test_config_without_trailing_commas() {
+    let config = FmtConfig {
+        indent: 2,
+        trailing_commas: false,
+        ..FmtConfig::default()
+    };
+    let input = "let t = {\n  a = [\n    1,\n    2\n  ],\n  b = f(\n    1,\n    2\n  )\n};\n";
+    assert_eq!(print_to_buffer_with_config(input, &config), input);
+}
+
+#[test]
+fn test_config_preserves_blank_lines() {
+    let config = FmtConfig {
+        indent: 2,
+        blank_lines: BlankLines::Preserve,
+        ..FmtConfig::default()
+    };
+    let input = "let a = 1;\nlet b = 2;\n\n\n// about c\nlet c = 3;\nlet m = module {} => {\n  let d = 4;\n  let e = 5;\n\n  let f = 6;\n};\n";
+    let expected = "let a = 1;\nlet b = 2;\n\n// about c\nlet c = 3;\nlet m = module {} => {\n  let d = 4;\n  let e = 5;\n\n  let f = 6;\n};\n";
+    assert_eq!(print_to_buffer_with_config(input, &config), expected);
+    assert_eq!(print_to_buffer_with_config(expected, &config), expected);
+}
+
+#[test]
+fn test_config_always_separates_statements() {
+    let config = FmtConfig {
+        indent: 2,
+        ..FmtConfig::default()
+    };
+    let input = "let a = 1;\nlet b = 2;\n";
+    assert_eq!(
+        print_to_buffer_with_config(input, &config),
+        "let a = 1;\n\nlet b = 2;\n"
+    );
+}
@@ -9,6 +9,12 @@ pub trait Walker {
 
     fn walk_statement(&mut self, stmt: &mut Statement) {
         self.visit_statement(stmt);
+        self.walk_statement_children(stmt);
+    }
+
+    /// Walks the expressions in a statement without visiting the statement
+    /// itself. Useful for Walkers that override walk_statement.
+    fn walk_statement_children(&mut self, stmt: &mut Statement) {
         match stmt {
             Statement::Let(ref mut def) => {
                 self.walk_expression(&mut def.value);
@@ -34,10 +40,34 @@ pub trait Walker {
         }
     }
 
+    fn walk_value(&mut self, val: &mut Value) {
+        self.visit_value(val);
+        match val {
+            Value::Tuple(ref mut fs) => {
+                self.walk_fieldset(&mut fs.val);
+            }
+            Value::List(ref mut def) => {
+                for expr in def.elems.iter_mut() {
+                    self.walk_expression(expr);
+                }
+            }
+            _ => {
+                // noop
+            }
+        }
+    }
+
     fn walk_expression(&mut self, expr: &mut Expression) {
         self.visit_expression(expr);
+        self.walk_expression_children(expr);
+    }
+
+    /// Walks the values and expressions in an expression without visiting the
+    /// expression itself. Useful for Walkers that override walk_expression.
+    fn walk_expression_children(&mut self, expr: &mut Expression) {
         match expr {
             Expression::Call(ref mut def) => {
+                self.walk_value(&mut def.funcref);
                 for expr in def.arglist.iter_mut() {
                     self.walk_expression(expr);
                 }
@@ -46,6 +76,7 @@ pub trait Walker {
                 self.walk_expression(&mut def.target);
             }
             Expression::Copy(ref mut def) => {
+                self.walk_value(&mut def.selector);
                 self.walk_fieldset(&mut def.fields);
             }
             Expression::Format(ref mut def) => match def.args {
@@ -60,13 +91,16 @@ pub trait Walker {
             },
             Expression::FuncOp(ref mut def) => match def {
                 FuncOpDef::Reduce(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                     self.walk_expression(def.acc.as_mut())
                 }
                 FuncOpDef::Map(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                 }
                 FuncOpDef::Filter(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                 }
             },
@@ -83,6 +117,9 @@ pub trait Walker {
                 for stmt in def.statements.iter_mut() {
                     self.walk_statement(stmt);
                 }
+                if let Some(ref mut expr) = def.out_expr {
+                    self.walk_expression(expr.as_mut());
+                }
             }
             Expression::Range(ref mut def) => {
                 self.walk_expression(def.start.as_mut());
@@ -104,9 +141,12 @@ pub trait Walker {
                 self.walk_fieldset(&mut def.tuple);
             }
             Expression::Simple(ref mut val) => {
-                self.visit_value(val);
+                self.walk_value(val);
+            }
+            Expression::Fail(ref mut def) => {
+                self.walk_expression(def.message.as_mut());
             }
-            Expression::Import(_) | Expression::Include(_) | Expression::Fail(_) => {
+            Expression::Import(_) | Expression::Include(_) => {
                 //noop
             }
             Expression::Not(ref mut def) => {
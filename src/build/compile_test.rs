@@ -13,11 +13,13 @@
 //  limitations under the License.
 
 use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use regex::Regex;
 
 //TODO(jwall): use super::assets::MemoryCache;
-use super::FileBuilder;
+use super::{FileBuilder, Val};
 use crate::build::opcode::Environment;
 
 fn assert_build(input: &str) {
@@ -592,3 +594,491 @@ fn test_invalid_call_selector() {
         ],
     )
 }
+
+fn import_path_fixture(dir: &str) -> PathBuf {
+    std::env::current_dir()
+        .unwrap()
+        .join("integration_tests")
+        .join("import_paths")
+        .join(dir)
+}
+
+fn assert_import_path_build(input: &str, name: &str, expected: Val) {
+    let i_paths = vec![import_path_fixture("first"), import_path_fixture("second")];
+    let out_buffer: Vec<u8> = Vec::new();
+    let err_buffer: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out_buffer, err_buffer));
+    let mut b = FileBuilder::new(import_path_fixture("project"), &i_paths, &env);
+    b.eval_string(input).unwrap();
+    assert_eq!(b.get_out_by_name(name).unwrap(), Rc::new(expected));
+}
+
+#[test]
+fn test_import_searches_import_path_in_order() {
+    assert_import_path_build(
+        "let source = (import \"shared_lib.ucg\").source;",
+        "source",
+        Val::Str("first".to_owned()),
+    );
+}
+
+#[test]
+fn test_import_falls_through_to_later_import_path() {
+    assert_import_path_build(
+        "let source = (import \"only_second.ucg\").source;",
+        "source",
+        Val::Str("second".to_owned()),
+    );
+}
+
+#[test]
+fn test_import_local_file_shadows_import_path() {
+    assert_import_path_build(
+        "let source = (import \"shadowed.ucg\").source;",
+        "source",
+        Val::Str("project".to_owned()),
+    );
+}
+
+#[test]
+fn test_import_from_import_path_in_module() {
+    assert_import_path_build(
+        "let mod = module{} => (lib.source) {
+            let lib = import \"shared_lib.ucg\";
+        };
+        let source = mod{};",
+        "source",
+        Val::Str("first".to_owned()),
+    );
+}
+
+#[test]
+fn test_include_searches_import_path() {
+    assert_import_path_build(
+        "let source = include str \"data.txt\";",
+        "source",
+        Val::Str("first".to_owned()),
+    );
+}
+
+#[test]
+fn test_import_missing_from_import_path_compile_failure() {
+    let i_paths = vec![import_path_fixture("first"), import_path_fixture("second")];
+    let out_buffer: Vec<u8> = Vec::new();
+    let err_buffer: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out_buffer, err_buffer));
+    let mut b = FileBuilder::new(import_path_fixture("project"), &i_paths, &env);
+    let err = b
+        .eval_string("let lib = import \"no_such_lib.ucg\";")
+        .unwrap_err();
+    assert!(format!("{}", err).contains("OSError: Path not found"));
+}
+
+fn assert_broken_import_failure(input: &str, expect: Vec<Regex>) {
+    let i_paths = Vec::new();
+    let out_buffer: Vec<u8> = Vec::new();
+    let err_buffer: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out_buffer, err_buffer));
+    let dir = std::env::current_dir()
+        .unwrap()
+        .join("integration_tests")
+        .join("broken_imports");
+    let mut b = FileBuilder::new(dir, &i_paths, &env);
+    let err = format!("{}", b.eval_string(input).unwrap_err());
+    for r in expect.iter() {
+        assert!(r.is_match(&err), "[{}] was not found in:\n{}", r, err);
+    }
+}
+
+#[test]
+fn test_import_syntax_error_compile_failure() {
+    assert_broken_import_failure(
+        "let lib = import \"syntax_error.ucg\";",
+        vec![
+            Regex::new(r"ParseError: .* at file: .*syntax_error.ucg line: 2 column: \d+").unwrap(),
+            Regex::new(r"\nVIA: line: 1 column: 18").unwrap(),
+        ],
+    );
+}
+
+#[test]
+fn test_nested_import_syntax_error_compile_failure() {
+    assert_broken_import_failure(
+        "let lib = import \"imports_syntax_error.ucg\";",
+        vec![
+            Regex::new(r"ParseError: .* at file: .*syntax_error.ucg line: 2 column: \d+").unwrap(),
+            Regex::new(r"\nVIA: file: .*imports_syntax_error.ucg line: 1 column: 18").unwrap(),
+            Regex::new(r"\nVIA: line: 1 column: 18").unwrap(),
+        ],
+    );
+}
+
+#[test]
+fn test_import_runtime_error_compile_failure() {
+    assert_broken_import_failure(
+        "let lib = import \"runtime_error.ucg\";",
+        vec![
+            Regex::new(r"at file: .*runtime_error.ucg line: 2").unwrap(),
+            Regex::new(r"\nVIA: line: 1 column: 18").unwrap(),
+        ],
+    );
+}
+
+fn build_file_error(path: &[&str]) -> Box<dyn std::error::Error> {
+    let i_paths = Vec::new();
+    let out_buffer: Vec<u8> = Vec::new();
+    let err_buffer: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out_buffer, err_buffer));
+    let mut file = std::env::current_dir().unwrap();
+    for p in path {
+        file.push(p);
+    }
+    let mut b = FileBuilder::new(file.parent().unwrap(), &i_paths, &env);
+    b.build(file).unwrap_err()
+}
+
+fn build_file_failure(path: &[&str]) -> String {
+    format!("{}", build_file_error(path))
+}
+
+#[test]
+fn test_build_failure_renders_source_snippet() {
+    let err = build_file_failure(&["integration_tests", "broken_imports", "runtime_error.ucg"]);
+    assert!(
+        err.contains(
+            "error: TypeFail: Expected Int but got String(two)
+ --> "
+        ),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(
+            "runtime_error.ucg:2:18
+  |
+2 | let broken = 1 + \"two\";
+  |                  ^^^^^"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_build_failure_snippet_counts_multibyte_characters() {
+    let err = build_file_failure(&[
+        "integration_tests",
+        "broken_imports",
+        "utf8_runtime_error.ucg",
+    ]);
+    assert!(
+        err.contains(
+            "2 | let broken = [\"\u{e9}\", 1 + \"two\"];
+  |                        ^^^^^"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_build_failure_renders_causes_and_imports() {
+    let err = build_file_failure(&[
+        "integration_tests",
+        "broken_imports",
+        "imports_syntax_error.ucg",
+    ]);
+    assert!(
+        err.contains(
+            "caused by: ParseError: Not a Bareword
+ --> "
+        ),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(
+            "note: via
+ --> "
+        ),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(
+            "imports_syntax_error.ucg:1:18
+  |
+1 | let lib = import \"syntax_error.ucg\";
+  |                  ^^^^^^^^^^^^^^^^^^"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_import_cycle_compile_failure() {
+    let err = build_file_failure(&["example_errors", "import_cycle1.ucg"]);
+    assert!(err.contains("Import cycle detected"), "{}", err);
+}
+
+#[test]
+fn test_build_failure_json_diagnostic() {
+    let err = build_file_error(&["integration_tests", "broken_imports", "runtime_error.ucg"]);
+    let diagnostic = crate::error::diagnostic_json(err.as_ref());
+    assert_eq!(diagnostic["type"], "TypeFail");
+    assert_eq!(diagnostic["message"], "Expected Int but got String(two)");
+    assert!(diagnostic["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("runtime_error.ucg"));
+    assert_eq!(diagnostic["line"], 2);
+    assert_eq!(diagnostic["column"], 18);
+    assert_eq!(diagnostic["call_stack"], serde_json::json!([]));
+    assert_eq!(diagnostic["causes"], serde_json::json!([]));
+}
+
+#[test]
+fn test_build_failure_json_diagnostic_with_causes() {
+    let err = build_file_error(&[
+        "integration_tests",
+        "broken_imports",
+        "imports_syntax_error.ucg",
+    ]);
+    let diagnostic = crate::error::diagnostic_json(err.as_ref());
+    assert_eq!(diagnostic["type"], "ParseError");
+    assert_eq!(diagnostic["message"], "Expected Expression to bind");
+    assert_eq!(diagnostic["line"], 2);
+    let call_stack = diagnostic["call_stack"].as_array().unwrap();
+    assert_eq!(call_stack.len(), 1);
+    assert!(call_stack[0]["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("imports_syntax_error.ucg"));
+    assert_eq!(call_stack[0]["line"], 1);
+    assert_eq!(call_stack[0]["column"], 18);
+    let causes = diagnostic["causes"].as_array().unwrap();
+    assert_eq!(causes.len(), 1);
+    assert_eq!(causes[0]["type"], "ParseError");
+    assert_eq!(causes[0]["message"], "Not a Bareword");
+    assert!(causes[0]["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("syntax_error.ucg"));
+}
+
+#[test]
+fn test_rebuild_after_dependency_change() {
+    let dir = std::env::temp_dir().join(format!("ucg_rebuild_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let main = dir.join("main.ucg");
+    let lib = dir.join("lib.ucg");
+    let data = dir.join("data.json");
+    std::fs::write(
+        &main,
+        "let lib = import \"lib.ucg\";\nlet data = include json \"data.json\";\nlet v = lib.v;\n",
+    )
+    .unwrap();
+    std::fs::write(&lib, "let v = 1;\n").unwrap();
+    std::fs::write(&data, "{\"d\": 1}").unwrap();
+    let i_paths = Vec::new();
+    let out_buffer: Vec<u8> = Vec::new();
+    let err_buffer: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out_buffer, err_buffer));
+    let build_v = || {
+        let mut b = FileBuilder::new(&dir, &i_paths, &env);
+        b.build(&main).unwrap();
+        b.get_out_by_name("v").unwrap()
+    };
+    assert_eq!(build_v(), Rc::new(Val::Int(1)));
+
+    let deps = env.borrow().get_dependencies(&main);
+    assert_eq!(deps, vec![data.clone(), lib.clone()].into_iter().collect());
+
+    // Without invalidating we get the cached import.
+    std::fs::write(&lib, "let v = 2;\n").unwrap();
+    assert_eq!(build_v(), Rc::new(Val::Int(1)));
+
+    let changed = vec![lib.clone()].into_iter().collect();
+    let stale = env.borrow_mut().invalidate_paths(&changed);
+    assert_eq!(stale, vec![lib.clone(), main.clone()].into_iter().collect());
+    assert_eq!(build_v(), Rc::new(Val::Int(2)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_shared_out_lock_allows_one_output_per_file() {
+    let dir = std::env::temp_dir().join(format!("ucg_shared_out_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.ucg");
+    let out = dir.join("main.json");
+    std::fs::write(&main, "out json {v = 1};\n").unwrap();
+    let _ = std::fs::remove_file(&out);
+    let i_paths = Vec::new();
+    let lock = std::sync::Arc::new(std::sync::Mutex::new(std::collections::BTreeSet::new()));
+    let build = || {
+        let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+        env.borrow_mut().set_shared_out_lock(lock.clone());
+        let mut b = FileBuilder::new(&dir, &i_paths, &env);
+        b.build(&main).map(|_| ())
+    };
+    build().unwrap();
+    assert!(out.exists());
+    std::fs::remove_file(&out).unwrap();
+    // Another environment sharing the lock fails the same way a second
+    // output in one environment does.
+    let err = build().unwrap_err();
+    assert!(
+        format!("{}", err).contains("You can only have one output per file"),
+        "{}",
+        err
+    );
+    assert!(!out.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_warns_about_unused_bindings() {
+    let dir = std::env::temp_dir().join(format!("ucg_warnings_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.ucg");
+    std::fs::write(
+        &main,
+        "let lib = import \"std/lists.ucg\";\nlet m = module{} => (out) {\n    let unused = 1;\n    let out = 2;\n};\n",
+    )
+    .unwrap();
+    let i_paths = Vec::new();
+    let build_warnings = |warnings: bool| {
+        let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+        env.borrow_mut().warnings = warnings;
+        let mut b = FileBuilder::new(&dir, &i_paths, &env);
+        b.build(&main).unwrap();
+        let stderr = env.borrow().stderr.clone();
+        String::from_utf8(stderr).unwrap()
+    };
+    let stderr = build_warnings(true);
+    assert!(
+        stderr.contains("warning: unused-import: Import bound to lib is never used"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("warning: unused-let: Binding unused is never used"),
+        "{}",
+        stderr
+    );
+    // The module's out binding is used by its out expression.
+    assert!(!stderr.contains("Binding out"), "{}", stderr);
+    assert_eq!(build_warnings(false), "");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_warns_about_unused_bindings_in_imported_files() {
+    let dir = std::env::temp_dir().join(format!("ucg_import_warnings_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.ucg");
+    let lib = dir.join("lib.ucg");
+    std::fs::write(&main, "let lib = import \"lib.ucg\";\nlib.val;\n").unwrap();
+    std::fs::write(
+        &lib,
+        "let lists = import \"std/lists.ucg\";\nlet val = 1;\n",
+    )
+    .unwrap();
+    let i_paths = Vec::new();
+    let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+    let warning_count = || {
+        let stderr = String::from_utf8(env.borrow().stderr.clone()).unwrap();
+        assert!(stderr.contains("lib.ucg:1:"), "{}", stderr);
+        stderr
+            .matches("warning: unused-import: Import bound to lists is never used")
+            .count()
+    };
+    FileBuilder::new(&dir, &i_paths, &env).build(&main).unwrap();
+    assert_eq!(warning_count(), 1);
+    // Building a file that was already imported doesn't warn again.
+    FileBuilder::new(&dir, &i_paths, &env).build(&lib).unwrap();
+    assert_eq!(warning_count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_warns_about_unused_bindings_in_cached_imported_files() {
+    let dir = std::env::temp_dir().join(format!("ucg_cached_warnings_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.ucg");
+    std::fs::write(&main, "let lib = import \"lib.ucg\";\nlib.val;\n").unwrap();
+    std::fs::write(
+        dir.join("lib.ucg"),
+        "let lists = import \"std/lists.ucg\";\nlet val = 1;\n",
+    )
+    .unwrap();
+    let i_paths = Vec::new();
+    // The second build gets the ops for lib.ucg from the disk cache.
+    for _ in 0..2 {
+        let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+        env.borrow_mut().set_op_cache_dir(dir.join("cache"));
+        FileBuilder::new(&dir, &i_paths, &env).build(&main).unwrap();
+        let stderr = String::from_utf8(env.borrow().stderr.clone()).unwrap();
+        assert!(
+            stderr.contains("warning: unused-import: Import bound to lists is never used"),
+            "{}",
+            stderr
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_writes_json_warnings() {
+    let dir = std::env::temp_dir().join(format!("ucg_json_warnings_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.ucg");
+    std::fs::write(&main, "let lists = import \"std/lists.ucg\";\n").unwrap();
+    let i_paths = Vec::new();
+    let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+    env.borrow_mut().message_format = crate::error::MessageFormat::Json;
+    FileBuilder::new(&dir, &i_paths, &env).build(&main).unwrap();
+    let stderr = String::from_utf8(env.borrow().stderr.clone()).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", stderr);
+    let warning: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(warning["type"], "unused-import");
+    assert_eq!(warning["message"], "Import bound to lists is never used");
+    assert_eq!(warning["line"], 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_module_import_resolves_from_module_directory() {
+    // shadowed.ucg isn't next to the module so it comes from the import path
+    // rather than the importing file's directory.
+    assert_import_path_build(
+        "let source = (import \"module_lib.ucg\").shadowed{};",
+        "source",
+        Val::Str("first".to_owned()),
+    );
+}
+
+#[test]
+fn test_module_imports_are_resolved_everywhere_in_the_module() {
+    assert_import_path_build(
+        "let m = import \"../modules/walked.ucg\";
+        let source = m.walked{};",
+        "source",
+        Val::List(vec![Rc::new(Val::Str("modules".to_owned())); 5]),
+    );
+    assert_import_path_build(
+        "let source = (import \"../modules/walked.ucg\").returned{};",
+        "source",
+        Val::Str("modules".to_owned()),
+    );
+    let i_paths = vec![import_path_fixture("first"), import_path_fixture("second")];
+    let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+    let mut b = FileBuilder::new(import_path_fixture("project"), &i_paths, &env);
+    let err = b
+        .eval_string("let source = (import \"../modules/walked.ucg\").failed{};")
+        .unwrap_err();
+    assert!(format!("{}", err).contains("modules"), "{}", err);
+}
@@ -25,9 +25,9 @@ use std::rc::Rc;
 
 use atty;
 use atty::Stream;
+use regex::Regex;
 use rustyline;
 use rustyline::error::ReadlineError;
-use simple_error;
 
 use crate::ast::*;
 use crate::build::opcode::pointer::OpPointer;
@@ -42,6 +42,7 @@ use crate::parse::parse;
 pub mod format;
 pub mod ir;
 pub mod opcode;
+pub mod report;
 pub mod scope;
 
 pub mod stdlib;
@@ -51,12 +52,22 @@ pub use self::ir::Val;
 /// The result of a build.
 type BuildResult = Result<(), Box<dyn Error>>;
 
+/// The result of a single assertion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertResult {
+    pub desc: String,
+    pub ok: bool,
+    pub pos: Position,
+}
+
 /// AssertCollector collects the results of assertions in the UCG AST.
 pub struct AssertCollector {
     pub counter: i32,
     pub success: bool,
     pub summary: String,
     pub failures: String,
+    pub results: Vec<AssertResult>,
+    filter: Option<Regex>,
 }
 
 impl AssertCollector {
@@ -66,10 +77,29 @@ impl AssertCollector {
             success: true,
             summary: String::new(),
             failures: String::new(),
+            results: Vec::new(),
+            filter: None,
         }
     }
 
-    fn record_assert_result(&mut self, msg: &str, is_success: bool) {
+    /// Only record the assertions whose description matches the filter.
+    pub fn set_filter(&mut self, filter: Regex) {
+        self.filter = Some(filter);
+    }
+
+    /// Forgets the results recorded so far but keeps the filter.
+    pub fn reset(&mut self) {
+        let filter = self.filter.take();
+        *self = Self::new();
+        self.filter = filter;
+    }
+
+    fn record_assert_result(&mut self, msg: &str, is_success: bool, pos: &Position) {
+        if let Some(ref filter) = self.filter {
+            if !filter.is_match(msg) {
+                return;
+            }
+        }
         if !is_success {
             let msg = format!("{} - NOT OK: {}\n", self.counter, msg);
             self.summary.push_str(&msg);
@@ -79,6 +109,11 @@ impl AssertCollector {
             let msg = format!("{} - OK: {}\n", self.counter, msg);
             self.summary.push_str(&msg);
         }
+        self.results.push(AssertResult {
+            desc: msg.trim_end().to_string(),
+            ok: is_success,
+            pos: pos.clone(),
+        });
         self.counter += 1;
     }
 }
@@ -113,6 +148,7 @@ where
         import_paths: &'a Vec<PathBuf>,
         environment: &'a RefCell<Environment<Stdout, Stderr>>,
     ) -> Self {
+        environment.borrow_mut().set_import_path(import_paths);
         FileBuilder {
             environment: environment,
             strict: false,
@@ -158,14 +194,7 @@ where
                 self.last = Some(v);
                 Ok(())
             }
-            Err(e) => {
-                let err = simple_error::SimpleError::new(&format!(
-                    "Error building file: {}\n{}",
-                    file.to_string_lossy(),
-                    e.as_ref()
-                ));
-                Err(Box::new(err))
-            }
+            Err(e) => Err(Box::new(error::FileBuildError::new(file, e))),
         }
     }
 
@@ -193,7 +222,7 @@ where
     /// Builds a list of parsed UCG Statements.
     pub fn eval_stmts(&mut self, ast: Vec<Statement>, path: Option<PathBuf>) -> BuildResult {
         // We should probably stash this in an op_cache somewhere?
-        let ops = translate::AST::translate(ast, &self.working_dir);
+        let ops = translate::AST::translate(ast, &self.working_dir, self.import_path);
         let mut vm = VM::new(
             self.strict,
             Rc::new(ops),
@@ -278,7 +307,7 @@ where
                     // if it is then
                     // eval statement
                     let stmts = parse(OffsetStrIter::new(&stmt), None)?;
-                    let ops = translate::AST::translate(stmts, &self.working_dir);
+                    let ops = translate::AST::translate(stmts, &self.working_dir, self.import_path);
                     vm = vm.to_new_pointer(OpPointer::new(Rc::new(ops)));
                     match vm.run(self.environment) {
                         // print the result
@@ -311,7 +340,10 @@ where
         path: Option<PathBuf>,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         match parse(input.clone(), None) {
-            Ok(stmts) => {
+            Ok(mut stmts) => {
+                if let Some(ref path) = path {
+                    self.environment.borrow_mut().warn_unused(path, &mut stmts);
+                }
                 self.eval_stmts(stmts, path)?;
                 if let Some(v) = self.out.clone() {
                     return Ok(v);
@@ -336,6 +368,7 @@ where
             Statement::Expression(expr),
             &mut ops_map,
             &self.working_dir,
+            self.import_path,
         );
         let mut vm = VM::new(
             self.strict,
@@ -365,6 +398,58 @@ where
         return None;
     }
 
+    /// Looks up a value in the build output using a dotted selector.
+    ///
+    /// The selector uses the same syntax as ucg's dot selectors so
+    /// `services.web.ports.0` selects the first port of the web service.
+    /// Field names containing dots can be quoted like `flags."log.debug"`.
+    /// An empty selector returns the entire build output.
+    pub fn get_out_by_selector(&self, selector: &str) -> Result<Rc<Val>, Box<dyn Error>> {
+        let mut val = match self.out.clone() {
+            Some(val) => val,
+            None => {
+                return Err(error::BuildError::new(
+                    "Build results in no artifacts.",
+                    error::ErrorType::NoSuchSymbol,
+                )
+                .to_boxed())
+            }
+        };
+        for segment in split_selector(selector)? {
+            let next = match val.as_ref() {
+                Val::Tuple(flds) => flds
+                    .iter()
+                    .find(|(k, _)| k == &segment)
+                    .map(|(_, v)| v.clone()),
+                Val::Env(flds) => flds
+                    .iter()
+                    .find(|(k, _)| k == &segment)
+                    .map(|(_, v)| Rc::new(Val::Str(v.clone()))),
+                Val::List(elems) => match segment.parse::<usize>() {
+                    Ok(idx) => elems.get(idx).cloned(),
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+            val = match next {
+                Some(v) => v,
+                None => {
+                    return Err(error::BuildError::new(
+                        format!(
+                            "No such field or index {} for {} in selector {}",
+                            segment,
+                            val.type_name(),
+                            selector
+                        ),
+                        error::ErrorType::NoSuchSymbol,
+                    )
+                    .to_boxed())
+                }
+            };
+        }
+        Ok(val)
+    }
+
     pub fn assert_results(&self) -> bool {
         self.environment.borrow().assert_results.success
     }
@@ -374,6 +459,49 @@ where
     }
 }
 
+/// Splits a dotted selector into its segments handling quoted field names.
+fn split_selector(selector: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut segments = Vec::new();
+    if selector.trim().is_empty() {
+        return Ok(segments);
+    }
+    let mut curr = String::new();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    let mut chars = selector.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    curr.push(escaped);
+                }
+            }
+            '"' => {
+                quoted = !quoted;
+                was_quoted = true;
+            }
+            '.' if !quoted => {
+                if curr.is_empty() && !was_quoted {
+                    break;
+                }
+                segments.push(curr);
+                curr = String::new();
+                was_quoted = false;
+            }
+            c => curr.push(c),
+        }
+    }
+    if quoted || (curr.is_empty() && !was_quoted) {
+        return Err(error::BuildError::new(
+            format!("Invalid selector {}", selector),
+            error::ErrorType::ParseError,
+        )
+        .to_boxed());
+    }
+    segments.push(curr);
+    Ok(segments)
+}
+
 #[cfg(test)]
 mod compile_test;
 
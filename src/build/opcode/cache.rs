@@ -11,51 +11,779 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::cell::Cell;
 use std::collections::btree_map;
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use super::translate::PositionMap;
 use super::Error;
 use super::OpPointer;
+use super::{Hook, Op, Primitive};
+use crate::ast::walk::{AstWalker, Walker};
+use crate::ast::{CastType, Expression, Position, Statement};
+use crate::lint::{Lint, Rule};
 
 /// A Cache of Op codes.
+///
+/// Op codes are always cached in memory. If a cache directory has been set
+/// they are also persisted there keyed by the path, the contents of the file,
+/// and the version of the compiler so that later runs can skip parsing and
+/// translating files that haven't changed. The lints about unused bindings
+/// are cached along with the ops so warning about them never needs the file
+/// to be parsed again.
+///
+/// Entries are kept in a directory per compiler and cache format version.
+/// The directories of other versions are removed the first time a version
+/// writes to the cache. Entries for stale file contents are not pruned so
+/// the cache directory can be deleted at any time to reclaim the space.
 pub struct Ops {
-    ops: BTreeMap<PathBuf, Rc<PositionMap>>,
+    ops: BTreeMap<PathBuf, Cached>,
+    dir: Option<PathBuf>,
+}
+
+struct Cached {
+    ops: Rc<PositionMap>,
+    lints: Vec<Lint>,
 }
 
 impl Ops {
     pub fn new() -> Self {
         Self {
             ops: BTreeMap::new(),
+            dir: None,
         }
     }
 
+    pub fn set_cache_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.dir = Some(dir.into());
+    }
+
     pub fn entry<'a, P: Into<PathBuf>>(&'a mut self, path: P) -> Entry<'a> {
-        Entry(self.ops.entry(path.into()))
+        Entry(self.ops.entry(path.into()), self.dir.as_ref())
+    }
+
+    /// Returns the lints cached with the ops for a path.
+    pub fn lints<P: AsRef<Path>>(&self, path: P) -> &[Lint] {
+        match self.ops.get(path.as_ref()) {
+            Some(cached) => &cached.lints,
+            None => &[],
+        }
+    }
+
+    /// Forgets the in memory ops for a path so they get recompiled the next
+    /// time they are used.
+    pub fn invalidate<P: AsRef<Path>>(&mut self, path: P) {
+        self.ops.remove(path.as_ref());
     }
 }
 
-pub struct Entry<'a>(btree_map::Entry<'a, PathBuf, Rc<PositionMap>>);
+pub struct Entry<'a>(btree_map::Entry<'a, PathBuf, Cached>, Option<&'a PathBuf>);
 
 impl<'a> Entry<'a> {
-    pub fn get_pointer_or_else<F: FnOnce() -> Result<PositionMap, Error>, P: Into<PathBuf>>(
-        self,
-        f: F,
-        path: P,
-    ) -> Result<OpPointer, Error> {
+    /// Returns an OpPointer for the cached ops or compiles them.
+    ///
+    /// `src` is only called if the ops are not already cached in memory. `f`
+    /// is only called if they are not in the on disk cache either. It returns
+    /// the ops, the lints to cache with them, and whether they only depend on
+    /// the contents of the file and so may be persisted.
+    pub fn get_pointer_or_else<S, F, P>(self, src: S, f: F, path: P) -> Result<OpPointer, Error>
+    where
+        S: FnOnce() -> Result<String, Error>,
+        F: FnOnce(&str) -> Result<(PositionMap, Vec<Lint>, bool), Error>,
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
         let cached = match self.0 {
-            btree_map::Entry::Occupied(e) => e.get().clone(),
+            btree_map::Entry::Occupied(e) => e.get().ops.clone(),
             btree_map::Entry::Vacant(e) => {
-                // TODO(jwall) Check a file cache for the opcodes before
-                let v = Rc::new(f()?);
-                e.insert(v.clone());
-                v
+                let contents = src()?;
+                let cache_file = self.1.map(|dir| cache_file_path(dir, &path, &contents));
+                let cached = cache_file
+                    .as_ref()
+                    .and_then(|cache_file| read_cache_file(cache_file, &path).ok());
+                let (ops, lints) = match cached {
+                    Some(cached) => cached,
+                    None => {
+                        let (ops, lints, persist) = f(&contents)?;
+                        if let (Some(ref cache_file), true) = (&cache_file, persist) {
+                            // The disk cache is an optimization so failing to
+                            // write it is not an error.
+                            let _ = write_cache_file(cache_file, &path, &ops, &lints);
+                        }
+                        (ops, lints)
+                    }
+                };
+                let ops = Rc::new(ops);
+                e.insert(Cached {
+                    ops: ops.clone(),
+                    lints,
+                });
+                ops
             }
         };
         let mut ptr = OpPointer::new(cached);
-        ptr.set_path(path.into());
+        ptr.set_path(path);
         Ok(ptr)
     }
 }
+
+/// Returns whether the ops translated from these statements depend only on
+/// the contents of their file. The import and include paths in modules are
+/// resolved against the filesystem and import path during translation so
+/// ops for files that have them mustn't be persisted.
+pub fn depends_only_on_contents(stmts: &mut [Statement]) -> bool {
+    let resolves_paths = Cell::new(false);
+    let find_paths = |e: &mut Expression| {
+        if let Expression::Import(_) | Expression::Include(_) = e {
+            resolves_paths.set(true);
+        }
+    };
+    let find_modules = |e: &mut Expression| {
+        if let Expression::Module(ref mut def) = e {
+            let mut walker = AstWalker::new().with_expr_handler(&find_paths);
+            for stmt in def.statements.iter_mut() {
+                walker.walk_statement(stmt);
+            }
+            if let Some(ref mut expr) = def.out_expr {
+                walker.walk_expression(expr);
+            }
+        }
+    };
+    let mut walker = AstWalker::new().with_expr_handler(&find_modules);
+    for stmt in stmts.iter_mut() {
+        walker.walk_statement(stmt);
+    }
+    !resolves_paths.get()
+}
+
+const MAGIC: &[u8] = b"UCGOPS\0";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Bump this whenever the encoding of ops, positions, or lints changes.
+const FORMAT_VERSION: u64 = 2;
+
+/// A 64 bit FNV-1a hash. We need a hash that is stable across builds of the
+/// compiler which the std library hashers don't promise.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    let mut hash = hash;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn cache_file_path(dir: &Path, path: &Path, contents: &str) -> PathBuf {
+    let mut hash = 0xcbf29ce484222325;
+    hash = fnv1a(hash, path.to_string_lossy().as_bytes());
+    hash = fnv1a(hash, &[0]);
+    hash = fnv1a(hash, contents.as_bytes());
+    let mut file = dir.join(version_dir_name());
+    file.push(format!("{:016x}.ops", hash));
+    file
+}
+
+fn version_dir_name() -> String {
+    format!("{}-{}", VERSION, FORMAT_VERSION)
+}
+
+/// Creates the directory for this version's cache files and removes the
+/// directories of every other version since they will never be read again.
+fn create_version_dir(version_dir: &Path) -> io::Result<()> {
+    if version_dir.is_dir() {
+        return Ok(());
+    }
+    if let Some(dir) = version_dir.parent() {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                if entry.path() != version_dir && entry.path().is_dir() {
+                    let _ = fs::remove_dir_all(entry.path());
+                }
+            }
+        }
+    }
+    fs::create_dir_all(version_dir)
+}
+
+fn read_cache_file(cache_file: &Path, path: &Path) -> io::Result<(PositionMap, Vec<Lint>)> {
+    let mut bytes = Vec::new();
+    File::open(cache_file)?.read_to_end(&mut bytes)?;
+    let mut dec = Decoder {
+        bytes: &bytes,
+        path,
+    };
+    if dec.take(MAGIC.len())? != MAGIC || dec.string()? != VERSION || dec.u64()? != FORMAT_VERSION {
+        return Err(invalid_data());
+    }
+    let mut ops = Vec::new();
+    for _ in 0..dec.u64()? {
+        ops.push(dec.op()?);
+    }
+    let mut pos = Vec::new();
+    for _ in 0..dec.u64()? {
+        pos.push(dec.position()?);
+    }
+    let mut lints = Vec::new();
+    for _ in 0..dec.u64()? {
+        lints.push(dec.lint()?);
+    }
+    if !dec.bytes.is_empty() || ops.len() != pos.len() {
+        return Err(invalid_data());
+    }
+    Ok((PositionMap { ops, pos }, lints))
+}
+
+fn write_cache_file(
+    cache_file: &Path,
+    path: &Path,
+    ops: &PositionMap,
+    lints: &[Lint],
+) -> io::Result<()> {
+    let mut enc = Encoder {
+        bytes: Vec::new(),
+        path,
+    };
+    enc.bytes.extend_from_slice(MAGIC);
+    enc.string(VERSION);
+    enc.u64(FORMAT_VERSION);
+    enc.u64(ops.ops.len() as u64);
+    for op in ops.ops.iter() {
+        enc.op(op);
+    }
+    enc.u64(ops.pos.len() as u64);
+    for pos in ops.pos.iter() {
+        enc.position(pos);
+    }
+    enc.u64(lints.len() as u64);
+    for lint in lints.iter() {
+        enc.lint(lint);
+    }
+    if let Some(dir) = cache_file.parent() {
+        create_version_dir(dir)?;
+    }
+    // Write to a temporary file first so concurrent builds never see a
+    // partially written cache file.
+    let tmp = cache_file.with_extension(format!(
+        "tmp{}-{}",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&tmp)
+        .and_then(|mut f| f.write_all(&enc.bytes))
+        .and_then(|_| fs::rename(&tmp, cache_file));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// Distinguishes the temporary files of builds on different threads.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid op cache file")
+}
+
+struct Encoder<'a> {
+    bytes: Vec<u8>,
+    path: &'a Path,
+}
+
+impl<'a> Encoder<'a> {
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, v: &str) {
+        self.u64(v.len() as u64);
+        self.bytes.extend_from_slice(v.as_bytes());
+    }
+
+    fn position(&mut self, pos: &Position) {
+        // Nearly every position is in the file we are caching so we avoid
+        // repeating its path for each of them.
+        match pos.file {
+            None => self.u8(0),
+            Some(ref f) if f == self.path => self.u8(1),
+            Some(ref f) => {
+                self.u8(2);
+                self.string(&f.to_string_lossy());
+            }
+        }
+        self.u64(pos.line as u64);
+        self.u64(pos.column as u64);
+        self.u64(pos.offset as u64);
+    }
+
+    fn lint(&mut self, lint: &Lint) {
+        self.string(lint.rule.name());
+        self.string(&lint.msg);
+        self.position(&lint.pos);
+    }
+
+    fn primitive(&mut self, p: &Primitive) {
+        match p {
+            Primitive::Int(i) => {
+                self.u8(0);
+                self.u64(*i as u64);
+            }
+            Primitive::Float(f) => {
+                self.u8(1);
+                self.u64(f.to_bits());
+            }
+            Primitive::Str(s) => {
+                self.u8(2);
+                self.string(s);
+            }
+            Primitive::Bool(b) => {
+                self.u8(3);
+                self.u8(*b as u8);
+            }
+            Primitive::Empty => self.u8(4),
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        match op {
+            Op::Bind => self.u8(0),
+            Op::BindOver => self.u8(1),
+            Op::Pop => self.u8(2),
+            Op::NewScope(i) => {
+                self.u8(3);
+                self.i32(*i);
+            }
+            Op::Add => self.u8(4),
+            Op::Sub => self.u8(5),
+            Op::Div => self.u8(6),
+            Op::Mul => self.u8(7),
+            Op::Mod => self.u8(8),
+            Op::Equal => self.u8(9),
+            Op::Gt => self.u8(10),
+            Op::Lt => self.u8(11),
+            Op::GtEq => self.u8(12),
+            Op::LtEq => self.u8(13),
+            Op::Not => self.u8(14),
+            Op::Val(p) => {
+                self.u8(15);
+                self.primitive(p);
+            }
+            Op::Cast(t) => {
+                self.u8(16);
+                self.u8(match t {
+                    CastType::Int => 0,
+                    CastType::Float => 1,
+                    CastType::Str => 2,
+                    CastType::Bool => 3,
+                });
+            }
+            Op::Sym(s) => {
+                self.u8(17);
+                self.string(s);
+            }
+            Op::DeRef(s) => {
+                self.u8(18);
+                self.string(s);
+            }
+            Op::InitTuple => self.u8(19),
+            Op::Field => self.u8(20),
+            Op::InitList => self.u8(21),
+            Op::Element => self.u8(22),
+            Op::Cp => self.u8(23),
+            Op::Bang => self.u8(24),
+            Op::Jump(i) => {
+                self.u8(25);
+                self.i32(*i);
+            }
+            Op::JumpIfTrue(i) => {
+                self.u8(26);
+                self.i32(*i);
+            }
+            Op::JumpIfFalse(i) => {
+                self.u8(27);
+                self.i32(*i);
+            }
+            Op::SelectJump(i) => {
+                self.u8(28);
+                self.i32(*i);
+            }
+            Op::And(i) => {
+                self.u8(29);
+                self.i32(*i);
+            }
+            Op::Or(i) => {
+                self.u8(30);
+                self.i32(*i);
+            }
+            Op::Index => self.u8(31),
+            Op::SafeIndex => self.u8(32),
+            Op::Exist => self.u8(33),
+            Op::Noop => self.u8(34),
+            Op::InitThunk(i) => {
+                self.u8(35);
+                self.i32(*i);
+            }
+            Op::Module(i) => {
+                self.u8(36);
+                self.i32(*i);
+            }
+            Op::Func(i) => {
+                self.u8(37);
+                self.i32(*i);
+            }
+            Op::Return => self.u8(38),
+            Op::FCall => self.u8(39),
+            Op::Typ => self.u8(40),
+            Op::Runtime(h) => {
+                self.u8(41);
+                match h {
+                    Hook::Map => self.u8(0),
+                    Hook::Include => self.u8(1),
+                    Hook::Filter => self.u8(2),
+                    Hook::Reduce => self.u8(3),
+                    Hook::Import => self.u8(4),
+                    Hook::Out => self.u8(5),
+                    Hook::Assert => self.u8(6),
+                    Hook::Convert => self.u8(7),
+                    Hook::Regex => self.u8(8),
+                    Hook::Range => self.u8(9),
+                    Hook::Trace(pos) => {
+                        self.u8(10);
+                        self.position(pos);
+                    }
+                }
+            }
+            Op::Render => self.u8(42),
+            Op::PushSelf => self.u8(43),
+            Op::PopSelf => self.u8(44),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    path: &'a Path,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(invalid_data());
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u64()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid_data())
+    }
+
+    fn position(&mut self) -> io::Result<Position> {
+        let file = match self.u8()? {
+            0 => None,
+            1 => Some(self.path.to_path_buf()),
+            2 => Some(PathBuf::from(self.string()?)),
+            _ => return Err(invalid_data()),
+        };
+        Ok(Position {
+            file,
+            line: self.u64()? as usize,
+            column: self.u64()? as usize,
+            offset: self.u64()? as usize,
+        })
+    }
+
+    fn lint(&mut self) -> io::Result<Lint> {
+        Ok(Lint {
+            rule: Rule::from_name(&self.string()?).ok_or_else(invalid_data)?,
+            msg: self.string()?,
+            pos: self.position()?,
+        })
+    }
+
+    fn primitive(&mut self) -> io::Result<Primitive> {
+        Ok(match self.u8()? {
+            0 => Primitive::Int(self.u64()? as i64),
+            1 => Primitive::Float(f64::from_bits(self.u64()?)),
+            2 => Primitive::Str(self.string()?),
+            3 => Primitive::Bool(self.u8()? != 0),
+            4 => Primitive::Empty,
+            _ => return Err(invalid_data()),
+        })
+    }
+
+    fn op(&mut self) -> io::Result<Op> {
+        Ok(match self.u8()? {
+            0 => Op::Bind,
+            1 => Op::BindOver,
+            2 => Op::Pop,
+            3 => Op::NewScope(self.i32()?),
+            4 => Op::Add,
+            5 => Op::Sub,
+            6 => Op::Div,
+            7 => Op::Mul,
+            8 => Op::Mod,
+            9 => Op::Equal,
+            10 => Op::Gt,
+            11 => Op::Lt,
+            12 => Op::GtEq,
+            13 => Op::LtEq,
+            14 => Op::Not,
+            15 => Op::Val(self.primitive()?),
+            16 => Op::Cast(match self.u8()? {
+                0 => CastType::Int,
+                1 => CastType::Float,
+                2 => CastType::Str,
+                3 => CastType::Bool,
+                _ => return Err(invalid_data()),
+            }),
+            17 => Op::Sym(self.string()?),
+            18 => Op::DeRef(self.string()?),
+            19 => Op::InitTuple,
+            20 => Op::Field,
+            21 => Op::InitList,
+            22 => Op::Element,
+            23 => Op::Cp,
+            24 => Op::Bang,
+            25 => Op::Jump(self.i32()?),
+            26 => Op::JumpIfTrue(self.i32()?),
+            27 => Op::JumpIfFalse(self.i32()?),
+            28 => Op::SelectJump(self.i32()?),
+            29 => Op::And(self.i32()?),
+            30 => Op::Or(self.i32()?),
+            31 => Op::Index,
+            32 => Op::SafeIndex,
+            33 => Op::Exist,
+            34 => Op::Noop,
+            35 => Op::InitThunk(self.i32()?),
+            36 => Op::Module(self.i32()?),
+            37 => Op::Func(self.i32()?),
+            38 => Op::Return,
+            39 => Op::FCall,
+            40 => Op::Typ,
+            41 => Op::Runtime(match self.u8()? {
+                0 => Hook::Map,
+                1 => Hook::Include,
+                2 => Hook::Filter,
+                3 => Hook::Reduce,
+                4 => Hook::Import,
+                5 => Hook::Out,
+                6 => Hook::Assert,
+                7 => Hook::Convert,
+                8 => Hook::Regex,
+                9 => Hook::Range,
+                10 => Hook::Trace(self.position()?),
+                _ => return Err(invalid_data()),
+            }),
+            42 => Op::Render,
+            43 => Op::PushSelf,
+            44 => Op::PopSelf,
+            _ => return Err(invalid_data()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod cache_test {
+    use super::*;
+    use crate::iter::OffsetStrIter;
+    use crate::lint::Linter;
+    use crate::parse::parse;
+
+    fn temp_cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ucg_cache_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn compile(path: &Path, contents: &str) -> Result<PositionMap, Error> {
+        let iter = OffsetStrIter::new(contents).with_src_file(path);
+        let stmts = parse(iter, None).unwrap();
+        Ok(super::super::translate::AST::translate(
+            stmts,
+            &path.parent().unwrap(),
+            &[],
+        ))
+    }
+
+    fn lint(path: &Path, contents: &str) -> Vec<Lint> {
+        let iter = OffsetStrIter::new(contents).with_src_file(path);
+        let mut stmts = parse(iter, None).unwrap();
+        Linter::new(vec![Rule::UnusedLet, Rule::UnusedImport]).lint(path, &mut stmts)
+    }
+
+    const SRC: &str = "let l = import \"std/lists.ucg\";
+let t = {a = 1, b = 2.5, c = \"three\", d = true, e = NULL};
+let f = func(x) => x + 1;
+let m = module{arg = 1} => (result) { let result = mod.arg * 2; };
+let s = {a = 1, b = self.a};
+let v = TRACE [int(\"1\"), 3 %% 2];
+let r = map(f, filter(func(x) => x > 1, [1, 2]));
+let r2 = reduce(func(acc, x) => acc + x, 0, 1:2:5);
+let sel = select (t.d) => { true = \"a\" ~ \"a.*\", false = \"a\" in t };
+let typ = t.c is \"str\";
+assert {ok = t.a == 1 && t.b > 2.0 || t.d, desc = \"ok\"};
+out json t;
+";
+
+    #[test]
+    fn test_cache_file_round_trip() {
+        let dir = temp_cache_dir("round_trip");
+        let path = dir.join("src").join("file.ucg");
+        let ops = compile(&path, SRC).unwrap();
+        let lints = lint(&path, SRC);
+        assert!(!lints.is_empty());
+        let cache_file = cache_file_path(&dir, &path, SRC);
+        write_cache_file(&cache_file, &path, &ops, &lints).unwrap();
+        assert_eq!(read_cache_file(&cache_file, &path).unwrap(), (ops, lints));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_file_keyed_by_path_and_contents() {
+        let dir = temp_cache_dir("keyed");
+        let path = dir.join("file.ucg");
+        let key = cache_file_path(&dir, &path, SRC);
+        assert_eq!(key, cache_file_path(&dir, &path, SRC));
+        assert_ne!(key, cache_file_path(&dir, &path, "let x = 1;"));
+        assert_ne!(key, cache_file_path(&dir, &dir.join("other.ucg"), SRC));
+        assert!(key.starts_with(dir.join(version_dir_name())));
+    }
+
+    #[test]
+    fn test_ops_reuses_disk_cache() {
+        let dir = temp_cache_dir("reuse");
+        let path = dir.join("file.ucg");
+        let mut first = Ops::new();
+        first.set_cache_dir(&dir);
+        let ptr = first
+            .entry(path.clone())
+            .get_pointer_or_else(
+                || Ok(SRC.to_owned()),
+                |c| Ok((compile(&path, c)?, lint(&path, c), true)),
+                path.clone(),
+            )
+            .unwrap();
+        // A fresh cache with the same directory should not need to compile again.
+        let mut second = Ops::new();
+        second.set_cache_dir(&dir);
+        let cached = second
+            .entry(path.clone())
+            .get_pointer_or_else(
+                || Ok(SRC.to_owned()),
+                |_| panic!("Expected the ops to come from the disk cache"),
+                path.clone(),
+            )
+            .unwrap();
+        assert_eq!(ptr.pos_map, cached.pos_map);
+        assert_eq!(first.lints(&path), second.lints(&path));
+        assert!(!second.lints(&path).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_cache_file_is_ignored() {
+        let dir = temp_cache_dir("corrupt");
+        let path = dir.join("file.ucg");
+        let cache_file = cache_file_path(&dir, &path, SRC);
+        fs::create_dir_all(cache_file.parent().unwrap()).unwrap();
+        File::create(&cache_file)
+            .unwrap()
+            .write_all(b"UCGOPS\0garbage")
+            .unwrap();
+        assert!(read_cache_file(&cache_file, &path).is_err());
+        let mut ops = Ops::new();
+        ops.set_cache_dir(&dir);
+        let ptr = ops
+            .entry(path.clone())
+            .get_pointer_or_else(
+                || Ok(SRC.to_owned()),
+                |c| Ok((compile(&path, c)?, lint(&path, c), true)),
+                path.clone(),
+            )
+            .unwrap();
+        assert_eq!(*ptr.pos_map, compile(&path, SRC).unwrap());
+        assert_eq!(read_cache_file(&cache_file, &path).unwrap().0, *ptr.pos_map);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_file_checks_format_version() {
+        let dir = temp_cache_dir("format");
+        let path = dir.join("file.ucg");
+        let ops = compile(&path, SRC).unwrap();
+        let cache_file = cache_file_path(&dir, &path, SRC);
+        write_cache_file(&cache_file, &path, &ops, &[]).unwrap();
+        let mut bytes = fs::read(&cache_file).unwrap();
+        let format_offset = MAGIC.len() + 8 + VERSION.len();
+        bytes[format_offset..format_offset + 8]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&cache_file, &bytes).unwrap();
+        assert!(read_cache_file(&cache_file, &path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_prunes_other_versions() {
+        let dir = temp_cache_dir("prune");
+        let stale = dir.join("0.0.0-0");
+        fs::create_dir_all(&stale).unwrap();
+        let path = dir.join("file.ucg");
+        let ops = compile(&path, SRC).unwrap();
+        let cache_file = cache_file_path(&dir, &path, SRC);
+        write_cache_file(&cache_file, &path, &ops, &[]).unwrap();
+        assert!(!stale.exists());
+        assert!(cache_file.exists());
+        // Only the cache file is left behind.
+        assert_eq!(
+            fs::read_dir(cache_file.parent().unwrap()).unwrap().count(),
+            1
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_module_paths_are_not_persisted() {
+        let parse_str = |src: &str| parse(OffsetStrIter::new(src), None).unwrap();
+        assert!(depends_only_on_contents(&mut parse_str(SRC)));
+        assert!(!depends_only_on_contents(&mut parse_str(
+            "let m = module{} => (lib) { let lib = import \"lib.ucg\"; };"
+        )));
+        assert!(!depends_only_on_contents(&mut parse_str(
+            "let m = module{} => (s) { let s = include str \"data.txt\"; };"
+        )));
+        assert!(!depends_only_on_contents(&mut parse_str(
+            "let m = module{} => ((import \"lib.ucg\").v) {};"
+        )));
+    }
+}
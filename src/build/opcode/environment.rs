@@ -16,16 +16,20 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 use super::cache;
 use super::pointer::OpPointer;
 use super::Error;
 use super::Value;
+use crate::ast::{Position, Statement};
 use crate::build::stdlib;
 use crate::build::AssertCollector;
 use crate::build::Val;
 use crate::convert::{ConverterRegistry, ImporterRegistry};
+use crate::error::{diagnostic_json, render_warning, MessageFormat};
 use crate::iter::OffsetStrIter;
+use crate::lint::{Lint, Linter, Rule};
 use crate::parse::parse;
 
 // Shared Environmental between VM's for runtime usage.
@@ -42,7 +46,15 @@ where
     pub stdout: Stdout,
     pub stderr: Stderr,
     pub env_vars: BTreeMap<String, String>, // Environment Variables
+    env_tuple: Option<Rc<Value>>,
+    pub import_path: Vec<PathBuf>, // Paths to search for imports and includes.
     pub out_lock: BTreeSet<PathBuf>,
+    shared_out_lock: Option<Arc<Mutex<BTreeSet<PathBuf>>>>,
+    pub write_out_files: bool, // Whether out statements create their output files.
+    pub warnings: bool,        // Whether builds warn about unused bindings and imports.
+    warned: BTreeSet<PathBuf>, // The files we've already warned about.
+    pub message_format: MessageFormat, // How warnings are written to stderr.
+    pub dependencies: BTreeMap<PathBuf, BTreeSet<PathBuf>>, // The files each file imports or includes.
 }
 
 impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
@@ -54,6 +66,8 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
         let mut me = Self {
             val_cache: BTreeMap::new(),
             env_vars: vars,
+            env_tuple: None,
+            import_path: Vec::new(),
             op_cache: cache::Ops::new(),
             assert_results: AssertCollector::new(),
             converter_registry: ConverterRegistry::make_registry(),
@@ -61,9 +75,19 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
             stdout: out,
             stderr: err,
             out_lock: BTreeSet::new(),
+            shared_out_lock: None,
+            write_out_files: true,
+            warnings: true,
+            warned: BTreeSet::new(),
+            message_format: MessageFormat::Human,
+            dependencies: BTreeMap::new(),
         };
         me.populate_stdlib();
-        return me
+        return me;
+    }
+
+    pub fn set_import_path(&mut self, paths: &[PathBuf]) {
+        self.import_path = paths.to_vec();
     }
 
     pub fn get_cached_path_val(&self, path: &String) -> Option<Rc<Value>> {
@@ -78,49 +102,151 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
     where
         P: Into<PathBuf> + Clone,
     {
-        let path_copy = path.clone();
-        self.op_cache.entry(path.clone()).get_pointer_or_else(
+        let p = path.into();
+        let root = p.parent().unwrap().to_path_buf();
+        let import_path = &self.import_path;
+        let ptr = self.op_cache.entry(p.clone()).get_pointer_or_else(
             || {
-                // FIXME(jwall): We need to do proper error handling here.
-                let p = path.into();
-                let root = p.parent().unwrap();
                 // first we read in the file
                 let mut f = File::open(&p)?;
-                // then we parse it
                 let mut contents = String::new();
                 f.read_to_string(&mut contents)?;
-                let iter = OffsetStrIter::new(&contents).with_src_file(&p);
-                // FIXME(jwall): Unify BuildError and our other Error
-                let stmts = parse(iter, None).unwrap();
+                Ok(contents)
+            },
+            |contents| {
+                // then we parse it
+                let iter = OffsetStrIter::new(contents).with_src_file(&p);
+                let mut stmts = parse(iter, None)?;
+                let persist = cache::depends_only_on_contents(&mut stmts);
+                // The lints are cached with the ops so later builds can
+                // warn without parsing the file again.
+                let lints = unused_lints(&p, &mut stmts);
                 // then we create an ops from it
-                let ops = super::translate::AST::translate(stmts, &root);
-                Ok(ops)
+                let ops = super::translate::AST::translate(stmts, &root, import_path);
+                Ok((ops, lints, persist))
             },
-            path_copy,
-        )
+            p.clone(),
+        )?;
+        if self.warnings && self.warned.insert(warned_key(&p)) {
+            let lints = self.op_cache.lints(&p);
+            write_warnings(&mut self.stderr, lints, self.message_format);
+        }
+        Ok(ptr)
     }
 
     fn add_ops_for_path_and_content<P>(&mut self, path: P, contents: &str) -> Result<(), Error>
     where
         P: Into<PathBuf> + Clone,
     {
-        let path_copy = path.clone();
-        self.op_cache.entry(path.clone()).get_pointer_or_else(
-            || {
-                let p = path.into();
-                let root = p.parent().unwrap();
+        let p = path.into();
+        let root = p.parent().unwrap().to_path_buf();
+        let import_path = &self.import_path;
+        self.op_cache.entry(p.clone()).get_pointer_or_else(
+            || Ok(contents.to_owned()),
+            |contents| {
                 let iter = OffsetStrIter::new(contents).with_src_file(&p);
-                // FIXME(jwall): Unify BuildError and our other Error
-                let stmts = parse(iter, None).unwrap();
+                let mut stmts = parse(iter, None)?;
+                let persist = cache::depends_only_on_contents(&mut stmts);
                 // then we create an ops from it
-                let ops = super::translate::AST::translate(stmts, &root);
-                Ok(ops)
+                let ops = super::translate::AST::translate(stmts, &root, import_path);
+                Ok((ops, Vec::new(), persist))
             },
-            path_copy,
+            p.clone(),
         )?;
         Ok(())
     }
 
+    /// Warns on stderr about the let bindings in a file that are never used
+    /// or exported and the imports that are never used. Each file is only
+    /// warned about once until it gets invalidated.
+    pub fn warn_unused(&mut self, path: &Path, stmts: &mut [Statement]) {
+        if self.warnings && self.warned.insert(warned_key(path)) {
+            let lints = unused_lints(path, stmts);
+            write_warnings(&mut self.stderr, &lints, self.message_format);
+        }
+    }
+
+    /// Records that the file `from` imports or includes the file `to`.
+    pub fn record_dependency<P: Into<PathBuf>>(&mut self, from: Option<&PathBuf>, to: P) {
+        if let Some(from) = from {
+            self.dependencies
+                .entry(from.clone())
+                .or_default()
+                .insert(to.into());
+        }
+    }
+
+    /// Forgets the recorded dependencies of a file before it gets rebuilt.
+    pub fn clear_dependencies<P: AsRef<Path>>(&mut self, path: P) {
+        self.dependencies.remove(path.as_ref());
+    }
+
+    /// Returns every file that the file at path imports or includes either
+    /// directly or through the files it imports.
+    pub fn get_dependencies<P: AsRef<Path>>(&self, path: P) -> BTreeSet<PathBuf> {
+        let mut found = BTreeSet::new();
+        let mut queue = vec![path.as_ref().to_path_buf()];
+        while let Some(next) = queue.pop() {
+            if let Some(deps) = self.dependencies.get(&next) {
+                for dep in deps {
+                    if found.insert(dep.clone()) {
+                        queue.push(dep.clone());
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Invalidates the cached ops and values of the changed files and of
+    /// every file that depends on them. Returns the invalidated files.
+    pub fn invalidate_paths(&mut self, changed: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+        let mut stale = changed.clone();
+        for path in self.dependencies.keys() {
+            if !self.get_dependencies(path).is_disjoint(changed) {
+                stale.insert(path.clone());
+            }
+        }
+        for path in stale.iter() {
+            self.op_cache.invalidate(path);
+            self.warned.remove(path);
+            self.val_cache.remove(&path.to_string_lossy().to_string());
+        }
+        stale
+    }
+
+    /// Persist compiled op codes in the given directory so later builds can
+    /// reuse them.
+    pub fn set_op_cache_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.op_cache.set_cache_dir(dir);
+    }
+
+    /// Returns the tuple the `env` symbol resolves to.
+    ///
+    /// The tuple is constructed once and then shared so that every
+    /// reference to `env` is the same value.
+    pub fn get_env_vars_tuple(&mut self) -> Rc<Value> {
+        if let Some(ref tpl) = self.env_tuple {
+            return tpl.clone();
+        }
+        let flds = self
+            .env_vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let tpl: Rc<Value> = Rc::new(Val::Env(flds).into());
+        self.env_tuple = Some(tpl.clone());
+        tpl
+    }
+
+    /// Returns true if the value is the tuple the `env` symbol resolves to.
+    pub fn is_env_vars_tuple(&self, val: &Rc<Value>) -> bool {
+        match self.env_tuple {
+            Some(ref tpl) => Rc::ptr_eq(tpl, val),
+            None => false,
+        }
+    }
+
     fn populate_stdlib(&mut self) {
         for (p, s) in stdlib::get_libs().drain() {
             // We unwrap the error here since we expect stdlibs to
@@ -129,8 +255,8 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
         }
     }
 
-    pub fn record_assert_result(&mut self, desc: &str, ok: bool) {
-        self.assert_results.record_assert_result(desc, ok);
+    pub fn record_assert_result(&mut self, desc: &str, ok: bool, pos: &Position) {
+        self.assert_results.record_assert_result(desc, ok, pos);
     }
 
     pub fn get_out_lock_for_path<P: AsRef<Path>>(&self, path: P) -> bool {
@@ -145,6 +271,21 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
         self.out_lock.remove(path.as_ref());
     }
 
+    /// Shares the files that have been output to with the environments
+    /// on other threads.
+    pub fn set_shared_out_lock(&mut self, lock: Arc<Mutex<BTreeSet<PathBuf>>>) {
+        self.shared_out_lock = Some(lock);
+    }
+
+    /// Claims a file to write output to. Returns false if an environment on
+    /// another thread already output to it.
+    pub fn claim_out_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        match self.shared_out_lock {
+            Some(ref lock) => lock.lock().unwrap().insert(path.as_ref().to_path_buf()),
+            None => true,
+        }
+    }
+
     pub fn stdout(&self) -> Stdout {
         self.stdout.clone()
     }
@@ -173,3 +314,22 @@ impl<Stdout: Write + Clone, Stderr: Write + Clone> Environment<Stdout, Stderr> {
         return true;
     }
 }
+
+// The same file can be built and imported by different paths.
+fn warned_key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn unused_lints(path: &Path, stmts: &mut [Statement]) -> Vec<Lint> {
+    Linter::new(vec![Rule::UnusedLet, Rule::UnusedImport]).lint(path, stmts)
+}
+
+fn write_warnings<W: Write>(w: &mut W, lints: &[Lint], format: MessageFormat) {
+    for lint in lints {
+        // Warnings never fail the build.
+        let _ = match format {
+            MessageFormat::Human => write!(w, "{}", render_warning(lint)),
+            MessageFormat::Json => writeln!(w, "{}", diagnostic_json(lint)),
+        };
+    }
+}
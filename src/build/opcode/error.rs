@@ -18,23 +18,37 @@ use std::io;
 
 use crate::ast::Position;
 use crate::build::opcode::convert;
+use crate::error::{BuildError, ErrorType};
 
 #[derive(Debug)]
 pub struct Error {
+    err_type: ErrorType,
     message: String,
     pos: Option<Position>,
+    cause: Option<Box<dyn std::error::Error>>,
     call_stack: Vec<Position>,
 }
 
 impl Error {
-    pub fn new(msg: String, pos: Position) -> Self {
+    pub fn new(msg: String, t: ErrorType, pos: Position) -> Self {
         Self {
+            err_type: t,
             message: msg,
             pos: Some(pos),
+            cause: None,
             call_stack: Vec::new(),
         }
     }
 
+    /// Wraps an error from an importer or converter. Errors that already
+    /// have an ErrorType keep it and the rest get the one given.
+    pub fn wrap(e: Box<dyn std::error::Error>, t: ErrorType, pos: Position) -> Self {
+        match e.downcast::<BuildError>() {
+            Ok(e) => Self::from(*e).with_pos(pos),
+            Err(e) => Self::new(e.to_string(), t, pos),
+        }
+    }
+
     pub fn with_pos(mut self, pos: Position) -> Self {
         self.pos = Some(pos);
         self
@@ -43,6 +57,39 @@ impl Error {
     pub fn push_call_stack(&mut self, pos: Position) {
         self.call_stack.push(pos);
     }
+
+    pub fn err_type(&self) -> ErrorType {
+        self.err_type
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn pos(&self) -> Option<&Position> {
+        self.pos.as_ref()
+    }
+
+    pub fn cause(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause.as_ref().map(|c| c.as_ref())
+    }
+
+    /// The positions of the calls and imports that led to this error
+    /// innermost first.
+    pub fn call_stack(&self) -> &[Position] {
+        &self.call_stack
+    }
+
+    /// Records the import that led to this error. Errors without a position
+    /// of their own are reported at the import itself.
+    pub fn imported_from(mut self, pos: Position) -> Self {
+        if self.pos.is_none() {
+            self.pos = Some(pos);
+        } else {
+            self.call_stack.push(pos);
+        }
+        self
+    }
 }
 
 macro_rules! decorate_error {
@@ -54,6 +101,15 @@ macro_rules! decorate_error {
     };
 }
 
+macro_rules! decorate_import {
+    ($pos:expr => $result:expr) => {
+        match $result {
+            Ok(v) => Ok(v),
+            Err(e) => Err(e.imported_from($pos.clone())),
+        }
+    };
+}
+
 macro_rules! decorate_call {
     ($pos:expr => $result:expr) => {
         match $result {
@@ -69,8 +125,10 @@ macro_rules! decorate_call {
 impl From<regex::Error> for Error {
     fn from(e: regex::Error) -> Self {
         Error {
+            err_type: ErrorType::ParseError,
             message: format!("{}", e),
             pos: None,
+            cause: None,
             call_stack: Vec::new(),
         }
     }
@@ -79,14 +137,14 @@ impl From<regex::Error> for Error {
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
         let msg = match e.kind() {
-            io::ErrorKind::NotFound | io::ErrorKind::Other => {
-                format!("OSError: Path not found: {}", e)
-            }
+            io::ErrorKind::NotFound | io::ErrorKind::Other => format!("Path not found: {}", e),
             _ => format!("{}", e),
         };
         Error {
+            err_type: ErrorType::OSError,
             message: msg,
             pos: None,
+            cause: None,
             call_stack: Vec::new(),
         }
     }
@@ -95,8 +153,22 @@ impl From<std::io::Error> for Error {
 impl From<convert::Error> for Error {
     fn from(e: convert::Error) -> Self {
         Error {
+            err_type: ErrorType::TypeFail,
             message: e.message(),
             pos: None,
+            cause: None,
+            call_stack: Vec::new(),
+        }
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Error {
+            err_type: e.err_type,
+            message: e.msg,
+            pos: e.pos,
+            cause: e.cause,
             call_stack: Vec::new(),
         }
     }
@@ -104,11 +176,15 @@ impl From<convert::Error> for Error {
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.err_type)?;
         if let Some(ref pos) = self.pos {
             write!(f, "{} at {}", self.message, pos)?;
         } else {
             write!(f, "{}", self.message)?;
         }
+        if let Some(ref cause) = self.cause {
+            write!(f, "\nCaused By:\n\t{}", cause)?;
+        }
         if !self.call_stack.is_empty() {
             for p in self.call_stack.iter() {
                 write!(f, "\nVIA: {}", p)?;
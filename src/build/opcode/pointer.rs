@@ -15,6 +15,7 @@ use std::path::PathBuf;
 use std::rc::Rc;
 
 use crate::ast::Position;
+use crate::error::ErrorType;
 
 use super::translate::PositionMap;
 use super::{Error, Op};
@@ -61,6 +62,7 @@ impl OpPointer {
         }
         Err(Error::new(
             format!("FAULT!!! Invalid Jump!"),
+            ErrorType::Unsupported,
             match self.pos() {
                 Some(pos) => pos.clone(),
                 None => Position::new(0, 0, 0),
@@ -87,6 +89,7 @@ impl OpPointer {
             Some(ptr) => Ok(ptr),
             None => Err(Error::new(
                 format!("FAULT!!! Position Check failure!"),
+                ErrorType::Unsupported,
                 Position::new(0, 0, 0),
             )),
         }
@@ -26,21 +26,19 @@ use super::VM;
 use super::{Composite, Error, Hook, Primitive, Value};
 use crate::ast::Position;
 use crate::build::ir::Val;
+use crate::error::ErrorType;
 use Composite::{List, Tuple};
 use Primitive::{Bool, Empty, Int, Str};
 
 pub struct Builtins {
     pub strict: bool,
-    import_path: Vec<PathBuf>,
     validate_mode: bool,
 }
 
 impl Builtins {
     pub fn new(strict: bool) -> Self {
-        // FIXME(jwall): This should probably be injected in.
         Self {
             strict: strict,
-            import_path: Vec::new(),
             validate_mode: false,
         }
     }
@@ -48,7 +46,6 @@ impl Builtins {
     pub fn clone(&self) -> Self {
         Self {
             strict: self.strict,
-            import_path: self.import_path.clone(),
             validate_mode: self.validate_mode,
         }
     }
@@ -91,7 +88,7 @@ impl Builtins {
     fn normalize_path<P, BP>(
         &self,
         base_path: BP,
-        use_import_path: bool,
+        import_path: &[PathBuf],
         path: P,
     ) -> Result<PathBuf, Error>
     where
@@ -108,11 +105,12 @@ impl Builtins {
         if path.is_relative() {
             normalized.push(&path);
             // First see if the normalized file exists or not.
-            if !normalized.exists() && use_import_path {
+            if !normalized.exists() {
                 // TODO(jwall): Support importing from a zip file in this
                 // import_path?
                 // If it does not then look for it in the list of import_paths
-                for mut p in self.import_path.iter().cloned() {
+                // in order. The first one that has the file wins.
+                for mut p in import_path.iter().cloned() {
                     p.push(&path);
                     if p.exists() {
                         normalized = p;
@@ -140,36 +138,45 @@ impl Builtins {
         &self,
         base_path: BP,
         path: P,
-        use_import_path: bool,
+        import_path: &[PathBuf],
         pos: Position,
     ) -> Result<PathBuf, Error>
     where
         P: Into<PathBuf> + Display + Clone,
         BP: Into<PathBuf>,
     {
-        // FIXME(jwall): Use import paths if desired.
-        match self.normalize_path(base_path, use_import_path, path.clone()) {
+        match self.normalize_path(base_path, import_path, path.clone()) {
             Ok(p) => Ok(p),
             Err(e) => {
                 //panic!(format!("Error finding path {}: {}", path, e));
                 Err(Error::new(
                     format!("Error finding path {}: {}", path, e),
+                    ErrorType::ImportError,
                     pos,
                 ))
             }
         }
     }
 
-    fn get_file_as_string<P: Into<PathBuf>>(
+    fn get_file_as_string<P, O, E>(
         &self,
         base_path: P,
         path: &str,
+        env: &RefCell<Environment<O, E>>,
         pos: Position,
-    ) -> Result<String, Error> {
+    ) -> Result<String, Error>
+    where
+        P: Into<PathBuf>,
+        O: std::io::Write + Clone,
+        E: std::io::Write + Clone,
+    {
         let sep = format!("{}", std::path::MAIN_SEPARATOR);
         let raw_path = path.replace("/", &sep);
-        // FIXME(jwall): import paths?
-        let normalized = self.find_file(base_path, raw_path, false, pos)?;
+        let import_path = env.borrow().import_path.clone();
+        let include_file = pos.file.clone();
+        let normalized = self.find_file(base_path, raw_path, &import_path, pos)?;
+        env.borrow_mut()
+            .record_dependency(include_file.as_ref(), normalized.clone());
         // TODO(jwall): Proper error here
         let mut f = File::open(normalized)?;
         let mut contents = String::new();
@@ -195,10 +202,12 @@ impl Builtins {
         if let Some((val, path_pos)) = path {
             if let &Value::P(Str(ref path)) = val.as_ref() {
                 // TODO(jwall): A bit hacky we should probably change import stacks to be pathbufs.
-                let normalized =
-                    decorate_error!(path_pos => self.normalize_path(base_path, false, path))?;
+                let import_path = env.borrow().import_path.clone();
+                let normalized = decorate_error!(path_pos => self.normalize_path(base_path, &import_path, path))?;
                 // first we chack the cache
                 let path = normalized.to_string_lossy().to_string();
+                env.borrow_mut()
+                    .record_dependency(pos.file.as_ref(), normalized.clone());
                 if let Some(val) = env.borrow().get_cached_path_val(&path) {
                     stack.push((val, path_pos));
                     return Ok(());
@@ -206,6 +215,7 @@ impl Builtins {
                 if import_stack.iter().find(|p| *p == &path).is_some() {
                     return Err(Error::new(
                         format!("Import cycle detected: {} in {:?}", path, import_stack),
+                        ErrorType::ImportError,
                         pos,
                     ));
                 }
@@ -215,15 +225,22 @@ impl Builtins {
                         stack.push((v, path_pos));
                     }
                     None => {
-                        let op_pointer = decorate_error!(path_pos => env.borrow_mut().get_ops_for_path(&normalized))?;
+                        // The file is about to be run so any dependencies
+                        // recorded for it before are stale.
+                        env.borrow_mut().clear_dependencies(&normalized);
+                        let op_pointer = decorate_import!(path_pos => env.borrow_mut().get_ops_for_path(&normalized))?;
+                        // The imported file is on the import stack while it
+                        // runs so that cycles back to it get detected.
+                        let mut child_import_stack = import_stack.clone();
+                        child_import_stack.push(path.clone());
                         // TODO(jwall): What if we don't have a base path?
                         let mut vm = VM::with_pointer(
                             self.strict,
                             op_pointer,
                             normalized.parent().unwrap(),
                         )
-                        .with_import_stack(import_stack.clone());
-                        vm.run(env)?;
+                        .with_import_stack(child_import_stack);
+                        decorate_import!(path_pos => vm.run(env))?;
                         let result = Rc::new(vm.symbols_to_tuple(true));
                         env.borrow_mut().update_path_val(&path, result.clone());
                         stack.push((result, pos));
@@ -232,7 +249,7 @@ impl Builtins {
                 import_stack.push(path.clone());
                 return Ok(());
             }
-            return Err(Error::new(format!("Invalid Path {:?}", val), pos));
+            return Err(Error::new(format!("Invalid Path {:?}", val), ErrorType::ImportError, pos));
         }
         unreachable!();
     }
@@ -255,7 +272,11 @@ impl Builtins {
             if let &Value::P(Str(ref path)) = val.as_ref() {
                 path.clone()
             } else {
-                return Err(Error::new(format!("Invalid Path {:?}", val), path_pos));
+                return Err(Error::new(
+                    format!("Invalid Path {:?}", val),
+                    ErrorType::IncludeError,
+                    path_pos,
+                ));
             }
         } else {
             unreachable!();
@@ -266,6 +287,7 @@ impl Builtins {
             } else {
                 return Err(Error::new(
                     format!("Expected conversion type but got {:?}", val),
+                    ErrorType::IncludeError,
                     typ_pos,
                 ));
             }
@@ -277,27 +299,34 @@ impl Builtins {
                 Rc::new(P(Str(self.get_file_as_string(
                     base_path,
                     &path,
+                    env,
                     pos.clone(),
                 )?))),
                 pos.clone(),
             ));
         } else {
+            let contents = self.get_file_as_string(base_path, &path, env, pos.clone())?;
             stack.push((
                 Rc::new(match env.borrow().importer_registry.get_importer(&typ) {
                     Some(importer) => {
-                        let contents = self.get_file_as_string(base_path, &path, pos.clone())?;
                         if contents.len() == 0 {
                             eprintln!("including an empty file. Use NULL as the result");
                             P(Empty)
                         } else {
                             match importer.import(contents.as_bytes()) {
                                 Ok(v) => v.into(),
-                                Err(e) => return Err(Error::new(format!("{}", e), pos)),
+                                Err(e) => {
+                                    return Err(Error::wrap(e, ErrorType::IncludeError, pos))
+                                }
                             }
                         }
                     }
                     None => {
-                        return Err(Error::new(format!("No such conversion type {}", &typ), pos))
+                        return Err(Error::new(
+                            format!("No such conversion type {}", &typ),
+                            ErrorType::IncludeError,
+                            pos,
+                        ))
                     }
                 }),
                 pos,
@@ -336,7 +365,7 @@ impl Builtins {
                         "TYPE FAIL - Expected Boolean field ok in tuple {} at {}\n",
                         tuple, tpl_pos
                     );
-                    env.borrow_mut().record_assert_result(&msg, false);
+                    env.borrow_mut().record_assert_result(&msg, false, &tpl_pos);
                     return Ok(());
                 };
 
@@ -347,17 +376,17 @@ impl Builtins {
                         "TYPE FAIL - Expected String field desc in tuple {} at {}\n",
                         tuple, tpl_pos
                     );
-                    env.borrow_mut().record_assert_result(&msg, false);
+                    env.borrow_mut().record_assert_result(&msg, false, &tpl_pos);
                     return Ok(());
                 };
-                env.borrow_mut().record_assert_result(desc, ok);
+                env.borrow_mut().record_assert_result(desc, ok, &tpl_pos);
                 return Ok(());
             }
             let msg = format!(
                 "TYPE FAIL - Expected tuple with ok and desc fields got {} at {}\n",
                 tuple, tpl_pos
             );
-            env.borrow_mut().record_assert_result(&msg, false);
+            env.borrow_mut().record_assert_result(&msg, false, &tpl_pos);
         } else {
             unreachable!();
         }
@@ -378,9 +407,14 @@ impl Builtins {
     {
         let write_path: Option<PathBuf> = if let Some(path) = path {
             let write_path = path.as_ref().to_path_buf();
-            if env.borrow().get_out_lock_for_path(&path) {
+            // Environments on other threads may share the files that have
+            // been output to with us.
+            if env.borrow().get_out_lock_for_path(&path)
+                || !env.borrow().claim_out_path(&path)
+            {
                 return Err(Error::new(
                     format!("You can only have one output per file"),
+                    ErrorType::Unsupported,
                     pos,
                 ));
             }
@@ -390,6 +424,7 @@ impl Builtins {
             if env.borrow().get_out_lock_for_path("/dev/stdout") {
                 return Err(Error::new(
                     format!("You can only have one output per file"),
+                    ErrorType::Unsupported,
                     pos,
                 ));
             }
@@ -405,6 +440,7 @@ impl Builtins {
                     let stdout = env.borrow().stdout();
                     if let Some(c) = env.borrow().converter_registry.get_converter(c_type) {
                         let mut writer: Box<dyn std::io::Write> = match write_path {
+                            Some(_) if !env.borrow().write_out_files => Box::new(std::io::sink()),
                             Some(p) => {
                                 let p = p.with_extension(c.file_ext());
                                 Box::new(File::create(&p)?)
@@ -412,18 +448,20 @@ impl Builtins {
                             None => Box::new(stdout),
                         };
                         if let Err(e) = c.convert(Rc::new(val), &mut writer) {
-                            return Err(Error::new(format!("{}", e), pos.clone()));
+                            return Err(Error::wrap(e, ErrorType::ConvertError, pos.clone()));
                         }
                         return Ok(());
                     } else {
                         return Err(Error::new(
                             format!("No such conversion type {:?}", c_type),
+                            ErrorType::ConvertError,
                             c_type_pos,
                         ));
                     }
                 }
                 return Err(Error::new(
                     format!("Not a conversion type {:?}", c_type_val),
+                    ErrorType::ConvertError,
                     val_pos,
                 ));
             }
@@ -460,6 +498,7 @@ impl Builtins {
                             Err(_e) => {
                                 return Err(Error::new(
                                     format!("No such conversion type {:?}", c_type),
+                                    ErrorType::ConvertError,
                                     c_typ_pos,
                                 ));
                             }
@@ -470,6 +509,7 @@ impl Builtins {
             }
             return Err(Error::new(
                 format!("Not a conversion type {:?}", val),
+                ErrorType::ConvertError,
                 val_pos,
             ));
         }
@@ -503,7 +543,7 @@ impl Builtins {
         let f = if let &F(ref f) = fptr.as_ref() {
             f
         } else {
-            return Err(Error::new(format!("Not a function!!"), fptr_pos));
+            return Err(Error::new(format!("Not a function!!"), ErrorType::TypeFail, fptr_pos));
         };
 
         match list.as_ref() {
@@ -542,6 +582,7 @@ impl Builtins {
                                 format!(
                                     "Map Functions over tuples must return a list of two items"
                                 ),
+                                ErrorType::TypeFail,
                                 result_pos,
                             ));
                         }
@@ -549,6 +590,7 @@ impl Builtins {
                             &P(Str(ref name)) => name.clone(),
                             _ => return Err(Error::new(
                                 format!("Map functions over tuples must return a String as the first list item"),
+                                ErrorType::TypeFail,
                                 result_pos,
                             )),
                         };
@@ -572,6 +614,7 @@ impl Builtins {
                     } else {
                         return Err(Error::new(
                             format!("Map functions over string should return strings"),
+                            ErrorType::TypeFail,
                             result_pos,
                         ));
                     }
@@ -581,6 +624,7 @@ impl Builtins {
             _ => {
                 return Err(Error::new(
                     format!("You can only map over lists, tuples, or strings"),
+                    ErrorType::TypeFail,
                     pos,
                 ))
             }
@@ -615,7 +659,7 @@ impl Builtins {
         let f = if let &F(ref f) = fptr.as_ref() {
             f
         } else {
-            return Err(Error::new(format!("Not a function!!"), fptr_pos));
+            return Err(Error::new(format!("Not a function!!"), ErrorType::TypeFail, fptr_pos));
         };
 
         match list.as_ref() {
@@ -692,6 +736,7 @@ impl Builtins {
             _ => {
                 return Err(Error::new(
                     format!("You can only filter over lists, tuples, or strings"),
+                    ErrorType::TypeFail,
                     pos,
                 ))
             }
@@ -707,6 +752,7 @@ impl Builtins {
             } else {
                 return Err(Error::new(
                     format!("Expected string bug got {:?}", val),
+                    ErrorType::TypeFail,
                     val_pos,
                 ));
             }
@@ -721,6 +767,7 @@ impl Builtins {
             } else {
                 return Err(Error::new(
                     format!("Expected string bug got {:?}", val),
+                    ErrorType::TypeFail,
                     val_pos,
                 ));
             }
@@ -767,7 +814,7 @@ impl Builtins {
         let f = if let &F(ref f) = fptr.as_ref() {
             f
         } else {
-            return Err(Error::new(format!("Not a function!"), fptr_pos));
+            return Err(Error::new(format!("Not a function!"), ErrorType::TypeFail, fptr_pos));
         };
 
         match list.as_ref() {
@@ -818,6 +865,7 @@ impl Builtins {
             _ => {
                 return Err(Error::new(
                     format!("You can only reduce over lists, tuples, or strings"),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -866,6 +914,7 @@ impl Builtins {
             _ => {
                 return Err(Error::new(
                     format!("Ranges can only be created with Ints"),
+                    ErrorType::TypeFail,
                     pos,
                 ));
             }
@@ -905,7 +954,7 @@ impl Builtins {
             writable_val,
             &val_pos
         ) {
-            return Err(Error::new(format!("{}", e), pos));
+            return Err(Error::new(format!("{}", e), ErrorType::OSError, pos));
         };
         stack.push((val, val_pos));
         Ok(())
@@ -11,7 +11,7 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::ast::{
     BinaryExprType, BinaryOpDef, Expression, FormatArgs, Position, PositionedItem, SelectDef,
@@ -53,148 +53,167 @@ impl PositionMap {
 }
 
 impl AST {
-    pub fn translate<P: AsRef<Path>>(stmts: Vec<Statement>, root: &P) -> PositionMap {
+    pub fn translate<P: AsRef<Path>>(
+        stmts: Vec<Statement>,
+        root: &P,
+        import_paths: &[PathBuf],
+    ) -> PositionMap {
         let mut ops = PositionMap::new();
-        Self::translate_stmts(stmts, &mut ops, root.as_ref());
+        Self::translate_stmts(stmts, &mut ops, root.as_ref(), import_paths);
         return ops;
     }
 
-    pub fn translate_stmt(stmt: Statement, mut ops: &mut PositionMap, root: &Path) {
+    pub fn translate_stmt(
+        stmt: Statement,
+        mut ops: &mut PositionMap,
+        root: &Path,
+        import_paths: &[PathBuf],
+    ) {
         match stmt {
             Statement::Expression(expr) => {
                 let expr_pos = expr.pos().clone();
-                Self::translate_expr(expr, &mut ops, root);
+                Self::translate_expr(expr, &mut ops, root, import_paths);
                 ops.push(Op::Pop, expr_pos);
             }
             Statement::Assert(pos, expr) => {
-                Self::translate_expr(expr, &mut ops, root);
+                Self::translate_expr(expr, &mut ops, root, import_paths);
                 ops.push(Op::Runtime(Hook::Assert), pos);
             }
             Statement::Let(def) => {
                 let binding = def.name.fragment;
                 ops.push(Op::Sym(binding), def.name.pos);
-                Self::translate_expr(def.value, &mut ops, root);
+                Self::translate_expr(def.value, &mut ops, root, import_paths);
                 ops.push(Op::Bind, def.pos);
             }
             Statement::Output(pos, tok, expr) => {
                 ops.push(Op::Val(Primitive::Str(tok.fragment)), tok.pos);
-                Self::translate_expr(expr, &mut ops, root);
+                Self::translate_expr(expr, &mut ops, root, import_paths);
                 ops.push(Op::Runtime(Hook::Out), pos);
             }
             Statement::Print(pos, tok, expr) => {
                 ops.push(Op::Val(Primitive::Str(tok.fragment)), tok.pos);
-                Self::translate_expr(expr, &mut ops, root);
+                Self::translate_expr(expr, &mut ops, root, import_paths);
                 ops.push(Op::Runtime(Hook::Convert), pos.clone());
                 ops.push(Op::Pop, pos);
             }
         }
     }
 
-    fn translate_stmts(stmts: Vec<Statement>, mut ops: &mut PositionMap, root: &Path) {
+    fn translate_stmts(
+        stmts: Vec<Statement>,
+        mut ops: &mut PositionMap,
+        root: &Path,
+        import_paths: &[PathBuf],
+    ) {
         for stmt in stmts {
-            Self::translate_stmt(stmt, &mut ops, root);
+            Self::translate_stmt(stmt, &mut ops, root, import_paths);
         }
     }
 
-    fn translate_expr(expr: Expression, mut ops: &mut PositionMap, root: &Path) {
+    fn translate_expr(
+        expr: Expression,
+        mut ops: &mut PositionMap,
+        root: &Path,
+        import_paths: &[PathBuf],
+    ) {
         match expr {
             Expression::Simple(v) => {
-                Self::translate_value(v, &mut ops, root);
+                Self::translate_value(v, &mut ops, root, import_paths);
             }
             Expression::Binary(def) => {
                 match def.kind {
                     BinaryExprType::Add => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Add, def.pos);
                     }
                     BinaryExprType::Sub => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Sub, def.pos);
                     }
                     BinaryExprType::Div => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Div, def.pos);
                     }
                     BinaryExprType::Mul => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Mul, def.pos);
                     }
                     BinaryExprType::Equal => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Equal, def.pos);
                     }
                     BinaryExprType::GT => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Gt, def.pos);
                     }
                     BinaryExprType::LT => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Lt, def.pos);
                     }
                     BinaryExprType::GTEqual => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::GtEq, def.pos);
                     }
                     BinaryExprType::LTEqual => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::LtEq, def.pos);
                     }
                     BinaryExprType::NotEqual => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Equal, def.pos.clone());
                         ops.push(Op::Not, def.pos);
                     }
                     BinaryExprType::REMatch => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Runtime(Hook::Regex), def.pos);
                     }
                     BinaryExprType::NotREMatch => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Runtime(Hook::Regex), def.pos.clone());
                         ops.push(Op::Not, def.pos);
                     }
                     BinaryExprType::IS => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Typ, def.pos.clone());
                         ops.push(Op::Equal, def.pos);
                     }
                     BinaryExprType::AND => {
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Noop, def.pos);
                         let idx = ops.len() - 1;
-                        Self::translate_expr(*def.right, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
                         let jptr = (ops.len() - 1 - idx) as i32;
                         ops.replace(idx, Op::And(jptr));
                     }
                     BinaryExprType::OR => {
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Noop, def.pos); // Placeholder
                         let idx = ops.len() - 1;
-                        Self::translate_expr(*def.right, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
                         let jptr = (ops.len() - 1 - idx) as i32;
                         ops.replace(idx, Op::Or(jptr));
                     }
                     BinaryExprType::Mod => {
-                        Self::translate_expr(*def.right, &mut ops, root);
-                        Self::translate_expr(*def.left, &mut ops, root);
+                        Self::translate_expr(*def.right, &mut ops, root, import_paths);
+                        Self::translate_expr(*def.left, &mut ops, root, import_paths);
                         ops.push(Op::Mod, def.pos);
                     }
                     BinaryExprType::IN => {
                         // Dot expressions expect the right side to be pushed first
-                        Self::translate_expr(*def.right.clone(), &mut ops, root);
+                        Self::translate_expr(*def.right.clone(), &mut ops, root, import_paths);
                         // Symbols on the left side should be converted to strings to satisfy
                         // the Index operation contract.
                         // FIXME(jwall): List checks should not use symbol translation.
@@ -224,10 +243,10 @@ impl AST {
                                     )],
                                     pos: def.left.pos().clone(),
                                 });
-                                Self::translate_expr(new_expr, &mut ops, root);
+                                Self::translate_expr(new_expr, &mut ops, root, import_paths);
                             }
                             expr => {
-                                Self::translate_expr(expr, &mut ops, root);
+                                Self::translate_expr(expr, &mut ops, root, import_paths);
                             }
                         }
                         ops.push(Op::Exist, def.pos.clone());
@@ -238,7 +257,7 @@ impl AST {
                         match *def.right {
                             Expression::Copy(copy_def) => {
                                 // Dot expressions expect the left side to be pushed first
-                                Self::translate_expr(*def.left, &mut ops, root);
+                                Self::translate_expr(*def.left, &mut ops, root, import_paths);
                                 // first handle the selector
                                 match copy_def.selector {
                                     Value::Str(sym) | Value::Symbol(sym) => {
@@ -250,18 +269,24 @@ impl AST {
                                     _ => unreachable!(),
                                 }
                                 ops.push(Op::Index, copy_def.pos.clone());
-                                Self::translate_copy(ops, copy_def.fields, copy_def.pos, root);
+                                Self::translate_copy(
+                                    ops,
+                                    copy_def.fields,
+                                    copy_def.pos,
+                                    root,
+                                    import_paths,
+                                );
                                 return;
                             }
                             Expression::Call(call_def) => {
                                 // first push our arguments.
                                 let count = call_def.arglist.len() as i64;
                                 for e in call_def.arglist {
-                                    Self::translate_expr(e, &mut ops, root);
+                                    Self::translate_expr(e, &mut ops, root, import_paths);
                                 }
                                 ops.push(Op::Val(Primitive::Int(count)), call_def.pos.clone());
                                 // Dot expressions expect the left side to be pushed first
-                                Self::translate_expr(*def.left, &mut ops, root);
+                                Self::translate_expr(*def.left, &mut ops, root, import_paths);
                                 // then handle the selector
                                 let func_pos = call_def.funcref.pos().clone();
                                 match call_def.funcref {
@@ -279,17 +304,18 @@ impl AST {
                             }
                             Expression::Simple(Value::Symbol(name)) => {
                                 // Dot expressions expect the left side to be pushed first
-                                Self::translate_expr(*def.left, &mut ops, root);
+                                Self::translate_expr(*def.left, &mut ops, root, import_paths);
                                 Self::translate_expr(
                                     Expression::Simple(Value::Str(name)),
                                     &mut ops,
                                     root,
+                                    import_paths,
                                 );
                             }
                             expr => {
                                 // Dot expressions expect the left side to be pushed first
-                                Self::translate_expr(*def.left, &mut ops, root);
-                                Self::translate_expr(expr, &mut ops, root);
+                                Self::translate_expr(*def.left, &mut ops, root, import_paths);
+                                Self::translate_expr(expr, &mut ops, root, import_paths);
                             }
                         }
                         ops.push(Op::Index, def.pos);
@@ -297,11 +323,11 @@ impl AST {
                 };
             }
             Expression::Grouped(expr, _) => {
-                Self::translate_expr(*expr, &mut ops, root);
+                Self::translate_expr(*expr, &mut ops, root, import_paths);
             }
             Expression::Fail(def) => {
                 let msg_pos = def.message.pos().clone();
-                Self::translate_expr(*def.message, &mut ops, root);
+                Self::translate_expr(*def.message, &mut ops, root, import_paths);
                 ops.push(Op::Val(Primitive::Str("UserDefined: ".to_owned())), msg_pos);
                 ops.push(Op::Add, def.pos.clone());
                 ops.push(Op::Bang, def.pos);
@@ -326,6 +352,7 @@ impl AST {
                             &mut ops,
                             true,
                             root,
+                            import_paths,
                         );
                         for p in parts_iter {
                             Self::translate_template_part(
@@ -335,6 +362,7 @@ impl AST {
                                 &mut ops,
                                 true,
                                 root,
+                                import_paths,
                             );
                             // TODO(jwall): We could get a little more helpful about where
                             // these positions are.
@@ -355,7 +383,7 @@ impl AST {
                         // existed.
                         let expr_pos = expr.pos().clone();
                         ops.push(Op::Sym("item".to_owned()), expr.pos().clone());
-                        Self::translate_expr(*expr, &mut ops, root);
+                        Self::translate_expr(*expr, &mut ops, root, import_paths);
                         ops.push(Op::BindOver, expr_pos.clone());
                         let mut elems = Vec::new();
                         let mut elems_iter = elems.drain(0..);
@@ -366,6 +394,7 @@ impl AST {
                             &mut ops,
                             false,
                             root,
+                            import_paths,
                         );
                         for p in parts_iter {
                             Self::translate_template_part(
@@ -375,6 +404,7 @@ impl AST {
                                 &mut ops,
                                 false,
                                 root,
+                                import_paths,
                             );
                             ops.push(Op::Add, expr_pos.clone());
                         }
@@ -392,7 +422,7 @@ impl AST {
                 }
                 ops.push(Op::Noop, def.pos.clone());
                 let idx = ops.len() - 1;
-                Self::translate_expr(*def.fields, &mut ops, root);
+                Self::translate_expr(*def.fields, &mut ops, root, import_paths);
                 ops.push(Op::Return, def.pos);
                 let jptr = ops.len() - 1 - idx;
                 ops.replace(idx, Op::Func(jptr as i32));
@@ -401,27 +431,27 @@ impl AST {
                 match def {
                     FuncOpDef::Map(def) => {
                         // push the function on the stack first.
-                        Self::translate_expr(*def.func, &mut ops, root);
+                        Self::translate_expr(*def.func, &mut ops, root, import_paths);
                         // push the target on the stack third
-                        Self::translate_expr(*def.target, &mut ops, root);
+                        Self::translate_expr(*def.target, &mut ops, root, import_paths);
                         // finally push the Hook::Map opcode
                         ops.push(Op::Runtime(Hook::Map), def.pos);
                     }
                     FuncOpDef::Filter(def) => {
                         // push the function on the stack first.
-                        Self::translate_expr(*def.func, &mut ops, root);
+                        Self::translate_expr(*def.func, &mut ops, root, import_paths);
                         // push the target on the stack third
-                        Self::translate_expr(*def.target, &mut ops, root);
+                        Self::translate_expr(*def.target, &mut ops, root, import_paths);
                         // finally push the Hook::Map opcode
                         ops.push(Op::Runtime(Hook::Filter), def.pos);
                     }
                     FuncOpDef::Reduce(def) => {
                         // push the function on the stack first.
-                        Self::translate_expr(*def.func, &mut ops, root);
+                        Self::translate_expr(*def.func, &mut ops, root, import_paths);
                         // push the accumulator on the stack third
-                        Self::translate_expr(*def.acc, &mut ops, root);
+                        Self::translate_expr(*def.acc, &mut ops, root, import_paths);
                         // push the target on the stack third
-                        Self::translate_expr(*def.target, &mut ops, root);
+                        Self::translate_expr(*def.target, &mut ops, root, import_paths);
                         // finally push the Hook::Map opcode
                         ops.push(Op::Runtime(Hook::Reduce), def.pos);
                     }
@@ -437,7 +467,7 @@ impl AST {
                 ops.push(Op::Runtime(Hook::Include), def.pos);
             }
             Expression::Module(mut def) => {
-                def.imports_to_absolute(root.to_path_buf());
+                def.imports_to_absolute(root.to_path_buf(), import_paths);
                 let argset = def.arg_set;
                 let out_expr = def.out_expr;
                 let stmts = def.statements;
@@ -445,7 +475,7 @@ impl AST {
                 ops.push(Op::InitTuple, def.pos.clone());
                 for (t, e) in argset {
                     ops.push(Op::Sym(t.fragment), t.pos.clone());
-                    Self::translate_expr(e, &mut ops, root);
+                    Self::translate_expr(e, &mut ops, root, import_paths);
                     ops.push(Op::Field, t.pos);
                 }
                 // If there is one then emit our return expression
@@ -454,7 +484,7 @@ impl AST {
                     let expr_pos = expr.pos().clone();
                     ops.push(Op::Noop, expr.pos().clone());
                     let idx = ops.len() - 1;
-                    Self::translate_expr(*expr, &mut ops, root);
+                    Self::translate_expr(*expr, &mut ops, root, import_paths);
                     ops.push(Op::Return, expr_pos.clone());
                     let jptr = ops.len() - idx - 1;
                     ops.replace(idx, Op::InitThunk(jptr as i32));
@@ -466,36 +496,36 @@ impl AST {
                 // Bind our mod tuple.
                 ops.push(Op::Bind, def.pos.clone());
                 // emit all of our statements;
-                Self::translate_stmts(stmts, &mut ops, root);
+                Self::translate_stmts(stmts, &mut ops, root, import_paths);
                 // Return from the module
                 ops.push(Op::Return, def.pos);
                 let jptr = ops.len() - idx - 1;
                 ops.replace(idx, Op::Module(jptr as i32));
             }
             Expression::Not(def) => {
-                Self::translate_expr(*def.expr, &mut ops, root);
+                Self::translate_expr(*def.expr, &mut ops, root, import_paths);
                 ops.push(Op::Not, def.pos);
             }
             Expression::Range(def) => {
-                Self::translate_expr(*def.end, &mut ops, root);
+                Self::translate_expr(*def.end, &mut ops, root, import_paths);
                 if let Some(expr) = def.step {
-                    Self::translate_expr(*expr, &mut ops, root);
+                    Self::translate_expr(*expr, &mut ops, root, import_paths);
                 } else {
                     ops.push(Op::Val(Primitive::Empty), def.pos.clone());
                 }
-                Self::translate_expr(*def.start, &mut ops, root);
+                Self::translate_expr(*def.start, &mut ops, root, import_paths);
                 ops.push(Op::Runtime(Hook::Range), def.pos);
             }
             Expression::Select(def) => {
                 let default_pos = def.val.pos().clone();
-                Self::translate_expr(*def.val, &mut ops, root);
+                Self::translate_expr(*def.val, &mut ops, root, import_paths);
                 let mut jumps = Vec::new();
                 for (key, val) in def.tuple {
                     ops.push(Op::Sym(key.fragment), key.pos.clone());
                     ops.push(Op::Noop, key.pos);
                     let idx = ops.len() - 1;
                     let expr_pos = val.pos().clone();
-                    Self::translate_expr(val, &mut ops, root);
+                    Self::translate_expr(val, &mut ops, root, import_paths);
                     ops.push(Op::Noop, expr_pos);
                     jumps.push(ops.len() - 1);
                     let jptr = ops.len() - idx - 1;
@@ -503,7 +533,7 @@ impl AST {
                 }
                 ops.push(Op::Pop, def.pos.clone());
                 if let Some(default) = def.default {
-                    Self::translate_expr(*default, &mut ops, root);
+                    Self::translate_expr(*default, &mut ops, root, import_paths);
                 } else {
                     ops.push(
                         Op::Val(Primitive::Str(
@@ -522,21 +552,21 @@ impl AST {
             Expression::Call(call_def) => {
                 let count = call_def.arglist.len() as i64;
                 for e in call_def.arglist {
-                    Self::translate_expr(e, &mut ops, root);
+                    Self::translate_expr(e, &mut ops, root, import_paths);
                 }
                 ops.push(Op::Val(Primitive::Int(count)), call_def.pos.clone());
                 // then push the func reference
                 let func_pos = call_def.funcref.pos().clone();
-                Self::translate_value(call_def.funcref, &mut ops, root);
+                Self::translate_value(call_def.funcref, &mut ops, root, import_paths);
                 ops.push(Op::FCall, func_pos);
             }
             Expression::Cast(cast_def) => {
-                Self::translate_expr(*cast_def.target, &mut ops, root);
+                Self::translate_expr(*cast_def.target, &mut ops, root, import_paths);
                 ops.push(Op::Cast(cast_def.cast_type), cast_def.pos);
             }
             Expression::Copy(def) => {
-                Self::translate_value(def.selector, &mut ops, root);
-                Self::translate_copy(ops, def.fields, def.pos, root);
+                Self::translate_value(def.selector, &mut ops, root, import_paths);
+                Self::translate_copy(ops, def.fields, def.pos, root, import_paths);
             }
             Expression::Debug(def) => {
                 let mut buffer: Vec<u8> = Vec::new();
@@ -546,7 +576,7 @@ impl AST {
                 }
                 let expr_pretty = String::from_utf8(buffer).unwrap();
                 ops.push(Op::Val(Primitive::Str(expr_pretty)), def.pos.clone());
-                Self::translate_expr(*def.expr, &mut ops, root);
+                Self::translate_expr(*def.expr, &mut ops, root, import_paths);
                 ops.push(Op::Runtime(Hook::Trace(def.pos.clone())), def.pos);
             }
         }
@@ -559,6 +589,7 @@ impl AST {
         mut ops: &mut PositionMap,
         place_holder: bool,
         root: &Path,
+        import_paths: &[PathBuf],
     ) {
         match part {
             TemplatePart::Str(s) => {
@@ -569,7 +600,7 @@ impl AST {
                     // In theory this should never be reachable
                     unreachable!();
                 } else {
-                    Self::translate_expr(elems.next().unwrap(), &mut ops, root);
+                    Self::translate_expr(elems.next().unwrap(), &mut ops, root, import_paths);
                     ops.push(Op::Render, pos);
                 }
             }
@@ -577,7 +608,7 @@ impl AST {
                 if place_holder {
                     unreachable!();
                 } else {
-                    Self::translate_expr(expr, &mut ops, root);
+                    Self::translate_expr(expr, &mut ops, root, import_paths);
                     ops.push(Op::Render, pos);
                 }
             }
@@ -589,19 +620,25 @@ impl AST {
         flds: Vec<(Token, Expression)>,
         pos: Position,
         root: &Path,
+        import_paths: &[PathBuf],
     ) {
         ops.push(Op::PushSelf, pos.clone());
         ops.push(Op::InitTuple, pos.clone());
         for (t, e) in flds {
             ops.push(Op::Sym(t.fragment), t.pos.clone());
-            Self::translate_expr(e, &mut ops, root);
+            Self::translate_expr(e, &mut ops, root, import_paths);
             ops.push(Op::Field, t.pos.clone());
         }
         ops.push(Op::Cp, pos.clone());
         ops.push(Op::PopSelf, pos);
     }
 
-    fn translate_value(value: Value, mut ops: &mut PositionMap, root: &Path) {
+    fn translate_value(
+        value: Value,
+        mut ops: &mut PositionMap,
+        root: &Path,
+        import_paths: &[PathBuf],
+    ) {
         match value {
             Value::Int(i) => ops.push(Op::Val(Primitive::Int(i.val)), i.pos),
             Value::Float(f) => ops.push(Op::Val(Primitive::Float(f.val)), f.pos),
@@ -615,7 +652,7 @@ impl AST {
                 ops.push(Op::InitTuple, flds.pos);
                 for (k, v) in flds.val {
                     ops.push(Op::Sym(k.fragment), k.pos.clone());
-                    Self::translate_expr(v, &mut ops, root);
+                    Self::translate_expr(v, &mut ops, root, import_paths);
                     ops.push(Op::Field, k.pos.clone());
                 }
             }
@@ -623,7 +660,7 @@ impl AST {
                 ops.push(Op::InitList, els.pos);
                 for el in els.elems {
                     let el_pos = el.pos().clone();
-                    Self::translate_expr(el, &mut ops, root);
+                    Self::translate_expr(el, &mut ops, root, import_paths);
                     ops.push(Op::Element, el_pos);
                 }
             }
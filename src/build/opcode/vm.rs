@@ -18,6 +18,7 @@ use std::path::PathBuf;
 use std::rc::Rc;
 
 use crate::ast::{CastType, Position};
+use crate::error::ErrorType;
 
 use super::environment::Environment;
 use super::pointer::OpPointer;
@@ -152,7 +153,7 @@ impl VM {
                 Op::Val(p) => self.push(Rc::new(P(p.clone())), pos)?,
                 Op::Cast(t) => self.op_cast(t)?,
                 Op::Sym(s) => self.push(Rc::new(S(s.clone())), pos)?,
-                Op::DeRef(s) => self.op_deref(s.clone(), &pos)?,
+                Op::DeRef(s) => self.op_deref(s.clone(), &pos, env)?,
                 Op::Add => self.op_add(pos)?,
                 Op::Mod => self.op_mod(pos)?,
                 Op::Sub => self.op_sub(pos)?,
@@ -172,8 +173,8 @@ impl VM {
                 Op::InitTuple => self.push(Rc::new(C(Tuple(Vec::new(), Vec::new()))), pos)?,
                 Op::Field => self.op_field()?,
                 Op::Element => self.op_element()?,
-                Op::Index => self.op_index(!self.runtime.strict, pos)?,
-                Op::SafeIndex => self.op_index(true, pos)?,
+                Op::Index => self.op_index(!self.runtime.strict, pos, env)?,
+                Op::SafeIndex => self.op_index(true, pos, env)?,
                 Op::Exist => self.op_exist(pos)?,
                 Op::Cp => self.op_copy(pos, env)?,
                 //FIXME(jwall): Should this take a user provided message?
@@ -250,7 +251,21 @@ impl VM {
         Ok(())
     }
 
-    fn op_deref(&mut self, name: String, pos: &Position) -> Result<(), Error> {
+    fn op_deref<O, E>(
+        &mut self,
+        name: String,
+        pos: &Position,
+        env: &RefCell<Environment<O, E>>,
+    ) -> Result<(), Error>
+    where
+        O: std::io::Write + Clone,
+        E: std::io::Write + Clone,
+    {
+        // env is only special if it hasn't been bound to something else.
+        if name == "env" && !self.symbols.is_bound(&name) {
+            let val = env.borrow_mut().get_env_vars_tuple();
+            return self.push(val, pos.clone());
+        }
         let (val, _) = self.get_binding(&name, pos)?.clone();
         self.push(val, pos.clone())
     }
@@ -279,6 +294,7 @@ impl VM {
                     "Not a boolean condition {:?} in && expression at {}",
                     cond, pos
                 ),
+                ErrorType::TypeFail,
                 cond_pos.clone(),
             ));
         }
@@ -299,6 +315,7 @@ impl VM {
                     "Not a boolean condition {:?} in || expression at {}!",
                     cond, pos
                 ),
+                ErrorType::TypeFail,
                 cond_pos.clone(),
             ));
         }
@@ -314,6 +331,7 @@ impl VM {
         } else {
             return Err(Error::new(
                 format!("Expected boolean but got {:?}!", cond),
+                ErrorType::TypeFail,
                 cond_pos.clone(),
             ));
         }
@@ -329,6 +347,7 @@ impl VM {
         } else {
             return Err(Error::new(
                 format!("Expected boolean but got {:?}!", cond),
+                ErrorType::TypeFail,
                 pos.clone(),
             ));
         }
@@ -373,6 +392,7 @@ impl VM {
                 } else {
                     return Err(Error::new(
                         format!("Expected tuple but got {:?}", tpl_val),
+                        ErrorType::TypeFail,
                         tpl_val_pos,
                     ));
                 }
@@ -380,6 +400,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected tuple but got {:?}", mod_val),
+                    ErrorType::TypeFail,
                     mod_val_pos,
                 ));
             }
@@ -435,12 +456,17 @@ impl VM {
                 } else {
                     return Err(Error::new(
                         format!("Not an argument name {:?}", e),
+                        ErrorType::TypeFail,
                         args_pos,
                     ));
                 }
             }
         } else {
-            return Err(Error::new(format!("Fault!!! Bad Argument List"), args_pos));
+            return Err(Error::new(
+                format!("Fault!!! Bad Argument List"),
+                ErrorType::Unsupported,
+                args_pos,
+            ));
         }
         let mut ops = self.ops.clone();
         ops.jump(idx)?;
@@ -523,6 +549,7 @@ impl VM {
                             "Func called with too many args expected {} args but got {}",
                             arity, arg_length
                         ),
+                        ErrorType::BadArgLen,
                         pos,
                     ));
                 }
@@ -532,6 +559,7 @@ impl VM {
                             "Func called with too few args expected {} args but got {}",
                             arity, arg_length
                         ),
+                        ErrorType::BadArgLen,
                         pos,
                     ));
                 }
@@ -540,7 +568,7 @@ impl VM {
                 Self::fcall_impl(f, self.runtime.strict, &mut self.stack, env.clone(), &self.import_stack))?;
             self.push(val, pos.clone())?;
         } else {
-            return Err(Error::new(format!("Not a function! {:?}", f,), pos));
+            return Err(Error::new(format!("Not a function! {:?}", f,), ErrorType::TypeFail, pos));
         }
         Ok(())
     }
@@ -561,6 +589,7 @@ impl VM {
                 "Expected Boolean but got {:?} in expression at {}",
                 operand, pos
             ),
+            ErrorType::TypeFail,
             operand_pos,
         ));
     }
@@ -576,6 +605,7 @@ impl VM {
                     "Expected values of the same type but got {:?} at {} and {:?} at {} for expression",
                     left, left_pos, right, right_pos,
                 ),
+                ErrorType::TypeFail,
                 pos,
             ));
         }
@@ -599,6 +629,7 @@ impl VM {
                         "Expected numeric values of the same type but got {:?} at {} and {:?} at {} for expression",
                         left, left_pos, right, right_pos,
                     ),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ));
             }
@@ -622,6 +653,7 @@ impl VM {
                         "Expected numeric values of the same type but got {:?} at {} and {:?} at {} for expression",
                         left, left_pos, right, right_pos,
                     ),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ));
             }
@@ -645,6 +677,7 @@ impl VM {
                         "Expected numeric values of the same type but got {:?} at {} and {:?} at {} for expression",
                         left, left_pos, right, right_pos,
                     ),
+                    ErrorType::TypeFail,
                     pos,
                 ));
             }
@@ -668,6 +701,7 @@ impl VM {
                         "Expected numeric values of the same type but got {:?} at {} and {:?} at {} for expression",
                         left, left_pos, right, right_pos,
                     ),
+                    ErrorType::TypeFail,
                     pos,
                 ));
             }
@@ -803,13 +837,22 @@ impl VM {
     fn op_bang(&mut self) -> Result<(), Error> {
         let (msg_val, err_pos) = self.pop()?;
         if let &P(Str(ref msg)) = msg_val.as_ref() {
-            return Err(Error::new(msg.clone(), err_pos));
+            return Err(Error::new(msg.clone(), ErrorType::UserDefined, err_pos));
         } else {
             unreachable!();
         }
     }
 
-    fn op_index(&mut self, safe: bool, pos: Position) -> Result<(), Error> {
+    fn op_index<O, E>(
+        &mut self,
+        safe: bool,
+        pos: Position,
+        env: &RefCell<Environment<O, E>>,
+    ) -> Result<(), Error>
+    where
+        O: std::io::Write + Clone,
+        E: std::io::Write + Clone,
+    {
         // left and then right
         let (right, right_pos) = self.pop()?;
         let (left, _) = self.pop()?;
@@ -836,12 +879,30 @@ impl VM {
                 // noop
             }
         };
+        if env.borrow().is_env_vars_tuple(&left) {
+            // We don't want to dump the whole environment into the error message.
+            let msg = match right.as_ref() {
+                P(Str(ref s)) => format!("No such environment variable {}", s),
+                _ => format!("Invalid environment variable selector {}", right),
+            };
+            if safe {
+                if !self.runtime.strict {
+                    if let Err(e) = writeln!(&mut env.borrow_mut().stderr, "WARNING: {} at {}", msg, pos) {
+                        return Err(Error::new(format!("{}", e), ErrorType::OSError, pos));
+                    }
+                }
+                self.push(Rc::new(P(Empty)), pos)?;
+                return Ok(());
+            }
+            return Err(Error::new(msg, ErrorType::NoSuchSymbol, pos));
+        }
         if safe {
             self.push(Rc::new(P(Empty)), pos)?;
             return Ok(());
         }
         return Err(Error::new(
             format!("Invalid selector index: {:?} target: {:?}", right, left),
+            ErrorType::NoSuchSymbol,
             pos,
         ));
     }
@@ -861,6 +922,7 @@ impl VM {
                 } else {
                     return Err(Error::new(
                         format!("Expected String or Symbol got: {}", right.type_name()),
+                        ErrorType::TypeFail,
                         right_pos,
                     ));
                 }
@@ -882,6 +944,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected String, Tuple, or List got: {}", left.type_name()),
+                    ErrorType::TypeFail,
                     left_pos,
                 ));
             }
@@ -1002,6 +1065,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected a Tuple or Module but got {:?}", tgt),
+                    ErrorType::TypeFail,
                     pos,
                 ));
             }
@@ -1031,6 +1095,7 @@ impl VM {
                             name,
                             value.type_name(),
                         ),
+                        ErrorType::TypeFail,
                         val_pos.clone(),
                     ));
                 }
@@ -1061,12 +1126,14 @@ impl VM {
         if self.reserved_words.contains(name.as_str()) {
             return Err(Error::new(
                 format!("{} is a reserved word.", name),
+                ErrorType::ReservedWordError,
                 name_pos.clone(),
             ));
         }
         if self.symbols.is_bound(&name) && strict {
             return Err(Error::new(
                 format!("Binding {} already exists", name),
+                ErrorType::DuplicateBinding,
                 pos.clone(),
             ));
         }
@@ -1087,7 +1154,11 @@ impl VM {
         match tpl {
             Some((v, pos)) => Ok((v, pos)),
             None => {
-                return Err(Error::new(format!("No such binding {}", name), pos.clone()));
+                return Err(Error::new(
+                    format!("No such binding {}", name),
+                    ErrorType::NoSuchSymbol,
+                    pos.clone(),
+                ));
             }
         }
     }
@@ -1109,6 +1180,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected {} but got {:?}", left.type_name(), right),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -1122,6 +1194,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected {} but got {:?}", left.type_name(), right),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -1135,6 +1208,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected {} but got {:?}", left.type_name(), right),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -1148,6 +1222,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected {} but got {:?}", left.type_name(), right),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -1188,6 +1263,7 @@ impl VM {
             _ => {
                 return Err(Error::new(
                     format!("Expected {} but got {:?}", left.type_name(), right),
+                    ErrorType::TypeFail,
                     pos.clone(),
                 ))
             }
@@ -0,0 +1,278 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reporters for the results of `ucg test` in formats CI systems understand.
+use std::error::Error;
+use std::io::Write;
+
+use xml::writer::events::XmlEvent;
+use xml::EmitterConfig;
+
+use super::AssertResult;
+
+/// The results of testing a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    pub file: String,
+    pub results: Vec<AssertResult>,
+    /// The error the file failed to build with if it did.
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn new<S: Into<String>>(file: S) -> Self {
+        Self {
+            file: file.into(),
+            results: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.results.iter().filter(|r| !r.ok).count()
+    }
+
+    fn errors(&self) -> usize {
+        if self.error.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn tests(&self) -> usize {
+        self.results.len() + self.errors()
+    }
+
+    fn result_file(&self, result: &AssertResult) -> String {
+        match result.pos.file {
+            Some(ref f) => f.to_string_lossy().to_string(),
+            None => self.file.clone(),
+        }
+    }
+}
+
+/// Writes the reports as a JUnit XML document.
+///
+/// Every file is a testsuite and every assertion is a testcase. A file that
+/// fails to build gets a single testcase with an error.
+pub fn write_junit<W: Write>(reports: &[FileReport], w: W) -> Result<(), Box<dyn Error>> {
+    let mut writer = EmitterConfig::new().perform_indent(true).create_writer(w);
+    let tests = reports
+        .iter()
+        .map(FileReport::tests)
+        .sum::<usize>()
+        .to_string();
+    let failures = reports
+        .iter()
+        .map(FileReport::failures)
+        .sum::<usize>()
+        .to_string();
+    let errors = reports
+        .iter()
+        .map(FileReport::errors)
+        .sum::<usize>()
+        .to_string();
+    writer.write(
+        XmlEvent::start_element("testsuites")
+            .attr("name", "ucg")
+            .attr("tests", &tests)
+            .attr("failures", &failures)
+            .attr("errors", &errors),
+    )?;
+    for report in reports {
+        let tests = report.tests().to_string();
+        let failures = report.failures().to_string();
+        let errors = report.errors().to_string();
+        writer.write(
+            XmlEvent::start_element("testsuite")
+                .attr("name", &report.file)
+                .attr("tests", &tests)
+                .attr("failures", &failures)
+                .attr("errors", &errors),
+        )?;
+        for result in report.results.iter() {
+            let file = report.result_file(result);
+            let line = result.pos.line.to_string();
+            writer.write(
+                XmlEvent::start_element("testcase")
+                    .attr("name", &result.desc)
+                    .attr("classname", &report.file)
+                    .attr("file", &file)
+                    .attr("line", &line),
+            )?;
+            if !result.ok {
+                writer.write(XmlEvent::start_element("failure").attr("message", &result.desc))?;
+                writer.write(XmlEvent::characters(&format!(
+                    "{} at {}:{}:{}",
+                    result.desc, file, result.pos.line, result.pos.column
+                )))?;
+                writer.write(XmlEvent::end_element())?;
+            }
+            writer.write(XmlEvent::end_element())?;
+        }
+        if let Some(ref err) = report.error {
+            writer.write(
+                XmlEvent::start_element("testcase")
+                    .attr("name", &report.file)
+                    .attr("classname", &report.file)
+                    .attr("file", &report.file),
+            )?;
+            let message = err.lines().next().unwrap_or("");
+            writer.write(XmlEvent::start_element("error").attr("message", message))?;
+            writer.write(XmlEvent::characters(err))?;
+            writer.write(XmlEvent::end_element())?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+    writeln!(writer.inner_mut())?;
+    Ok(())
+}
+
+/// Writes the reports as a TAP version 13 stream.
+///
+/// Every assertion is a test point. A file that fails to build is a single
+/// failed test point with the error as its diagnostic.
+pub fn write_tap<W: Write>(reports: &[FileReport], mut w: W) -> Result<(), Box<dyn Error>> {
+    writeln!(w, "TAP version 13")?;
+    writeln!(
+        w,
+        "1..{}",
+        reports.iter().map(FileReport::tests).sum::<usize>()
+    )?;
+    let mut counter = 0;
+    for report in reports {
+        writeln!(w, "# {}", report.file)?;
+        for result in report.results.iter() {
+            counter += 1;
+            let status = if result.ok { "ok" } else { "not ok" };
+            writeln!(w, "{} {} - {}", status, counter, tap_escape(&result.desc))?;
+            if !result.ok {
+                writeln!(w, "  ---")?;
+                writeln!(w, "  file: {}", yaml_string(&report.result_file(result)))?;
+                writeln!(w, "  line: {}", result.pos.line)?;
+                writeln!(w, "  column: {}", result.pos.column)?;
+                writeln!(w, "  ...")?;
+            }
+        }
+        if let Some(ref err) = report.error {
+            counter += 1;
+            writeln!(w, "not ok {} - {}", counter, tap_escape(&report.file))?;
+            writeln!(w, "  ---")?;
+            writeln!(w, "  file: {}", yaml_string(&report.file))?;
+            writeln!(w, "  message: |")?;
+            for line in err.lines() {
+                writeln!(w, "    {}", line)?;
+            }
+            writeln!(w, "  ...")?;
+        }
+    }
+    Ok(())
+}
+
+// A # in a description would start a TAP directive.
+fn tap_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace('\n', " ")
+}
+
+fn yaml_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+#[cfg(test)]
+mod report_test {
+    use super::*;
+    use crate::ast::Position;
+
+    fn reports() -> Vec<FileReport> {
+        let mut passing = FileReport::new("a_test.ucg");
+        passing.results.push(AssertResult {
+            desc: "one is one".to_string(),
+            ok: true,
+            pos: Position::new(1, 8, 7).with_file("/tmp/a_test.ucg"),
+        });
+        passing.results.push(AssertResult {
+            desc: "one is <two> # maybe".to_string(),
+            ok: false,
+            pos: Position::new(2, 8, 30).with_file("/tmp/a_test.ucg"),
+        });
+        let mut broken = FileReport::new("b_test.ucg");
+        broken.error = Some("Expected Int\ncaused by: something".to_string());
+        vec![passing, broken]
+    }
+
+    #[test]
+    fn test_write_tap() {
+        let mut out = Vec::new();
+        write_tap(&reports(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"TAP version 13
+1..3
+# a_test.ucg
+ok 1 - one is one
+not ok 2 - one is <two> \# maybe
+  ---
+  file: "/tmp/a_test.ucg"
+  line: 2
+  column: 8
+  ...
+# b_test.ucg
+not ok 3 - b_test.ucg
+  ---
+  file: "b_test.ucg"
+  message: |
+    Expected Int
+    caused by: something
+  ...
+"#
+        );
+    }
+
+    #[test]
+    fn test_write_junit() {
+        let mut out = Vec::new();
+        write_junit(&reports(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains(r#"<testsuites name="ucg" tests="3" failures="1" errors="1">"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<testsuite name="a_test.ucg" tests="2" failures="1" errors="0">"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<testcase name="one is one" classname="a_test.ucg" file="/tmp/a_test.ucg" line="1" />"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<failure message="one is &lt;two&gt; # maybe">one is &lt;two> # maybe at /tmp/a_test.ucg:2:8</failure>"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<error message="Expected Int">Expected Int"#),
+            "{}",
+            out
+        );
+    }
+}
@@ -164,3 +164,126 @@ fn test_expr_copy_no_such_tuple() {
         b,
     );
 }
+
+#[test]
+fn test_get_out_by_selector() {
+    let i_paths = Vec::new();
+    let out: Vec<u8> = Vec::new();
+    let err: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out, err));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, &env);
+    b.eval_string(
+        "let services = {
+            web = {
+                ports = [80, 443],
+                \"log.level\" = \"debug\",
+            },
+        };",
+    )
+    .unwrap();
+    assert_eq!(
+        b.get_out_by_selector("services.web.ports.1").unwrap(),
+        Rc::new(Val::Int(443))
+    );
+    assert_eq!(
+        b.get_out_by_selector("services.web.\"log.level\"").unwrap(),
+        Rc::new(Val::Str("debug".to_owned()))
+    );
+    assert_eq!(b.get_out_by_selector("").unwrap(), b.out.clone().unwrap());
+    assert!(b.get_out_by_selector("services.web.ports.2").is_err());
+    assert!(b.get_out_by_selector("services.db").is_err());
+    assert!(b.get_out_by_selector("services..web").is_err());
+    assert!(b.get_out_by_selector("services.\"web").is_err());
+}
+
+fn env_var_builder_test(strict: bool, input: &str) -> (Result<Option<Rc<Val>>, String>, String) {
+    let i_paths = Vec::new();
+    let out: Vec<u8> = Vec::new();
+    let err: Vec<u8> = Vec::new();
+    let mut vars = std::collections::BTreeMap::new();
+    vars.insert("DEPLOY_ENV".to_owned(), "prod".to_owned());
+    let env = RefCell::new(Environment::new_with_vars(out, err, vars));
+    let result = {
+        let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, &env);
+        b.set_strict(strict);
+        b.eval_string(input)
+            .map(|_| b.get_out_by_name("result"))
+            .map_err(|e| format!("{}", e))
+    };
+    let stderr = String::from_utf8(env.borrow().stderr.clone()).unwrap();
+    (result, stderr)
+}
+
+#[test]
+fn test_env_symbol_lookup() {
+    for strict in vec![true, false] {
+        let (result, _) = env_var_builder_test(strict, "let result = env.DEPLOY_ENV;");
+        assert_eq!(
+            result.unwrap(),
+            Some(Rc::new(Val::Str("prod".to_owned())))
+        );
+    }
+}
+
+#[test]
+fn test_env_symbol_missing_var_strict() {
+    let (result, _) = env_var_builder_test(true, "let result = env.MISSING;");
+    let msg = result.unwrap_err();
+    assert!(
+        msg.contains("No such environment variable MISSING"),
+        "Unexpected error: {}",
+        msg
+    );
+    assert!(!msg.contains("prod"), "Leaked env vars in error: {}", msg);
+}
+
+#[test]
+fn test_env_symbol_missing_var_nostrict() {
+    let (result, stderr) = env_var_builder_test(false, "let result = env.MISSING;");
+    assert_eq!(result.unwrap(), Some(Rc::new(Val::Empty)));
+    assert!(
+        stderr.contains("WARNING: No such environment variable MISSING"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_env_symbol_can_be_shadowed() {
+    let (result, _) = env_var_builder_test(
+        true,
+        "let env = {DEPLOY_ENV = \"dev\"};\nlet result = env.DEPLOY_ENV;",
+    );
+    assert_eq!(result.unwrap(), Some(Rc::new(Val::Str("dev".to_owned()))));
+}
+
+#[test]
+fn test_assert_filter_and_reset() {
+    let i_paths = Vec::new();
+    let env = RefCell::new(Environment::new(Vec::new(), Vec::new()));
+    env.borrow_mut()
+        .assert_results
+        .set_filter(regex::Regex::new("^math").unwrap());
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, &env);
+    b.enable_validate_mode();
+    b.eval_string(
+        "assert {ok = 1 == 2, desc = \"strings\"};\nassert {ok = 1 + 1 == 2, desc = \"math works\"};",
+    )
+    .unwrap();
+    {
+        let results = &env.borrow().assert_results;
+        assert!(results.success);
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.results[0].desc, "math works");
+        assert_eq!(results.results[0].pos.line, 2);
+    }
+    env.borrow_mut().assert_results.reset();
+    b.eval_string(
+        "assert {ok = false, desc = \"math fails\"};\nassert {ok = false, desc = \"other\"};",
+    )
+    .unwrap();
+    let results = &env.borrow().assert_results;
+    assert!(!results.success);
+    assert_eq!(results.counter, 1);
+    assert_eq!(results.summary, "0 - NOT OK: math fails\n");
+}
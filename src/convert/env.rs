@@ -17,65 +17,143 @@ use std::io::Write as IOWrite;
 use std::rc::Rc;
 
 use crate::build::Val;
-use crate::convert::traits::{ConvertResult, Converter};
+use crate::convert::traits::{ConvertResult, Converter, ImportResult, Importer};
+use crate::error::{BuildError, ErrorType};
 
 /// EnvConverter implements the conversion logic for converting a Val into a
-/// set of environment variables.
-pub struct EnvConverter {}
+/// set of environment variables that a shell can source or a dotenv library
+/// can load.
+pub struct EnvConverter {
+    sep: String,
+    prefix: Option<String>,
+    export: bool,
+    strict: bool,
+}
 
 impl EnvConverter {
     pub fn new() -> Self {
-        EnvConverter {}
+        EnvConverter {
+            sep: "_".to_string(),
+            prefix: None,
+            export: false,
+            strict: false,
+        }
     }
 
-    fn convert_tuple(&self, flds: &Vec<(String, Rc<Val>)>, w: &mut dyn IOWrite) -> ConvertResult {
-        for &(ref name, ref val) in flds.iter() {
-            if val.is_tuple() {
-                eprintln!("Skipping embedded tuple...");
-                return Ok(());
-            }
-            if let &Val::Empty = val.as_ref() {
-                eprintln!("Skipping empty variable: {}", name);
-                return Ok(());
-            }
-            write!(w, "{}=", name)?;
-            self.write(&val, w)?;
+    /// Sets the separator between the names of nested tuples and their fields.
+    pub fn with_sep<S: Into<String>>(mut self, sep: S) -> Self {
+        self.sep = sep.into();
+        self
+    }
+
+    /// Sets a prefix for every variable name.
+    pub fn with_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Prefixes every variable with `export`.
+    pub fn with_export(mut self, export: bool) -> Self {
+        self.export = export;
+        self
+    }
+
+    /// Errors on values that can't be environment variables instead of
+    /// skipping them.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // Values that can't be environment variables are skipped with a note on
+    // stderr unless we are strict.
+    fn skip(&self, msg: String) -> ConvertResult {
+        if self.strict {
+            return Err(BuildError::new(msg, ErrorType::ConvertError).to_boxed());
         }
+        eprintln!("{}. Skipping it.", msg);
         Ok(())
     }
 
-    fn convert_list(&self, _items: &Vec<Rc<Val>>, _w: &mut dyn IOWrite) -> ConvertResult {
-        eprintln!("Skipping List...");
-        Ok(())
+    fn is_valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+            _ => return false,
+        }
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
     }
 
-    fn write(&self, v: &Val, w: &mut dyn IOWrite) -> ConvertResult {
-        match v {
-            &Val::Empty => {
-                // Empty is a noop.
-                return Ok(());
-            }
-            &Val::Boolean(b) => {
-                write!(w, "{}\n", if b { "true" } else { "false" })?;
-            }
-            &Val::Float(ref f) => {
-                write!(w, "{}\n", f)?;
+    /// Quotes a string so a POSIX shell and dotenv parsers both read it back
+    /// unchanged. Single quotes don't interpret anything so they are used
+    /// unless the string itself contains one.
+    fn quote(s: &str) -> String {
+        if !s.contains('\'') {
+            return format!("'{}'", s);
+        }
+        let mut quoted = String::with_capacity(s.len() + 2);
+        quoted.push('"');
+        for c in s.chars() {
+            if c == '"' || c == '\\' || c == '$' || c == '`' {
+                quoted.push('\\');
             }
-            &Val::Int(ref i) => {
-                write!(w, "{}\n", i)?;
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn write_var(&self, name: &str, v: &Val, w: &mut dyn IOWrite) -> ConvertResult {
+        let val = match v {
+            // Empty values are not emitted.
+            Val::Empty => return Ok(()),
+            Val::Boolean(b) => b.to_string(),
+            Val::Float(f) => f.to_string(),
+            Val::Int(i) => i.to_string(),
+            Val::Str(s) => Self::quote(s),
+            Val::Tuple(flds) => {
+                for (fld, val) in flds.iter() {
+                    self.write_var(&format!("{}{}{}", name, self.sep, fld), val, w)?;
+                }
+                return Ok(());
             }
-            &Val::Str(ref s) => {
-                write!(w, "'{}'\n", s)?;
+            Val::List(_) => {
+                return self.skip(format!(
+                    "Env variables can't be lists but {} is a list",
+                    name
+                ));
             }
-            &Val::List(ref items) => {
-                self.convert_list(items, w)?;
+            Val::Env(_) => {
+                return self.skip(format!("Env variables can't be env but {} is env", name));
             }
-            &Val::Tuple(ref flds) => {
-                self.convert_tuple(flds, w)?;
+        };
+        if !Self::is_valid_name(name) {
+            return self.skip(format!("Invalid environment variable name {:?}", name));
+        }
+        if self.export {
+            write!(w, "export ")?;
+        }
+        writeln!(w, "{}={}", name, val)?;
+        Ok(())
+    }
+
+    fn write(&self, v: &Val, w: &mut dyn IOWrite) -> ConvertResult {
+        let flds = match v {
+            Val::Tuple(flds) => flds,
+            _ => {
+                return Err(BuildError::new(
+                    "Env conversions expect a tuple",
+                    ErrorType::ConvertError,
+                )
+                .to_boxed());
             }
-            &Val::Env(ref _fs) => {
-                // This is ignored
-                eprintln!("Skipping env...");
+        };
+        for (name, val) in flds.iter() {
+            match self.prefix {
+                Some(ref prefix) => {
+                    self.write_var(&format!("{}{}{}", prefix, self.sep, name), val, w)?
+                }
+                None => self.write_var(name, val, w)?,
             }
         }
         Ok(())
@@ -92,7 +170,13 @@ impl Converter for EnvConverter {
     }
 
     fn description(&self) -> String {
-        "Convert ucg Vals into environment variables.".to_string()
+        if self.export {
+            "Convert ucg Vals into exported environment variables.".to_string()
+        } else if self.strict {
+            "Convert ucg Vals into a dotenv file.".to_string()
+        } else {
+            "Convert ucg Vals into environment variables.".to_string()
+        }
     }
 
     #[allow(unused_must_use)]
@@ -100,3 +184,475 @@ impl Converter for EnvConverter {
         include_str!("env_help.txt").to_string()
     }
 }
+
+/// EnvVarsConverter lets ucg choose the options of the env conversion. It
+/// expects a tuple with the variables in a `vars` field and optional `sep`,
+/// `prefix` and `export` fields.
+pub struct EnvVarsConverter {}
+
+impl EnvVarsConverter {
+    fn error<S: Into<String>>(msg: S) -> ConvertResult {
+        Err(BuildError::new(msg, ErrorType::ConvertError).to_boxed())
+    }
+
+    fn write(&self, v: &Val, w: &mut dyn IOWrite) -> ConvertResult {
+        let flds = match v {
+            Val::Tuple(flds) => flds,
+            _ => return Self::error("Envvars conversions expect a tuple"),
+        };
+        let mut vars = None;
+        // Anything that can't be a variable is an error since the options
+        // were chosen on purpose.
+        let mut conv = EnvConverter::new().with_strict(true);
+        for (name, val) in flds.iter() {
+            conv = match (name.as_str(), val.as_ref()) {
+                ("vars", Val::Tuple(_)) => {
+                    vars = Some(val.clone());
+                    conv
+                }
+                ("sep", Val::Str(s)) => conv.with_sep(s.as_str()),
+                ("prefix", Val::Str(s)) => conv.with_prefix(s.as_str()),
+                ("export", Val::Boolean(b)) => conv.with_export(*b),
+                ("vars", _) => return Self::error("The vars field must be a tuple"),
+                ("sep", _) | ("prefix", _) => {
+                    return Self::error(format!("The {} field must be a string", name));
+                }
+                ("export", _) => return Self::error("The export field must be a boolean"),
+                _ => return Self::error(format!("Unexpected field {} in envvars tuple", name)),
+            };
+        }
+        match vars {
+            Some(vars) => conv.write(&vars, w),
+            None => Self::error("Envvars tuples must have a vars field"),
+        }
+    }
+}
+
+impl Converter for EnvVarsConverter {
+    fn convert(&self, v: Rc<Val>, mut w: &mut dyn IOWrite) -> ConvertResult {
+        self.write(&v, &mut w)
+    }
+
+    fn file_ext(&self) -> String {
+        String::from("env")
+    }
+
+    fn description(&self) -> String {
+        "Convert ucg Vals into environment variables with a chosen prefix and separator."
+            .to_string()
+    }
+
+    #[allow(unused_must_use)]
+    fn help(&self) -> String {
+        include_str!("env_help.txt").to_string()
+    }
+}
+
+type ParseResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Parses the lines of a dotenv file.
+struct DotenvParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl DotenvParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn error<T, S: Into<String>>(&self, msg: S) -> ParseResult<T> {
+        let consumed = &self.chars[..self.pos.min(self.chars.len())];
+        let line = consumed.iter().filter(|c| **c == '\n').count() + 1;
+        Err(BuildError::new(
+            format!("{} at line: {}", msg.into(), line),
+            ErrorType::ParseError,
+        )
+        .to_boxed())
+    }
+
+    fn skip_blanks(&mut self) {
+        while self.peek().map(|c| c == ' ' || c == '\t').unwrap_or(false) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Only whitespace or a comment may follow a value.
+    fn end_of_line(&mut self) -> ParseResult<()> {
+        self.skip_blanks();
+        match self.peek() {
+            None | Some('#') | Some('\n') | Some('\r') => {
+                self.skip_line();
+                Ok(())
+            }
+            _ => self.error("Unexpected text after the value"),
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .map(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+            .unwrap_or(false)
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn quoted(&mut self, quote: char) -> ParseResult<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut val = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == quote {
+                return Ok(val);
+            }
+            // Single quoted values are taken literally.
+            if c != '\\' || quote == '\'' {
+                val.push(c);
+                continue;
+            }
+            match self.peek() {
+                Some(c) => {
+                    self.pos += 1;
+                    match c {
+                        'n' => val.push('\n'),
+                        'r' => val.push('\r'),
+                        't' => val.push('\t'),
+                        '"' | '\\' | '$' | '`' => val.push(c),
+                        c => {
+                            val.push('\\');
+                            val.push(c);
+                        }
+                    }
+                }
+                None => break,
+            }
+        }
+        self.pos = start;
+        self.error("Unterminated quoted value")
+    }
+
+    fn unquoted(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            // A comment has to be separated from the value by whitespace.
+            if c == '#' && self.pos > 0 && self.chars[self.pos - 1].is_whitespace() {
+                break;
+            }
+            self.pos += 1;
+        }
+        let val: String = self.chars[start..self.pos].iter().collect();
+        val.trim_end().to_string()
+    }
+
+    fn parse(&mut self) -> ParseResult<Vec<(String, Rc<Val>)>> {
+        let mut flds: Vec<(String, Rc<Val>)> = Vec::new();
+        loop {
+            while self.peek().map(char::is_whitespace).unwrap_or(false) {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None => break,
+                Some('#') => {
+                    self.skip_line();
+                    continue;
+                }
+                _ => (),
+            }
+            let mut name = match self.name() {
+                Some(name) => name,
+                None => return self.error("Expected a variable name"),
+            };
+            if name == "export" && self.peek().map(|c| c == ' ' || c == '\t').unwrap_or(false) {
+                self.skip_blanks();
+                name = match self.name() {
+                    Some(name) => name,
+                    None => return self.error("Expected a variable name after export"),
+                };
+            }
+            self.skip_blanks();
+            if self.peek() != Some('=') {
+                return self.error(format!("Expected = after {}", name));
+            }
+            self.pos += 1;
+            self.skip_blanks();
+            let val = match self.peek() {
+                Some(q) if q == '\'' || q == '"' => {
+                    let val = self.quoted(q)?;
+                    self.end_of_line()?;
+                    val
+                }
+                _ => self.unquoted(),
+            };
+            let val = Rc::new(Val::Str(val));
+            // The last value for a variable wins.
+            match flds.iter_mut().find(|(n, _)| *n == name) {
+                Some(fld) => fld.1 = val,
+                None => flds.push((name, val)),
+            }
+        }
+        Ok(flds)
+    }
+}
+
+impl Importer for EnvConverter {
+    fn import(&self, bytes: &[u8]) -> ImportResult {
+        let mut parser = DotenvParser {
+            chars: std::str::from_utf8(bytes)?.chars().collect(),
+            pos: 0,
+        };
+        Ok(Rc::new(Val::Tuple(parser.parse()?)))
+    }
+}
+
+#[cfg(test)]
+mod env_test {
+    use std::error::Error;
+
+    use super::*;
+    use crate::convert::test_helpers::{convert, str_val};
+    use crate::convert::ConverterRegistry;
+
+    #[test]
+    fn convert_quotes_and_flattens_test() {
+        let vars = convert(
+            &EnvConverter::new(),
+            "let doc = {
+                USER = \"me\",
+                GREETING = \"it's $HOME \\\"quoted\\\" `cmd` \\\\\",
+                DB = {
+                    HOST = \"localhost\",
+                    UNSET = NULL,
+                    POOL = {SIZE = 5},
+                },
+                LATER = true,
+                RATIO = 0.5,
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            r#"USER='me'
+GREETING="it's \$HOME \"quoted\" \`cmd\` \\"
+DB_HOST='localhost'
+DB_POOL_SIZE=5
+LATER=true
+RATIO=0.5
+"#
+        );
+    }
+
+    #[test]
+    fn convert_with_export_test() {
+        let vars = convert(
+            &EnvConverter::new().with_export(true),
+            "let doc = {DB = {HOST = \"localhost\"}, PORT = 80};",
+        )
+        .unwrap();
+        assert_eq!(vars, "export DB_HOST='localhost'\nexport PORT=80\n");
+    }
+
+    #[test]
+    fn convert_with_prefix_and_sep_test() {
+        let vars = convert(
+            &EnvConverter::new().with_prefix("APP").with_sep("__"),
+            "let doc = {DB = {HOST = \"localhost\"}, PORT = 80};",
+        )
+        .unwrap();
+        assert_eq!(vars, "APP__DB__HOST='localhost'\nAPP__PORT=80\n");
+    }
+
+    #[test]
+    fn convert_envvars_options_test() {
+        let registry = ConverterRegistry::make_registry();
+        let envvars = registry.get_converter("envvars").unwrap();
+        let vars = convert(
+            envvars,
+            "let doc = {
+                vars = {DB = {HOST = \"localhost\"}, PORT = 80},
+                sep = \"__\",
+                prefix = \"APP\",
+                export = true,
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            "export APP__DB__HOST='localhost'\nexport APP__PORT=80\n"
+        );
+        // Without options it converts the vars like the dotenv converter.
+        let vars = convert(envvars, "let doc = {vars = {DB = {HOST = \"a\"}}};").unwrap();
+        assert_eq!(vars, "DB_HOST='a'\n");
+    }
+
+    #[test]
+    fn convert_envvars_errors_test() {
+        let conv = EnvVarsConverter {};
+        let assert_err = |input: &str, msg: &str| {
+            let err = convert(&conv, input).unwrap_err();
+            assert_eq!(err.to_string(), msg);
+        };
+        assert_err(
+            "let doc = {sep = \"_\"};",
+            "ConvertError: Envvars tuples must have a vars field",
+        );
+        assert_err(
+            "let doc = {vars = {}, sep = 1};",
+            "ConvertError: The sep field must be a string",
+        );
+        assert_err(
+            "let doc = {vars = {}, seperator = \"_\"};",
+            "ConvertError: Unexpected field seperator in envvars tuple",
+        );
+        assert_err(
+            "let doc = {vars = {A = [1]}, sep = \"__\"};",
+            "ConvertError: Env variables can't be lists but A is a list",
+        );
+        assert_err(
+            "let doc = [];",
+            "ConvertError: Envvars conversions expect a tuple",
+        );
+    }
+
+    // The env symbol is a tuple by the time it's converted so we build the
+    // Env value by hand.
+    fn env_doc() -> Rc<Val> {
+        Rc::new(Val::Tuple(vec![(
+            "E".to_string(),
+            Rc::new(Val::Env(vec![("HOME".to_string(), "/".to_string())])),
+        )]))
+    }
+
+    #[test]
+    fn convert_skips_what_cant_be_variables_test() {
+        let vars = convert(
+            &EnvConverter::new(),
+            "let doc = {A = {B = [1], C = 1}, \"not-valid\" = 1};",
+        )
+        .unwrap();
+        assert_eq!(vars, "A_C=1\n");
+        let mut buf: Vec<u8> = Vec::new();
+        EnvConverter::new().convert(env_doc(), &mut buf).unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn convert_dotenv_errors_test() {
+        let registry = ConverterRegistry::make_registry();
+        let dotenv = registry.get_converter("dotenv").unwrap();
+        let err = convert(dotenv, "let doc = {A = {B = [1]}};").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: Env variables can't be lists but A_B is a list"
+        );
+        let err = convert(dotenv, "let doc = {\"not-valid\" = 1};").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: Invalid environment variable name \"not-valid\""
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        let err = dotenv.convert(env_doc(), &mut buf).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: Env variables can't be env but E is env"
+        );
+        assert!(convert(&EnvConverter::new(), "let doc = \"a\";").is_err());
+    }
+
+    fn import(input: &str) -> Result<Rc<Val>, Box<dyn Error>> {
+        EnvConverter::new().import(input.as_bytes())
+    }
+
+    #[test]
+    fn import_test() {
+        let val = import(
+            "# A comment
+USER=me
+export HOST = localhost # the host
+URL=http://host/#anchor
+SINGLE='literal $HOME \\n'
+DOUBLE=\"line\\none \\\"quoted\\\" \\$HOME\"  # trailing
+MULTI=\"first
+second\"
+EMPTY=
+COMMENTED= # not a value
+USER=you
+",
+        )
+        .unwrap();
+        assert_eq!(
+            val,
+            Rc::new(Val::Tuple(vec![
+                ("USER".to_string(), str_val("you")),
+                ("HOST".to_string(), str_val("localhost")),
+                ("URL".to_string(), str_val("http://host/#anchor")),
+                ("SINGLE".to_string(), str_val("literal $HOME \\n")),
+                ("DOUBLE".to_string(), str_val("line\none \"quoted\" $HOME")),
+                ("MULTI".to_string(), str_val("first\nsecond")),
+                ("EMPTY".to_string(), str_val("")),
+                ("COMMENTED".to_string(), str_val("")),
+            ]))
+        );
+    }
+
+    #[test]
+    fn import_errors_test() {
+        let err = import("A=1\nB=\"open\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Unterminated quoted value at line: 2"
+        );
+        let err = import("A=1\n\nB 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "ParseError: Expected = after B at line: 3");
+        let err = import("A='x' y\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Unexpected text after the value at line: 1"
+        );
+        let err = import("=1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Expected a variable name at line: 1"
+        );
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let vars = convert(
+            &EnvConverter::new().with_export(true),
+            "let doc = {
+                PLAIN = \"it is $HOME\",
+                QUOTE = \"it's \\\"$HOME\\\" \\\\n\",
+                DB = {PORT = 5432},
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            import(&vars).unwrap(),
+            Rc::new(Val::Tuple(vec![
+                ("PLAIN".to_string(), str_val("it is $HOME")),
+                ("QUOTE".to_string(), str_val("it's \"$HOME\" \\n")),
+                ("DB_PORT".to_string(), str_val("5432")),
+            ]))
+        );
+    }
+}
@@ -0,0 +1,944 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Contains code for converting a UCG Val to and from HCL configuration.
+use std;
+use std::error::Error;
+use std::io::Write;
+use std::rc::Rc;
+
+use super::traits::{ConvertResult, Converter, ImportResult, Importer};
+use crate::build::Val;
+use crate::error::BuildError;
+use crate::error::ErrorType;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// HclConverter converts ucg tuples into HCL documents using a declarative DSL
+/// of attributes and blocks.
+pub struct HclConverter {}
+
+impl HclConverter {
+    fn get_str_val(v: &Val) -> Result<&str> {
+        if let Val::Str(ref s) = v {
+            Ok(s)
+        } else {
+            Err(BuildError::new("Not a String value", ErrorType::TypeFail).to_boxed())
+        }
+    }
+
+    fn get_tuple_val(v: &Val) -> Result<&Vec<(String, Rc<Val>)>> {
+        if let Val::Tuple(ref fs) = v {
+            Ok(fs)
+        } else {
+            Err(BuildError::new("Not a tuple value", ErrorType::TypeFail).to_boxed())
+        }
+    }
+
+    fn get_list_val(v: &Val) -> Result<&Vec<Rc<Val>>> {
+        if let Val::List(ref fs) = v {
+            Ok(fs)
+        } else {
+            Err(BuildError::new("Not a List value", ErrorType::TypeFail).to_boxed())
+        }
+    }
+
+    fn is_identifier(s: &str) -> bool {
+        let mut chars = s.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+            _ => return false,
+        }
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    fn get_identifier(s: &str, what: &str) -> Result<String> {
+        if Self::is_identifier(s) {
+            Ok(s.to_string())
+        } else {
+            Err(BuildError::new(
+                format!("Invalid HCL {} {:?}. Must be a valid identifier", what, s),
+                ErrorType::ConvertError,
+            )
+            .to_boxed())
+        }
+    }
+
+    fn quote(s: &str) -> String {
+        let mut quoted = String::with_capacity(s.len() + 2);
+        quoted.push('"');
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                // Escape template sequences so strings are always literal.
+                '$' | '%' if chars.peek() == Some(&'{') => {
+                    quoted.push(c);
+                    quoted.push(c);
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn object_key(k: &str) -> String {
+        if Self::is_identifier(k) {
+            k.to_string()
+        } else {
+            Self::quote(k)
+        }
+    }
+
+    fn write_indent(indent: usize, buf: &mut String) {
+        for _ in 0..indent {
+            buf.push_str("  ");
+        }
+    }
+
+    fn write_pairs(
+        &self,
+        pairs: Vec<(String, &Val)>,
+        indent: usize,
+        buf: &mut String,
+    ) -> ConvertResult {
+        for (k, v) in pairs {
+            Self::write_indent(indent, buf);
+            buf.push_str(&k);
+            buf.push_str(" = ");
+            self.write_value(v, indent, buf)?;
+            buf.push('\n');
+        }
+        Ok(())
+    }
+
+    fn write_value(&self, v: &Val, indent: usize, buf: &mut String) -> ConvertResult {
+        match v {
+            Val::Empty => buf.push_str("null"),
+            Val::Boolean(b) => buf.push_str(if *b { "true" } else { "false" }),
+            Val::Int(i) => buf.push_str(&i.to_string()),
+            Val::Float(f) if f.is_finite() => buf.push_str(&f.to_string()),
+            Val::Float(f) => {
+                return Err(BuildError::new(
+                    format!("HCL can't represent the number {}", f),
+                    ErrorType::ConvertError,
+                )
+                .to_boxed());
+            }
+            Val::Str(s) => buf.push_str(&Self::quote(s)),
+            Val::List(items) => {
+                if items.is_empty() {
+                    buf.push_str("[]");
+                    return Ok(());
+                }
+                buf.push_str("[\n");
+                for item in items.iter() {
+                    Self::write_indent(indent + 1, buf);
+                    self.write_value(item, indent + 1, buf)?;
+                    buf.push_str(",\n");
+                }
+                Self::write_indent(indent, buf);
+                buf.push(']');
+            }
+            Val::Tuple(flds) => {
+                if flds.is_empty() {
+                    buf.push_str("{}");
+                    return Ok(());
+                }
+                buf.push_str("{\n");
+                let pairs = flds
+                    .iter()
+                    .map(|(k, v)| (Self::object_key(k), v.as_ref()))
+                    .collect();
+                self.write_pairs(pairs, indent + 1, buf)?;
+                Self::write_indent(indent, buf);
+                buf.push('}');
+            }
+            Val::Env(flds) => {
+                let flds = flds
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Rc::new(Val::Str(v.clone()))))
+                    .collect();
+                self.write_value(&Val::Tuple(flds), indent, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the attrs and blocks of a body tuple.
+    fn write_body(
+        &self,
+        v: &Val,
+        indent: usize,
+        is_block: bool,
+        buf: &mut String,
+    ) -> ConvertResult {
+        let mut attrs: Option<&Vec<(String, Rc<Val>)>> = None;
+        let mut blocks: Option<&Vec<Rc<Val>>> = None;
+        for (ref field, ref val) in Self::get_tuple_val(v)?.iter() {
+            if val.is_empty() {
+                continue;
+            }
+            match field.as_str() {
+                "attrs" => attrs = Some(Self::get_tuple_val(val)?),
+                "blocks" => blocks = Some(Self::get_list_val(val)?),
+                // These are handled by write_block.
+                "type" | "labels" if is_block => (),
+                _ => {
+                    return Err(BuildError::new(
+                        format!("Unknown field {} in HCL body", field),
+                        ErrorType::ConvertError,
+                    )
+                    .to_boxed());
+                }
+            }
+        }
+        if let Some(attrs) = attrs {
+            let mut pairs = Vec::with_capacity(attrs.len());
+            for (k, v) in attrs.iter() {
+                pairs.push((Self::get_identifier(k, "attribute name")?, v.as_ref()));
+            }
+            self.write_pairs(pairs, indent, buf)?;
+        }
+        if let Some(blocks) = blocks {
+            for (i, block) in blocks.iter().enumerate() {
+                // Blocks are separated from each other and from any attributes
+                // by an empty line.
+                if i > 0 || attrs.map(|a| !a.is_empty()).unwrap_or(false) {
+                    buf.push('\n');
+                }
+                self.write_block(block, indent, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_block(&self, v: &Val, indent: usize, buf: &mut String) -> ConvertResult {
+        let mut typ: Option<&str> = None;
+        let mut labels: Option<&Vec<Rc<Val>>> = None;
+        let mut has_body = false;
+        for (ref field, ref val) in Self::get_tuple_val(v)?.iter() {
+            if val.is_empty() {
+                continue;
+            }
+            match field.as_str() {
+                "type" => typ = Some(Self::get_str_val(val)?),
+                "labels" => labels = Some(Self::get_list_val(val)?),
+                "attrs" => has_body = !Self::get_tuple_val(val)?.is_empty() || has_body,
+                "blocks" => has_body = !Self::get_list_val(val)?.is_empty() || has_body,
+                _ => (),
+            }
+        }
+        let typ = match typ {
+            Some(typ) => Self::get_identifier(typ, "block type")?,
+            None => {
+                return Err(BuildError::new(
+                    "HCL blocks must have a type field",
+                    ErrorType::ConvertError,
+                )
+                .to_boxed());
+            }
+        };
+        Self::write_indent(indent, buf);
+        buf.push_str(&typ);
+        if let Some(labels) = labels {
+            for l in labels.iter() {
+                buf.push(' ');
+                buf.push_str(&Self::quote(Self::get_str_val(l)?));
+            }
+        }
+        if !has_body {
+            buf.push_str(" {}\n");
+            return Ok(());
+        }
+        buf.push_str(" {\n");
+        self.write_body(v, indent + 1, true, buf)?;
+        Self::write_indent(indent, buf);
+        buf.push_str("}\n");
+        Ok(())
+    }
+}
+
+impl Converter for HclConverter {
+    fn convert(&self, v: Rc<Val>, w: &mut dyn Write) -> ConvertResult {
+        let mut buf = String::new();
+        self.write_body(&v, 0, false, &mut buf)?;
+        w.write_all(buf.as_bytes())?;
+        Ok(())
+    }
+
+    fn file_ext(&self) -> String {
+        String::from("tf")
+    }
+
+    fn description(&self) -> String {
+        "Convert ucg tuples into HCL configuration.".to_string()
+    }
+
+    #[allow(unused_must_use)]
+    fn help(&self) -> String {
+        include_str!("hcl_help.txt").to_string()
+    }
+}
+
+impl Importer for HclConverter {
+    fn import(&self, bytes: &[u8]) -> ImportResult {
+        let src = String::from_utf8(bytes.to_vec())?;
+        let mut p = HclParser {
+            chars: src.chars().collect(),
+            pos: 0,
+        };
+        Ok(Rc::new(p.body(None)?))
+    }
+}
+
+/// A parser for the subset of HCL that can be represented as ucg values.
+///
+/// Literal values are imported as their ucg equivalents. Any other
+/// expression, like a reference or a function call, is imported as a string
+/// containing the source of the expression.
+struct HclParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl HclParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn error<T, S: Into<String>>(&self, msg: S) -> Result<T> {
+        let consumed = &self.chars[..self.pos.min(self.chars.len())];
+        let line = consumed.iter().filter(|c| **c == '\n').count() + 1;
+        let column = consumed.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        Err(BuildError::new(
+            format!("{} at line: {} column: {}", msg.into(), line, column),
+            ErrorType::ParseError,
+        )
+        .to_boxed())
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(format!("Expected {:?}", c))
+        }
+    }
+
+    fn at_comment(&self) -> bool {
+        matches!(
+            (self.peek(), self.peek_at(1)),
+            (Some('#'), _) | (Some('/'), Some('/')) | (Some('/'), Some('*'))
+        )
+    }
+
+    fn skip_comment(&mut self) -> Result<()> {
+        if self.peek() == Some('/') && self.peek_at(1) == Some('*') {
+            self.pos += 2;
+            while !(self.peek() == Some('*') && self.peek_at(1) == Some('/')) {
+                if self.peek().is_none() {
+                    return self.error("Unterminated comment");
+                }
+                self.pos += 1;
+            }
+            self.pos += 2;
+        } else {
+            // Line comments run up to but not including the newline.
+            while self.peek().map(|c| c != '\n').unwrap_or(false) {
+                self.pos += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips whitespace and comments. Newlines are only skipped if asked to.
+    fn skip_ws(&mut self, newlines: bool) -> Result<()> {
+        loop {
+            match self.peek() {
+                Some('\n') if !newlines => return Ok(()),
+                Some(c) if c.is_whitespace() => self.pos += 1,
+                Some(_) if self.at_comment() => self.skip_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(c) if c.is_alphabetic() || c == '_' => (),
+            _ => return None,
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .map(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            .unwrap_or(false)
+        {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Parses the attributes and blocks of a body up to the closing char.
+    fn body(&mut self, close: Option<char>) -> Result<Val> {
+        let mut attrs = Vec::new();
+        let mut blocks = Vec::new();
+        loop {
+            self.skip_ws(true)?;
+            if self.peek() == close {
+                if close.is_some() {
+                    self.pos += 1;
+                }
+                break;
+            }
+            let name = match self.identifier() {
+                Some(name) => name,
+                None if self.peek().is_none() => return self.error("Unexpected end of input"),
+                None => return self.error("Expected an attribute or block"),
+            };
+            self.skip_ws(false)?;
+            if self.peek() == Some('=') && self.peek_at(1) != Some('=') {
+                self.pos += 1;
+                self.skip_ws(false)?;
+                let val = self.expression()?;
+                attrs.push((name, Rc::new(val)));
+                self.skip_ws(false)?;
+                match self.peek() {
+                    Some('\n') | None => (),
+                    Some(c) if Some(c) == close => (),
+                    _ => return self.error("Expected a newline after the attribute"),
+                }
+            } else {
+                blocks.push(Rc::new(self.block(name)?));
+            }
+        }
+        Ok(Val::Tuple(vec![
+            ("attrs".to_string(), Rc::new(Val::Tuple(attrs))),
+            ("blocks".to_string(), Rc::new(Val::List(blocks))),
+        ]))
+    }
+
+    fn block(&mut self, typ: String) -> Result<Val> {
+        let mut labels = Vec::new();
+        loop {
+            match self.peek() {
+                Some('{') => break,
+                Some('"') => labels.push(Rc::new(Val::Str(self.string()?))),
+                _ => match self.identifier() {
+                    Some(label) => labels.push(Rc::new(Val::Str(label))),
+                    None => return self.error("Expected a block label or {"),
+                },
+            }
+            self.skip_ws(false)?;
+        }
+        self.expect('{')?;
+        let body = self.body(Some('}'))?;
+        let mut flds = vec![
+            ("type".to_string(), Rc::new(Val::Str(typ))),
+            ("labels".to_string(), Rc::new(Val::List(labels))),
+        ];
+        if let Val::Tuple(body_flds) = body {
+            flds.extend(body_flds);
+        }
+        Ok(Val::Tuple(flds))
+    }
+
+    /// Returns true if the parser is at the end of an expression.
+    fn at_expression_end(&mut self) -> Result<bool> {
+        let start = self.pos;
+        self.skip_ws(false)?;
+        let end = matches!(
+            self.peek(),
+            None | Some('\n') | Some(',') | Some(']') | Some('}') | Some(')')
+        );
+        self.pos = start;
+        Ok(end)
+    }
+
+    fn expression(&mut self) -> Result<Val> {
+        let start = self.pos;
+        let val = match self.peek() {
+            Some('"') => Some(Val::Str(self.string()?)),
+            Some('[') => Some(self.list()?),
+            Some('{') => Some(self.object()?),
+            Some('<') if self.peek_at(1) == Some('<') => Some(Val::Str(self.heredoc()?)),
+            Some(c) if c.is_ascii_digit() || c == '-' => self.number(),
+            Some(_) => match self.identifier().as_deref() {
+                Some("true") => Some(Val::Boolean(true)),
+                Some("false") => Some(Val::Boolean(false)),
+                Some("null") => Some(Val::Empty),
+                _ => None,
+            },
+            None => return self.error("Expected an expression"),
+        };
+        match val {
+            Some(val) if self.at_expression_end()? => Ok(val),
+            _ => {
+                // This isn't a literal value so we import the source of the
+                // expression instead.
+                self.pos = start;
+                self.raw_expression()
+            }
+        }
+    }
+
+    fn raw_expression(&mut self) -> Result<Val> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None => break,
+                Some('"') => {
+                    self.string()?;
+                    continue;
+                }
+                Some('(') | Some('[') | Some('{') => depth += 1,
+                Some(')') | Some(']') | Some('}') if depth == 0 => break,
+                Some(')') | Some(']') | Some('}') => depth -= 1,
+                Some('\n') | Some(',') if depth == 0 => break,
+                Some(_) if depth == 0 && self.at_comment() => break,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return self.error("Expected an expression");
+        }
+        Ok(Val::Str(raw.to_string()))
+    }
+
+    fn number(&mut self) -> Option<Val> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        let mut is_float = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => (),
+                '.' | 'e' | 'E' => is_float = true,
+                '+' | '-' if is_float => (),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let num: String = self.chars[start..self.pos].iter().collect();
+        if is_float {
+            num.parse().ok().map(Val::Float)
+        } else {
+            num.parse().ok().map(Val::Int)
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        // Template sequences are kept as is so we track their nesting to find
+        // the real end of the string.
+        let mut depth = 0;
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return self.error("Unterminated string"),
+                Some(c) => c,
+            };
+            self.pos += 1;
+            match c {
+                '"' if depth == 0 => break,
+                '\\' => {
+                    let escaped = match self.peek() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(u @ 'u') | Some(u @ 'U') => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let hex: String = self.chars
+                                [self.pos + 1..(self.pos + 1 + len).min(self.chars.len())]
+                                .iter()
+                                .collect();
+                            match u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(std::char::from_u32)
+                            {
+                                Some(c) if hex.len() == len => {
+                                    self.pos += len;
+                                    c
+                                }
+                                _ => return self.error("Invalid unicode escape"),
+                            }
+                        }
+                        _ => return self.error("Invalid escape sequence"),
+                    };
+                    self.pos += 1;
+                    s.push(escaped);
+                }
+                // $${ and %%{ are escaped template sequences for a literal
+                // ${ and %{.
+                '$' | '%' if self.peek() == Some(c) && self.peek_at(1) == Some('{') => {
+                    self.pos += 2;
+                    s.push(c);
+                    s.push('{');
+                }
+                '$' | '%' if self.peek() == Some('{') => {
+                    depth += 1;
+                    self.pos += 1;
+                    s.push(c);
+                    s.push('{');
+                }
+                '}' if depth > 0 => {
+                    depth -= 1;
+                    s.push(c);
+                }
+                c => s.push(c),
+            }
+        }
+        Ok(s)
+    }
+
+    fn heredoc(&mut self) -> Result<String> {
+        self.pos += 2;
+        let indented = self.peek() == Some('-');
+        if indented {
+            self.pos += 1;
+        }
+        let marker = match self.identifier() {
+            Some(marker) => marker,
+            None => return self.error("Expected a heredoc marker"),
+        };
+        self.skip_ws(false)?;
+        self.expect('\n')?;
+        let mut lines = Vec::new();
+        loop {
+            if self.peek().is_none() {
+                return self.error(format!("Unterminated heredoc {}", marker));
+            }
+            let start = self.pos;
+            while self.peek().map(|c| c != '\n').unwrap_or(false) {
+                self.pos += 1;
+            }
+            let line: String = self.chars[start..self.pos].iter().collect();
+            if line.trim() == marker {
+                break;
+            }
+            lines.push(line);
+            // consume the newline
+            self.pos += 1;
+        }
+        if indented {
+            let indent = lines
+                .iter()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+                .min()
+                .unwrap_or(0);
+            for l in lines.iter_mut() {
+                *l = l.chars().skip(indent).collect();
+            }
+        }
+        let mut s = lines.join("\n");
+        s.push('\n');
+        Ok(s)
+    }
+
+    fn list(&mut self) -> Result<Val> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws(true)?;
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                break;
+            }
+            items.push(Rc::new(self.expression()?));
+            self.skip_ws(true)?;
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => (),
+                _ => return self.error("Expected , or ]"),
+            }
+        }
+        Ok(Val::List(items))
+    }
+
+    fn object(&mut self) -> Result<Val> {
+        self.expect('{')?;
+        let mut flds = Vec::new();
+        loop {
+            self.skip_ws(true)?;
+            if self.peek() == Some('}') {
+                self.pos += 1;
+                break;
+            }
+            let key = if self.peek() == Some('"') {
+                self.string()?
+            } else {
+                match self.identifier() {
+                    Some(key) => key,
+                    None => return self.error("Expected an object key"),
+                }
+            };
+            self.skip_ws(false)?;
+            match self.peek() {
+                Some('=') | Some(':') => self.pos += 1,
+                _ => return self.error("Expected = or :"),
+            }
+            self.skip_ws(false)?;
+            flds.push((key, Rc::new(self.expression()?)));
+            self.skip_ws(false)?;
+            match self.peek() {
+                Some(',') | Some('\n') => self.pos += 1,
+                Some('}') => (),
+                _ => return self.error("Expected , or a newline"),
+            }
+        }
+        Ok(Val::Tuple(flds))
+    }
+}
+
+#[cfg(test)]
+mod hcl_test {
+    use super::*;
+    use crate::convert::test_helpers::{convert, eval};
+
+    #[test]
+    fn convert_labeled_blocks_test() {
+        let hcl = convert(
+            &HclConverter {},
+            "let doc = {
+                attrs = {
+                    region = \"us-east-1\",
+                },
+                blocks = [
+                    {
+                        type = \"resource\",
+                        labels = [\"aws_instance\", \"web\"],
+                        attrs = {
+                            ami = \"ami-123\",
+                            count = 2,
+                            monitoring = true,
+                            tags = {Name = \"web\", \"app.tier\" = \"frontend\"},
+                            security_groups = [\"default\"],
+                            user_data = NULL,
+                        },
+                        blocks = [
+                            {type = \"lifecycle\", attrs = {create_before_destroy = true}},
+                        ],
+                    },
+                    {type = \"terraform\"},
+                ],
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            hcl,
+            "region = \"us-east-1\"
+
+resource \"aws_instance\" \"web\" {
+  ami = \"ami-123\"
+  count = 2
+  monitoring = true
+  tags = {
+    Name = \"web\"
+    \"app.tier\" = \"frontend\"
+  }
+  security_groups = [
+    \"default\",
+  ]
+  user_data = null
+
+  lifecycle {
+    create_before_destroy = true
+  }
+}
+
+terraform {}
+"
+        );
+    }
+
+    #[test]
+    fn convert_escapes_strings_test() {
+        let hcl = convert(
+            &HclConverter {},
+            "let doc = {attrs = {cmd = \"echo \\\"${HOME}\\\" %{x}\"}};",
+        )
+        .unwrap();
+        assert_eq!(hcl, "cmd = \"echo \\\"$${HOME}\\\" %%{x}\"\n");
+    }
+
+    #[test]
+    fn convert_invalid_documents_test() {
+        assert!(convert(&HclConverter {}, "let doc = {attrs = {\"not valid\" = 1}};").is_err());
+        assert!(convert(
+            &HclConverter {},
+            "let doc = {blocks = [{labels = [\"no_type\"]}]};"
+        )
+        .is_err());
+        assert!(convert(&HclConverter {}, "let doc = {region = \"us-east-1\"};").is_err());
+        assert!(convert(&HclConverter {}, "let doc = [1, 2];").is_err());
+        for f in [std::f64::NAN, std::f64::INFINITY, std::f64::NEG_INFINITY].iter() {
+            let doc = Val::Tuple(vec![(
+                "attrs".to_string(),
+                Rc::new(Val::Tuple(vec![("f".to_string(), Rc::new(Val::Float(*f)))])),
+            )]);
+            let mut buf: Vec<u8> = Vec::new();
+            assert!(HclConverter {}.convert(Rc::new(doc), &mut buf).is_err());
+        }
+    }
+
+    const TF: &str = r#"# Comments are ignored.
+region = "us-east-1" // trailing comments too
+/* block
+   comments */
+replicas = 3
+ratio = 0.5
+enabled = false
+nothing = null
+zone = var.zone
+names = ["a", "b",
+  "c"]
+tags = {
+  Name = "web-${var.env}"
+  "app.tier": "frontend", cost = 1
+}
+script = <<-EOT
+    echo "hi"
+      indented
+    EOT
+
+variable "region" {
+  type = string
+}
+
+resource "aws_instance" "web" {
+  ami = "ami-123"
+
+  lifecycle { create_before_destroy = true }
+  ebs_block_device {}
+}
+"#;
+
+    #[test]
+    fn import_hcl_test() {
+        let imported = HclConverter {}.import(TF.as_bytes()).unwrap();
+        let expected = eval(
+            "let doc = {
+                attrs = {
+                    region = \"us-east-1\",
+                    replicas = 3,
+                    ratio = 0.5,
+                    enabled = false,
+                    nothing = NULL,
+                    zone = \"var.zone\",
+                    names = [\"a\", \"b\", \"c\"],
+                    tags = {
+                        Name = \"web-${var.env}\",
+                        \"app.tier\" = \"frontend\",
+                        cost = 1,
+                    },
+                    script = \"echo \\\"hi\\\"
+  indented
+\",
+                },
+                blocks = [
+                    {
+                        type = \"variable\",
+                        labels = [\"region\"],
+                        attrs = {type = \"string\"},
+                        blocks = [],
+                    },
+                    {
+                        type = \"resource\",
+                        labels = [\"aws_instance\", \"web\"],
+                        attrs = {ami = \"ami-123\"},
+                        blocks = [
+                            {
+                                type = \"lifecycle\",
+                                labels = [],
+                                attrs = {create_before_destroy = true},
+                                blocks = [],
+                            },
+                            {
+                                type = \"ebs_block_device\",
+                                labels = [],
+                                attrs = {},
+                                blocks = [],
+                            },
+                        ],
+                    },
+                ],
+            };",
+        );
+        assert_eq!(imported, expected);
+    }
+
+    #[test]
+    fn import_hcl_round_trip_test() {
+        let hcl = "region = \"us-east-1\"
+tags = {
+  Name = \"web\"
+  literal = \"$${not_a_template} %%{x}\"
+}
+
+resource \"aws_instance\" \"web\" {
+  ami = \"ami-123\"
+
+  lifecycle {
+    create_before_destroy = true
+  }
+}
+";
+        let imported = HclConverter {}.import(hcl.as_bytes()).unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        HclConverter {}.convert(imported, &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), hcl);
+    }
+
+    #[test]
+    fn import_hcl_expressions_become_literal_strings_test() {
+        // Expressions are imported as their source so converting them back
+        // out quotes them and escapes their template sequences.
+        let hcl = "zone = var.zone\nname = \"web-${var.env}\"\n";
+        let imported = HclConverter {}.import(hcl.as_bytes()).unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        HclConverter {}.convert(imported, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "zone = \"var.zone\"\nname = \"web-$${var.env}\"\n"
+        );
+    }
+
+    #[test]
+    fn import_invalid_hcl_test() {
+        let conv = HclConverter {};
+        assert!(conv.import(b"region = \"us-east-1").is_err());
+        assert!(conv.import(b"resource \"a\" {\n  ami = 1\n").is_err());
+        assert!(conv.import(b"= 1\n").is_err());
+        assert!(conv.import(b"s = <<EOT\nnever closed\n").is_err());
+    }
+}
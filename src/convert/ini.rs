@@ -0,0 +1,467 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Contains code for converting a UCG Val to and from INI files.
+use std;
+use std::error::Error;
+use std::io::Write;
+use std::rc::Rc;
+
+use super::traits::{ConvertResult, Converter, ImportResult, Importer};
+use crate::build::Val;
+use crate::error::BuildError;
+use crate::error::ErrorType;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+type Fields = Vec<(String, Rc<Val>)>;
+
+/// IniConverter converts a tuple of tuples into the sections of an INI file.
+pub struct IniConverter {}
+
+impl IniConverter {
+    fn convert_error<T, S: Into<String>>(msg: S) -> Result<T> {
+        Err(BuildError::new(msg, ErrorType::ConvertError).to_boxed())
+    }
+
+    fn check_key(key: &str) -> Result<()> {
+        let valid = !key.is_empty()
+            && key.trim() == key
+            && !key.starts_with([';', '#', '['])
+            && !key.contains(['=', ':', '\n', '\r']);
+        if valid {
+            Ok(())
+        } else {
+            Self::convert_error(format!("Invalid INI key {:?}", key))
+        }
+    }
+
+    fn check_section(name: &str) -> Result<()> {
+        let valid = !name.is_empty() && name.trim() == name && !name.contains([']', '\n', '\r']);
+        if valid {
+            Ok(())
+        } else {
+            Self::convert_error(format!("Invalid INI section name {:?}", name))
+        }
+    }
+
+    /// Strings that wouldn't be read back as they are get double quotes.
+    fn needs_quotes(s: &str) -> bool {
+        s.is_empty()
+            || s.trim() != s
+            || s.starts_with(['"', '\''])
+            || s.contains([';', '#', '\n', '\r'])
+    }
+
+    fn quote(s: &str) -> String {
+        let mut quoted = String::with_capacity(s.len() + 2);
+        quoted.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn write_field(&self, path: &str, name: &str, v: &Val, w: &mut dyn Write) -> ConvertResult {
+        Self::check_key(name)?;
+        match v {
+            Val::Empty => writeln!(w, "{}", name)?,
+            Val::Boolean(b) => writeln!(w, "{} = {}", name, b)?,
+            Val::Int(i) => writeln!(w, "{} = {}", name, i)?,
+            Val::Float(f) => writeln!(w, "{} = {}", name, f)?,
+            Val::Str(s) if Self::needs_quotes(s) => writeln!(w, "{} = {}", name, Self::quote(s))?,
+            Val::Str(s) => writeln!(w, "{} = {}", name, s)?,
+            Val::Tuple(_) => {
+                return Self::convert_error(format!(
+                    "INI sections can't contain tuples but {} is a tuple",
+                    path
+                ));
+            }
+            Val::List(_) => {
+                return Self::convert_error(format!(
+                    "INI values can't be lists but {} is a list",
+                    path
+                ));
+            }
+            Val::Env(_) => {
+                return Self::convert_error(format!("INI values can't be env but {} is env", path));
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, v: &Val, w: &mut dyn Write) -> ConvertResult {
+        let flds = match v {
+            Val::Tuple(flds) => flds,
+            _ => return Self::convert_error("INI conversions expect a tuple"),
+        };
+        // Keys that aren't in a section have to come before the first section.
+        let mut wrote_any = false;
+        for (name, val) in flds.iter() {
+            if !val.is_tuple() {
+                self.write_field(name, name, val, w)?;
+                wrote_any = true;
+            }
+        }
+        for (section, val) in flds.iter() {
+            if let Val::Tuple(ref section_flds) = val.as_ref() {
+                Self::check_section(section)?;
+                if wrote_any {
+                    writeln!(w)?;
+                }
+                writeln!(w, "[{}]", section)?;
+                for (name, val) in section_flds.iter() {
+                    self.write_field(&format!("{}.{}", section, name), name, val, w)?;
+                }
+                wrote_any = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Converter for IniConverter {
+    fn convert(&self, v: Rc<Val>, mut w: &mut dyn Write) -> ConvertResult {
+        self.write(&v, &mut w)
+    }
+
+    fn file_ext(&self) -> String {
+        String::from("ini")
+    }
+
+    fn description(&self) -> String {
+        "Convert ucg tuples into INI files.".to_string()
+    }
+
+    #[allow(unused_must_use)]
+    fn help(&self) -> String {
+        include_str!("ini_help.txt").to_string()
+    }
+}
+
+fn parse_error<T, S: Into<String>>(msg: S, line: usize) -> Result<T> {
+    Err(BuildError::new(
+        format!("{} at line: {}", msg.into(), line),
+        ErrorType::ParseError,
+    )
+    .to_boxed())
+}
+
+/// Replaces the value of a field if it is already set. The last value for a
+/// key wins.
+fn set_field(flds: &mut Fields, name: &str, val: Val) {
+    let val = Rc::new(val);
+    match flds.iter_mut().find(|(n, _)| n == name) {
+        Some(fld) => fld.1 = val,
+        None => flds.push((name.to_string(), val)),
+    }
+}
+
+/// Parses a quoted value and returns it with the rest of the line.
+fn parse_quoted(s: &str, line: usize) -> Result<(String, &str)> {
+    let mut chars = s.char_indices();
+    let quote = match chars.next() {
+        Some((_, c)) => c,
+        None => return parse_error("Expected a quoted value", line),
+    };
+    let mut val = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((val, &s[i + 1..])),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, 'n')) => val.push('\n'),
+                Some((_, 'r')) => val.push('\r'),
+                Some((_, 't')) => val.push('\t'),
+                Some((_, c)) => val.push(c),
+                None => break,
+            },
+            c => val.push(c),
+        }
+    }
+    parse_error("Unterminated quoted value", line)
+}
+
+fn is_comment(s: &str) -> bool {
+    s.starts_with(';') || s.starts_with('#')
+}
+
+fn parse_value(s: &str, line: usize) -> Result<String> {
+    if s.starts_with('"') || s.starts_with('\'') {
+        let (val, rest) = parse_quoted(s, line)?;
+        let rest = rest.trim();
+        if !rest.is_empty() && !is_comment(rest) {
+            return parse_error("Unexpected text after a quoted value", line);
+        }
+        return Ok(val);
+    }
+    // A comment after a value has to be separated from it by whitespace.
+    let mut end = s.len();
+    let mut prev_ws = false;
+    for (i, c) in s.char_indices() {
+        if prev_ws && (c == ';' || c == '#') {
+            end = i;
+            break;
+        }
+        prev_ws = c.is_whitespace();
+    }
+    Ok(s[..end].trim_end().to_string())
+}
+
+impl Importer for IniConverter {
+    fn import(&self, bytes: &[u8]) -> ImportResult {
+        let src = std::str::from_utf8(bytes)?;
+        let mut flds = Vec::new();
+        let mut sections: Vec<(String, Fields)> = Vec::new();
+        let mut current = None;
+        for (i, line) in src.lines().enumerate() {
+            let line_num = i + 1;
+            let line = line.trim();
+            if line.is_empty() || is_comment(line) {
+                continue;
+            }
+            if line.starts_with('[') {
+                let end = match line.find(']') {
+                    Some(end) => end,
+                    None => return parse_error("Expected ] after the section name", line_num),
+                };
+                let rest = line[end + 1..].trim();
+                if !rest.is_empty() && !is_comment(rest) {
+                    return parse_error("Unexpected text after the section name", line_num);
+                }
+                let name = line[1..end].trim();
+                if name.is_empty() {
+                    return parse_error("Expected a section name", line_num);
+                }
+                current = match sections.iter().position(|(n, _)| n == name) {
+                    Some(idx) => Some(idx),
+                    None => {
+                        sections.push((name.to_string(), Vec::new()));
+                        Some(sections.len() - 1)
+                    }
+                };
+                continue;
+            }
+            // A key without a value is NULL.
+            let (key, val) = match line.find(['=', ':']) {
+                Some(idx) => (
+                    line[..idx].trim_end(),
+                    Val::Str(parse_value(line[idx + 1..].trim_start(), line_num)?),
+                ),
+                None => (line, Val::Empty),
+            };
+            if key.is_empty() {
+                return parse_error("Expected a key", line_num);
+            }
+            match current {
+                Some(idx) => set_field(&mut sections[idx].1, key, val),
+                None => set_field(&mut flds, key, val),
+            }
+        }
+        for (name, section_flds) in sections {
+            set_field(&mut flds, &name, Val::Tuple(section_flds));
+        }
+        Ok(Rc::new(Val::Tuple(flds)))
+    }
+}
+
+#[cfg(test)]
+mod ini_test {
+    use super::*;
+    use crate::convert::test_helpers::{convert, str_val};
+
+    fn import(input: &str) -> Result<Rc<Val>> {
+        IniConverter {}.import(input.as_bytes())
+    }
+
+    #[test]
+    fn convert_sections_test() {
+        let ini = convert(
+            &IniConverter {},
+            "let doc = {
+                program = {
+                    command = \"/usr/bin/app --port 80\",
+                    autostart = true,
+                    retries = 3,
+                    backoff = 1.5,
+                    comment = \"a ; b\",
+                    padded = \" x \",
+                    empty = \"\",
+                    quoted = \"\\\"q\\\"\",
+                    flag = NULL,
+                },
+                verbose = false,
+                empty_section = {},
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            ini,
+            r#"verbose = false
+
+[program]
+command = /usr/bin/app --port 80
+autostart = true
+retries = 3
+backoff = 1.5
+comment = "a ; b"
+padded = " x "
+empty = ""
+quoted = "\"q\""
+flag
+
+[empty_section]
+"#
+        );
+    }
+
+    #[test]
+    fn convert_nested_tuple_is_an_error_test() {
+        let err = convert(&IniConverter {}, "let doc = {a = {b = {c = 1}}};").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: INI sections can't contain tuples but a.b is a tuple"
+        );
+    }
+
+    #[test]
+    fn convert_list_is_an_error_test() {
+        let err = convert(&IniConverter {}, "let doc = {a = {b = [1]}};").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: INI values can't be lists but a.b is a list"
+        );
+        let err = convert(&IniConverter {}, "let doc = {b = [1]};").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ConvertError: INI values can't be lists but b is a list"
+        );
+    }
+
+    #[test]
+    fn convert_invalid_names_test() {
+        assert!(convert(&IniConverter {}, "let doc = [1];").is_err());
+        assert!(convert(&IniConverter {}, "let doc = {\"a=b\" = 1};").is_err());
+        assert!(convert(&IniConverter {}, "let doc = {\"a]\" = {}};").is_err());
+    }
+
+    #[test]
+    fn import_test() {
+        let val = import(
+            "; a comment
+name = top
+
+[program:app]
+# another comment
+command = /usr/bin/app --port 80 ; the command
+url: http://localhost:8080/#frag
+quoted = \"a ; b\\n\"  ; after
+single = 'c:\\path'
+empty =
+flag
+
+[ other ]
+x = 1
+x = 2
+[program:app]
+extra = yes
+",
+        )
+        .unwrap();
+        assert_eq!(
+            val,
+            Rc::new(Val::Tuple(vec![
+                ("name".to_string(), str_val("top")),
+                (
+                    "program:app".to_string(),
+                    Rc::new(Val::Tuple(vec![
+                        ("command".to_string(), str_val("/usr/bin/app --port 80")),
+                        ("url".to_string(), str_val("http://localhost:8080/#frag")),
+                        ("quoted".to_string(), str_val("a ; b\n")),
+                        ("single".to_string(), str_val("c:\\path")),
+                        ("empty".to_string(), str_val("")),
+                        ("flag".to_string(), Rc::new(Val::Empty)),
+                        ("extra".to_string(), str_val("yes")),
+                    ]))
+                ),
+                (
+                    "other".to_string(),
+                    Rc::new(Val::Tuple(vec![("x".to_string(), str_val("2"))]))
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn import_errors_test() {
+        let err = import("a = 1\n[broken\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Expected ] after the section name at line: 2"
+        );
+        let err = import("a = \"open\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Unterminated quoted value at line: 1"
+        );
+        let err = import("= 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "ParseError: Expected a key at line: 1");
+        let err = import("a = \"x\" y\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Unexpected text after a quoted value at line: 1"
+        );
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let ini = convert(
+            &IniConverter {},
+            "let doc = {
+                top = \"value\",
+                section = {
+                    hash = \"#not a comment\",
+                    multi = \"one\\ntwo\",
+                    path = \"C:\\\\Program Files\\\\app\",
+                    empty = \"\",
+                    flag = NULL,
+                },
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            import(&ini).unwrap(),
+            Rc::new(Val::Tuple(vec![
+                ("top".to_string(), str_val("value")),
+                (
+                    "section".to_string(),
+                    Rc::new(Val::Tuple(vec![
+                        ("hash".to_string(), str_val("#not a comment")),
+                        ("multi".to_string(), str_val("one\ntwo")),
+                        ("path".to_string(), str_val("C:\\Program Files\\app")),
+                        ("empty".to_string(), str_val("")),
+                        ("flag".to_string(), Rc::new(Val::Empty)),
+                    ]))
+                ),
+            ]))
+        );
+    }
+}
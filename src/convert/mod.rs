@@ -17,7 +17,12 @@ pub mod b64;
 pub mod env;
 pub mod exec;
 pub mod flags;
+pub mod hcl;
+pub mod ini;
 pub mod json;
+pub mod properties;
+#[cfg(test)]
+mod test_helpers;
 pub mod toml;
 pub mod traits;
 pub mod xml;
@@ -48,12 +53,27 @@ impl ConverterRegistry {
         let mut registry = Self::new();
         registry.register("json", Box::new(json::JsonConverter::new()));
         registry.register("env", Box::new(env::EnvConverter::new()));
+        registry.register(
+            "envexport",
+            Box::new(env::EnvConverter::new().with_export(true)),
+        );
+        registry.register(
+            "dotenv",
+            Box::new(env::EnvConverter::new().with_strict(true)),
+        );
+        registry.register("envvars", Box::new(env::EnvVarsConverter {}));
         registry.register("flags", Box::new(flags::FlagConverter::new()));
         registry.register("exec", Box::new(exec::ExecConverter::new()));
         registry.register("yaml", Box::new(yaml::YamlConverter::new()));
         registry.register("yamlmulti", Box::new(yamlmulti::MultiYamlConverter::new()));
         registry.register("toml", Box::new(toml::TomlConverter::new()));
         registry.register("xml", Box::new(xml::XmlConverter {}));
+        registry.register("hcl", Box::new(hcl::HclConverter {}));
+        registry.register("ini", Box::new(ini::IniConverter {}));
+        registry.register(
+            "properties",
+            Box::new(properties::PropertiesConverter {}),
+        );
         registry
     }
 
@@ -95,6 +115,10 @@ impl ImporterRegistry {
         registry.register("json", Box::new(json::JsonConverter {}));
         registry.register("yaml", Box::new(yaml::YamlConverter {}));
         registry.register("toml", Box::new(toml::TomlConverter {}));
+        registry.register("hcl", Box::new(hcl::HclConverter {}));
+        registry.register("ini", Box::new(ini::IniConverter {}));
+        registry.register("dotenv", Box::new(env::EnvConverter::new()));
+        registry.register("xml", Box::new(xml::XmlConverter {}));
         registry
     }
 
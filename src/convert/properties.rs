@@ -0,0 +1,370 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Contains code for converting a UCG Val into a Java properties file.
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::build::Val;
+use crate::convert::traits::{ConvertResult, Converter};
+use crate::error::{BuildError, ErrorType};
+
+/// PropertiesConverter flattens a tuple into the keys of a Java properties
+/// file.
+pub struct PropertiesConverter {}
+
+impl PropertiesConverter {
+    /// Escapes a key or value the way `java.util.Properties.store` does so
+    /// `Properties.load` reads it back unchanged.
+    fn escape(s: &str, is_key: bool) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for (i, c) in s.chars().enumerate() {
+            match c {
+                // A leading space in a value would be skipped as whitespace
+                // after the separator.
+                ' ' if is_key || i == 0 => escaped.push_str("\\ "),
+                // A trailing backslash would continue the line.
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\x0c' => escaped.push_str("\\f"),
+                '=' | ':' | '#' | '!' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                // Properties files are ISO 8859-1 so everything else is
+                // written as a \u escape of its UTF-16 code units.
+                c if !(' '..='~').contains(&c) => {
+                    let mut units = [0; 2];
+                    for unit in c.encode_utf16(&mut units).iter() {
+                        escaped.push_str(&format!("\\u{:04X}", unit));
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn write_property(&self, key: &str, val: &str, w: &mut dyn Write) -> ConvertResult {
+        writeln!(
+            w,
+            "{}={}",
+            Self::escape(key, true),
+            Self::escape(val, false)
+        )?;
+        Ok(())
+    }
+
+    fn write_val(&self, key: &str, v: &Val, w: &mut dyn Write) -> ConvertResult {
+        match v {
+            Val::Empty => self.write_property(key, "", w)?,
+            Val::Boolean(b) => self.write_property(key, &b.to_string(), w)?,
+            Val::Int(i) => self.write_property(key, &i.to_string(), w)?,
+            Val::Float(f) => self.write_property(key, &f.to_string(), w)?,
+            Val::Str(s) => self.write_property(key, s, w)?,
+            Val::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.write_val(&format!("{}[{}]", key, i), item, w)?;
+                }
+            }
+            Val::Tuple(flds) => {
+                for (name, val) in flds.iter() {
+                    self.write_val(&format!("{}.{}", key, name), val, w)?;
+                }
+            }
+            Val::Env(_) => {
+                return Err(BuildError::new(
+                    format!("Properties values can't be env but {} is env", key),
+                    ErrorType::ConvertError,
+                )
+                .to_boxed());
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, v: &Val, w: &mut dyn Write) -> ConvertResult {
+        match v {
+            Val::Tuple(flds) => {
+                for (name, val) in flds.iter() {
+                    self.write_val(name, val, w)?;
+                }
+                Ok(())
+            }
+            _ => Err(BuildError::new(
+                "Properties conversions expect a tuple",
+                ErrorType::ConvertError,
+            )
+            .to_boxed()),
+        }
+    }
+}
+
+impl Converter for PropertiesConverter {
+    fn convert(&self, v: Rc<Val>, mut w: &mut dyn Write) -> ConvertResult {
+        self.write(&v, &mut w)
+    }
+
+    fn file_ext(&self) -> String {
+        String::from("properties")
+    }
+
+    fn description(&self) -> String {
+        "Convert ucg tuples into Java properties files.".to_string()
+    }
+
+    #[allow(unused_must_use)]
+    fn help(&self) -> String {
+        include_str!("properties_help.txt").to_string()
+    }
+}
+
+#[cfg(test)]
+mod properties_test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::convert::test_helpers::convert;
+
+    // Joins the physical lines of a properties file into logical lines. A line
+    // ending in an odd number of backslashes continues on the next line with
+    // its leading whitespace skipped.
+    fn logical_lines(input: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut current: Option<String> = None;
+        for line in input.lines() {
+            let line = match current {
+                Some(_) => line.trim_start(),
+                None => {
+                    let trimmed = line.trim_start();
+                    if trimmed.is_empty() || trimmed.starts_with(|c| c == '#' || c == '!') {
+                        continue;
+                    }
+                    trimmed
+                }
+            };
+            let backslashes = line.chars().rev().take_while(|c| *c == '\\').count();
+            let mut joined = current.take().unwrap_or_default();
+            if backslashes % 2 == 1 {
+                joined.push_str(&line[..line.len() - 1]);
+                current = Some(joined);
+            } else {
+                joined.push_str(line);
+                lines.push(joined);
+            }
+        }
+        if let Some(joined) = current {
+            lines.push(joined);
+        }
+        lines
+    }
+
+    fn unescape(s: &str) -> String {
+        let mut out = String::new();
+        let mut units = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push_str(&String::from_utf16(&units).unwrap());
+                units.clear();
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    units.push(u16::from_str_radix(&hex, 16).unwrap());
+                    continue;
+                }
+                Some(c) => {
+                    out.push_str(&String::from_utf16(&units).unwrap());
+                    units.clear();
+                    out.push(match c {
+                        't' => '\t',
+                        'n' => '\n',
+                        'r' => '\r',
+                        'f' => '\x0c',
+                        c => c,
+                    });
+                }
+                None => (),
+            }
+        }
+        out.push_str(&String::from_utf16(&units).unwrap());
+        out
+    }
+
+    // A properties parser following the rules of java.util.Properties.load.
+    fn parse(input: &str) -> BTreeMap<String, String> {
+        let mut props = BTreeMap::new();
+        for line in logical_lines(input) {
+            let chars: Vec<char> = line.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                match chars[i] {
+                    '\\' => i += 2,
+                    '=' | ':' | ' ' | '\t' | '\x0c' => break,
+                    _ => i += 1,
+                }
+            }
+            let key: String = chars[..i.min(chars.len())].iter().collect();
+            let mut rest = chars[i.min(chars.len())..].iter().collect::<String>();
+            rest = rest
+                .trim_start_matches(|c| c == ' ' || c == '\t' || c == '\x0c')
+                .to_string();
+            if rest.starts_with(|c| c == '=' || c == ':') {
+                rest = rest[1..]
+                    .trim_start_matches(|c| c == ' ' || c == '\t' || c == '\x0c')
+                    .to_string();
+            }
+            props.insert(unescape(&key), unescape(&rest));
+        }
+        props
+    }
+
+    fn props(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn convert_flattens_tuples_and_lists_test() {
+        let properties = convert(
+            &PropertiesConverter {},
+            "let doc = {
+                server = {
+                    http = {port = 8080, ssl = false},
+                    hosts = [\"a\", {name = \"b\"}],
+                },
+                ratio = 0.5,
+                unset = NULL,
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            properties,
+            "server.http.port=8080
+server.http.ssl=false
+server.hosts[0]=a
+server.hosts[1].name=b
+ratio=0.5
+unset=
+"
+        );
+    }
+
+    #[test]
+    fn convert_escapes_test() {
+        let properties = convert(
+            &PropertiesConverter {},
+            "let doc = {
+                \"key with=sep:s\" = \" leading space\",
+                url = \"http://host:80/a=b#c!d\",
+                path = \"C:\\\\dir\\\\\",
+                lines = \"one\\ntwo\\tthree\",
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            properties,
+            r#"key\ with\=sep\:s=\ leading space
+url=http\://host\:80/a\=b\#c\!d
+path=C\:\\dir\\
+lines=one\ntwo\tthree
+"#
+        );
+    }
+
+    #[test]
+    fn convert_non_tuple_is_an_error_test() {
+        assert!(convert(&PropertiesConverter {}, "let doc = [1, 2];").is_err());
+        assert!(convert(&PropertiesConverter {}, "let doc = \"a\";").is_err());
+    }
+
+    #[test]
+    fn parser_handles_continuations_test() {
+        assert_eq!(
+            parse(
+                "# comment
+! also a comment
+a = one \\
+    two
+b:three
+c four\\\\
+d\\ e=\\u00E9
+"
+            ),
+            props(&[
+                ("a", "one two"),
+                ("b", "three"),
+                ("c", "four\\"),
+                ("d e", "é")
+            ])
+        );
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let properties = convert(
+            &PropertiesConverter {},
+            "let doc = {
+                server = {
+                    port = 8080,
+                    \"context path\" = \"/api\",
+                },
+                names = [\"first\", \"second\"],
+                message = \" Hello: world\\n\",
+                windows = \"C:\\\\Temp\\\\\",
+                comment = \"#not a comment\",
+                bang = \"!important\",
+                empty = \"\",
+            };",
+        )
+        .unwrap();
+        assert_eq!(
+            parse(&properties),
+            props(&[
+                ("server.port", "8080"),
+                ("server.context path", "/api"),
+                ("names[0]", "first"),
+                ("names[1]", "second"),
+                ("message", " Hello: world\n"),
+                ("windows", "C:\\Temp\\"),
+                ("comment", "#not a comment"),
+                ("bang", "!important"),
+                ("empty", ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn unicode_round_trip_test() {
+        let doc = Rc::new(Val::Tuple(vec![(
+            "caf\u{e9}".to_string(),
+            Rc::new(Val::Str("\u{2603} \u{1f600}".to_string())),
+        )]));
+        let mut buf: Vec<u8> = Vec::new();
+        PropertiesConverter {}.convert(doc, &mut buf).unwrap();
+        let properties = String::from_utf8(buf).unwrap();
+        assert_eq!(properties, "caf\\u00E9=\\u2603 \\uD83D\\uDE00\n");
+        assert_eq!(
+            parse(&properties),
+            props(&[("caf\u{e9}", "\u{2603} \u{1f600}")])
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Helpers shared by the converter and importer tests.
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::build::opcode::Environment;
+use crate::build::FileBuilder;
+use crate::build::Val;
+use crate::convert::traits::Converter;
+
+/// Evaluates the ucg input and returns the value bound to doc.
+pub fn eval(input: &str) -> Rc<Val> {
+    let i_paths = Vec::new();
+    let out: Vec<u8> = Vec::new();
+    let err: Vec<u8> = Vec::new();
+    let env = RefCell::new(Environment::new(out, err));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, &env);
+    b.eval_string(input).unwrap();
+    b.get_out_by_name("doc").unwrap()
+}
+
+/// Evaluates the ucg input and converts the value bound to doc.
+pub fn convert(converter: &dyn Converter, input: &str) -> Result<String, Box<dyn Error>> {
+    let mut buf: Vec<u8> = Vec::new();
+    converter.convert(eval(input), &mut buf)?;
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+pub fn str_val(s: &str) -> Rc<Val> {
+    Rc::new(Val::Str(s.to_string()))
+}
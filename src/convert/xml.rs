@@ -17,15 +17,19 @@ use std::error::Error;
 use std::io::Write;
 use std::rc::Rc;
 
-use super::traits::{ConvertResult, Converter};
+use super::traits::{ConvertResult, Converter, ImportResult, Importer};
 use crate::build::Val;
 use crate::error::BuildError;
 use crate::error::ErrorType;
 
-use xml::common::XmlVersion;
+use xml::attribute::OwnedAttribute;
+use xml::common::{Position, XmlVersion};
+use xml::name::OwnedName;
+use xml::namespace::Namespace;
+use xml::reader::XmlEvent as ReadEvent;
 use xml::writer::events::XmlEvent;
 use xml::writer::EventWriter;
-use xml::EmitterConfig;
+use xml::{EmitterConfig, ParserConfig};
 
 pub struct XmlConverter {}
 
@@ -240,3 +244,285 @@ impl Converter for XmlConverter {
         include_str!("xml_help.txt").to_string()
     }
 }
+
+// An element whose end tag hasn't been read yet.
+struct OpenElement {
+    name: String,
+    attrs: Vec<(String, Rc<Val>)>,
+    children: Vec<Rc<Val>>,
+    ns: Val,
+    namespace: Namespace,
+}
+
+impl OpenElement {
+    fn qualified_name(name: &OwnedName) -> String {
+        match name.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, name.local_name),
+            None => name.local_name.clone(),
+        }
+    }
+
+    fn new(
+        name: OwnedName,
+        attributes: Vec<OwnedAttribute>,
+        namespace: Namespace,
+        parent: Option<&Namespace>,
+    ) -> Self {
+        // The namespace holds every mapping in scope so the ones this
+        // element declares are the ones that differ from its parent.
+        let mut ns = Val::Empty;
+        let mut attrs = Vec::new();
+        let own_prefix = name.prefix.clone().unwrap_or_default();
+        for (prefix, uri) in namespace.0.iter() {
+            if prefix == "xml" || prefix == "xmlns" {
+                continue;
+            }
+            let inherited = parent.and_then(|p| p.get(prefix)).unwrap_or("");
+            if uri == inherited {
+                continue;
+            }
+            // The element's own namespace goes in the ns field. The converter
+            // only supports one so any others are kept as xmlns attributes.
+            if *prefix == own_prefix && ns.is_empty() {
+                ns = if prefix.is_empty() {
+                    Val::Str(uri.clone())
+                } else {
+                    Val::Tuple(vec![
+                        ("prefix".to_string(), Rc::new(Val::Str(prefix.clone()))),
+                        ("uri".to_string(), Rc::new(Val::Str(uri.clone()))),
+                    ])
+                };
+            } else if prefix.is_empty() {
+                attrs.push(("xmlns".to_string(), Rc::new(Val::Str(uri.clone()))));
+            } else {
+                attrs.push((format!("xmlns:{}", prefix), Rc::new(Val::Str(uri.clone()))));
+            }
+        }
+        for attr in attributes {
+            attrs.push((
+                Self::qualified_name(&attr.name),
+                Rc::new(Val::Str(attr.value)),
+            ));
+        }
+        OpenElement {
+            name: Self::qualified_name(&name),
+            attrs,
+            children: Vec::new(),
+            ns,
+            namespace,
+        }
+    }
+
+    fn into_val(self) -> Val {
+        Val::Tuple(vec![
+            ("name".to_string(), Rc::new(Val::Str(self.name))),
+            ("attrs".to_string(), Rc::new(Val::Tuple(self.attrs))),
+            ("children".to_string(), Rc::new(Val::List(self.children))),
+            ("ns".to_string(), Rc::new(self.ns)),
+        ])
+    }
+}
+
+impl Importer for XmlConverter {
+    fn import(&self, bytes: &[u8]) -> ImportResult {
+        let reader = ParserConfig::new()
+            .cdata_to_characters(true)
+            .ignore_comments(true)
+            .create_reader(bytes);
+        let mut doc = Vec::new();
+        let mut stack: Vec<OpenElement> = Vec::new();
+        let mut root = None;
+        for event in reader {
+            let event = event.map_err(|e| {
+                // xml-rs counts lines and columns from 0.
+                let pos = e.position();
+                BuildError::new(
+                    format!(
+                        "{} at line: {} column: {}",
+                        e.msg(),
+                        pos.row + 1,
+                        pos.column + 1
+                    ),
+                    ErrorType::ParseError,
+                )
+                .to_boxed()
+            })?;
+            match event {
+                ReadEvent::StartDocument {
+                    version,
+                    encoding,
+                    standalone,
+                } => {
+                    doc.push((
+                        "version".to_string(),
+                        Rc::new(Val::Str(version.to_string())),
+                    ));
+                    doc.push(("encoding".to_string(), Rc::new(Val::Str(encoding))));
+                    if let Some(standalone) = standalone {
+                        doc.push(("standalone".to_string(), Rc::new(Val::Boolean(standalone))));
+                    }
+                }
+                ReadEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let parent = stack.last().map(|e| &e.namespace);
+                    let element = OpenElement::new(name, attributes, namespace, parent);
+                    stack.push(element);
+                }
+                ReadEvent::EndElement { .. } => {
+                    // The reader makes sure the elements are balanced.
+                    if let Some(element) = stack.pop() {
+                        let node = Rc::new(element.into_val());
+                        match stack.last_mut() {
+                            Some(parent) => parent.children.push(node),
+                            None => root = Some(node),
+                        }
+                    }
+                }
+                ReadEvent::Characters(text) => {
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(Rc::new(Val::Str(text)));
+                    }
+                }
+                // Whitespace between elements, comments and processing
+                // instructions are dropped.
+                _ => (),
+            }
+        }
+        match root {
+            Some(root) => {
+                doc.push(("root".to_string(), root));
+                Ok(Rc::new(Val::Tuple(doc)))
+            }
+            None => Err(
+                BuildError::new("XML document has no root element", ErrorType::ParseError)
+                    .to_boxed(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod xml_test {
+    use super::*;
+    use crate::convert::test_helpers::str_val;
+
+    fn node(name: &str, attrs: Vec<(&str, &str)>, children: Vec<Rc<Val>>, ns: Val) -> Rc<Val> {
+        Rc::new(Val::Tuple(vec![
+            ("name".to_string(), str_val(name)),
+            (
+                "attrs".to_string(),
+                Rc::new(Val::Tuple(
+                    attrs
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), str_val(v)))
+                        .collect(),
+                )),
+            ),
+            ("children".to_string(), Rc::new(Val::List(children))),
+            ("ns".to_string(), Rc::new(ns)),
+        ]))
+    }
+
+    fn import(input: &str) -> Rc<Val> {
+        XmlConverter {}.import(input.as_bytes()).unwrap()
+    }
+
+    fn convert(v: Rc<Val>) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        XmlConverter {}.convert(v, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    const POM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project xmlns="http://maven.apache.org/POM/4.0.0" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/xsd/maven-4.0.0.xsd">
+  <!-- A comment -->
+  <modelVersion>4.0.0</modelVersion>
+  <name><![CDATA[a & b]]></name>
+  <m:meta xmlns:m="http://example.org/meta" id="1">text &lt;escaped&gt;<m:empty /></m:meta>
+</project>"#;
+
+    #[test]
+    fn import_test() {
+        assert_eq!(
+            import(POM),
+            Rc::new(Val::Tuple(vec![
+                ("version".to_string(), str_val("1.0")),
+                ("encoding".to_string(), str_val("UTF-8")),
+                (
+                    "root".to_string(),
+                    node(
+                        "project",
+                        vec![
+                            ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+                            (
+                                "xsi:schemaLocation",
+                                "http://maven.apache.org/POM/4.0.0 http://maven.apache.org/xsd/maven-4.0.0.xsd",
+                            ),
+                        ],
+                        vec![
+                            node("modelVersion", vec![], vec![str_val("4.0.0")], Val::Empty),
+                            node("name", vec![], vec![str_val("a & b")], Val::Empty),
+                            node(
+                                "m:meta",
+                                vec![("id", "1")],
+                                vec![
+                                    str_val("text <escaped>"),
+                                    node("m:empty", vec![], vec![], Val::Empty),
+                                ],
+                                Val::Tuple(vec![
+                                    ("prefix".to_string(), str_val("m")),
+                                    ("uri".to_string(), str_val("http://example.org/meta")),
+                                ]),
+                            ),
+                        ],
+                        Val::Str("http://maven.apache.org/POM/4.0.0".to_string()),
+                    )
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn import_standalone_test() {
+        let doc = import("<?xml version=\"1.1\" standalone=\"yes\"?><a/>");
+        if let Val::Tuple(ref flds) = doc.as_ref() {
+            assert_eq!(flds[0], ("version".to_string(), str_val("1.1")));
+            assert_eq!(
+                flds[2],
+                ("standalone".to_string(), Rc::new(Val::Boolean(true)))
+            );
+        } else {
+            panic!("Expected a tuple but got {:?}", doc);
+        }
+    }
+
+    #[test]
+    fn import_errors_test() {
+        let err = XmlConverter {}.import(b"<a>\n  <b></a>").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ParseError: Unexpected closing tag: a, expected b at line: 2 column: 9"
+        );
+        assert!(XmlConverter {}.import(b"").is_err());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let doc = import(POM);
+        let xml = convert(doc.clone());
+        assert_eq!(import(&xml), doc);
+        assert!(
+            xml.contains(r#"<project xmlns="http://maven.apache.org/POM/4.0.0" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance""#),
+            "{}",
+            xml
+        );
+        assert!(
+            xml.contains(r#"<m:meta xmlns:m="http://example.org/meta" id="1">"#),
+            "{}",
+            xml
+        );
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static analysis of the imports and includes between ucg files.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::ast::walk::Walker;
+use crate::ast::{Expression, Position, Statement, Value};
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+/// A single import or include of a file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dependency {
+    pub path: PathBuf,
+    /// The conversion type for includes. None for imports.
+    pub include_type: Option<String>,
+    pub pos: Position,
+}
+
+impl Dependency {
+    pub fn is_import(&self) -> bool {
+        self.include_type.is_none()
+    }
+}
+
+/// Collects the import and include expressions of a file.
+struct DependencyCollector {
+    found: Vec<(String, Option<String>, Position)>,
+}
+
+impl Walker for DependencyCollector {
+    fn visit_value(&mut self, _val: &mut Value) {}
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Import(ref def) => {
+                self.found
+                    .push((def.path.fragment.clone(), None, def.pos.clone()));
+            }
+            Expression::Include(ref def) => {
+                self.found.push((
+                    def.path.fragment.clone(),
+                    Some(def.typ.fragment.clone()),
+                    def.pos.clone(),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_statement(&mut self, _stmt: &mut Statement) {}
+}
+
+/// DependencyGraph tracks what every ucg file in a tree imports and includes
+/// without building any of them.
+pub struct DependencyGraph {
+    import_paths: Vec<PathBuf>,
+    /// The dependencies of every ucg file we looked at.
+    pub files: BTreeMap<PathBuf, Vec<Dependency>>,
+    /// The files that failed to parse and why.
+    pub errors: BTreeMap<PathBuf, String>,
+}
+
+impl DependencyGraph {
+    pub fn new(import_paths: &[PathBuf]) -> Self {
+        DependencyGraph {
+            import_paths: import_paths.to_vec(),
+            files: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a ucg file or every ucg file in a directory to the graph along
+    /// with all of the files they import.
+    pub fn add_path<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recurse: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let next_path = entry?.path();
+                if next_path.is_dir() {
+                    if recurse {
+                        self.add_path(&next_path, recurse)?;
+                    }
+                } else if next_path.extension() == Some(OsStr::new("ucg")) {
+                    self.add_file(&next_path)?;
+                }
+            }
+            Ok(())
+        } else {
+            self.add_file(path)
+        }
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut queue = VecDeque::new();
+        queue.push_back(path.canonicalize()?);
+        while let Some(next) = queue.pop_front() {
+            if self.files.contains_key(&next) || self.errors.contains_key(&next) {
+                continue;
+            }
+            let deps = match self.file_dependencies(&next) {
+                Ok(deps) => deps,
+                Err(e) => {
+                    self.errors.insert(next, e.to_string());
+                    continue;
+                }
+            };
+            for dep in deps.iter() {
+                // stdlib imports are relative and are compiled into ucg.
+                if dep.is_import() && dep.path.is_absolute() && dep.path.exists() {
+                    queue.push_back(dep.path.clone());
+                }
+            }
+            self.files.insert(next, deps);
+        }
+        Ok(())
+    }
+
+    fn file_dependencies(&self, path: &Path) -> Result<Vec<Dependency>, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut stmts = parse(OffsetStrIter::new(&contents).with_src_file(path), None)?;
+        let mut collector = DependencyCollector { found: Vec::new() };
+        collector.walk_statement_list(stmts.iter_mut().collect());
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut deps: Vec<Dependency> = Vec::new();
+        for (raw, include_type, pos) in collector.found {
+            let path = self.resolve(base, &raw);
+            // We only keep the first time a file uses a dependency.
+            if !deps
+                .iter()
+                .any(|d| d.path == path && d.include_type == include_type)
+            {
+                deps.push(Dependency {
+                    path,
+                    include_type,
+                    pos,
+                });
+            }
+        }
+        Ok(deps)
+    }
+
+    /// Resolves a path the same way the VM does for imports and includes.
+    fn resolve(&self, base: &Path, raw: &str) -> PathBuf {
+        let sep = format!("{}", std::path::MAIN_SEPARATOR);
+        let path = PathBuf::from(raw.replace("/", &sep));
+        // stdlib paths are special
+        if path.starts_with(format!("std{}", std::path::MAIN_SEPARATOR)) || path.is_absolute() {
+            return path;
+        }
+        let normalized = base.join(&path);
+        let found = if normalized.exists() {
+            Some(normalized.clone())
+        } else {
+            self.import_paths
+                .iter()
+                .map(|p| p.join(&path))
+                .find(|p| p.exists())
+        };
+        match found {
+            Some(p) => p.canonicalize().unwrap_or(p),
+            None => normalized,
+        }
+    }
+
+    /// Returns every file that imports or includes the file at path either
+    /// directly or through other files.
+    pub fn dependents_of<P: AsRef<Path>>(&self, path: P) -> BTreeSet<PathBuf> {
+        let target = path.as_ref();
+        let target = target
+            .canonicalize()
+            .unwrap_or_else(|_| target.to_path_buf());
+        let mut found = BTreeSet::new();
+        let mut queue = vec![target];
+        while let Some(next) = queue.pop() {
+            for (file, deps) in self.files.iter() {
+                if deps.iter().any(|d| d.path == next) && found.insert(file.clone()) {
+                    queue.push(file.clone());
+                }
+            }
+        }
+        found
+    }
+
+    /// Returns the import cycles in the graph. Each cycle starts and ends
+    /// with the same file.
+    pub fn cycles(&self) -> Vec<Vec<PathBuf>> {
+        let mut cycles = Vec::new();
+        let mut in_cycle = BTreeSet::new();
+        for start in self.files.keys() {
+            if in_cycle.contains(start) {
+                continue;
+            }
+            if let Some(cycle) = self.shortest_cycle(start) {
+                in_cycle.extend(cycle.iter().cloned());
+                cycles.push(cycle);
+            }
+        }
+        cycles
+    }
+
+    fn shortest_cycle(&self, start: &PathBuf) -> Option<Vec<PathBuf>> {
+        let mut came_from: BTreeMap<&PathBuf, &PathBuf> = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(next) = queue.pop_front() {
+            for dep in self.imports_of(next) {
+                if dep == start {
+                    // Walk back along the path we took to get here.
+                    let mut cycle = vec![start.clone(), next.clone()];
+                    let mut cur = next;
+                    while cur != start {
+                        cur = came_from[cur];
+                        cycle.push(cur.clone());
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if !came_from.contains_key(dep) {
+                    came_from.insert(dep, next);
+                    queue.push_back(dep);
+                }
+            }
+        }
+        None
+    }
+
+    fn imports_of<'a>(&'a self, path: &PathBuf) -> impl Iterator<Item = &'a PathBuf> {
+        self.files
+            .get(path)
+            .into_iter()
+            .flat_map(|deps| deps.iter())
+            .filter(|d| d.is_import())
+            .map(|d| &d.path)
+    }
+
+    fn cycle_edges(&self) -> BTreeSet<(PathBuf, PathBuf)> {
+        let mut edges = BTreeSet::new();
+        for cycle in self.cycles() {
+            for pair in cycle.windows(2) {
+                edges.insert((pair[0].clone(), pair[1].clone()));
+            }
+        }
+        edges
+    }
+
+    /// Writes the graph in the graphviz dot format. Paths are shown relative
+    /// to root. Includes are dashed and imports that form a cycle are red.
+    pub fn write_dot<W: Write>(&self, root: &Path, w: &mut W) -> std::io::Result<()> {
+        let cycle_edges = self.cycle_edges();
+        writeln!(w, "digraph ucg {{")?;
+        for (file, deps) in self.files.iter() {
+            writeln!(w, "    {:?};", display_path(root, file))?;
+            for dep in deps {
+                let mut attrs = Vec::new();
+                if let Some(ref typ) = dep.include_type {
+                    attrs.push(format!("style=dashed, label={:?}", typ));
+                }
+                if cycle_edges.contains(&(file.clone(), dep.path.clone())) {
+                    attrs.push("color=red".to_string());
+                }
+                let attrs = if attrs.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", attrs.join(", "))
+                };
+                writeln!(
+                    w,
+                    "    {:?} -> {:?}{};",
+                    display_path(root, file),
+                    display_path(root, &dep.path),
+                    attrs
+                )?;
+            }
+        }
+        writeln!(w, "}}")
+    }
+
+    /// Returns the graph as json. Paths are shown relative to root.
+    pub fn to_json(&self, root: &Path) -> serde_json::Value {
+        let mut files = serde_json::Map::new();
+        for (file, deps) in self.files.iter() {
+            let imports: Vec<String> = deps
+                .iter()
+                .filter(|d| d.is_import())
+                .map(|d| display_path(root, &d.path))
+                .collect();
+            let includes: Vec<serde_json::Value> = deps
+                .iter()
+                .filter_map(|d| {
+                    d.include_type
+                        .as_ref()
+                        .map(|typ| json!({"path": display_path(root, &d.path), "type": typ}))
+                })
+                .collect();
+            files.insert(
+                display_path(root, file),
+                json!({"imports": imports, "includes": includes}),
+            );
+        }
+        let cycles: Vec<Vec<String>> = self
+            .cycles()
+            .iter()
+            .map(|c| c.iter().map(|p| display_path(root, p)).collect())
+            .collect();
+        let errors: BTreeMap<String, &String> = self
+            .errors
+            .iter()
+            .map(|(p, e)| (display_path(root, p), e))
+            .collect();
+        json!({"files": files, "cycles": cycles, "errors": errors})
+    }
+}
+
+/// Shows a path relative to root when it is inside of root.
+pub fn display_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod deps_test {
+    use std::path::{Path, PathBuf};
+
+    use super::DependencyGraph;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ucg_deps_test_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_dependencies_and_dependents() {
+        let dir = test_dir("graph");
+        let shared = write(&dir, "lib/shared.ucg", "let v = 1;\n");
+        let util = write(&dir, "lib/util.ucg", "let s = import \"shared.ucg\";\n");
+        let data = write(&dir, "data.json", "{}");
+        let app = write(
+            &dir,
+            "app.ucg",
+            "let lib = import \"lib/util.ucg\";\nlet cfg = {data = include json \"data.json\"};\nlet std = import \"std/lists.ucg\";\n",
+        );
+        let other = write(&dir, "other.ucg", "let x = 1;\n");
+        let mut graph = DependencyGraph::new(&Vec::new());
+        graph.add_path(&dir, false).unwrap();
+        // The imported files outside of the directory get followed.
+        assert!(graph.files.contains_key(&util));
+        assert!(graph.files.contains_key(&shared));
+        let deps = &graph.files[&app];
+        assert_eq!(deps.len(), 3);
+        assert_eq!(deps[0].path, util);
+        assert!(deps[0].is_import());
+        assert_eq!(deps[1].path, data);
+        assert_eq!(deps[1].include_type, Some("json".to_string()));
+        assert_eq!(deps[2].path, PathBuf::from("std/lists.ucg"));
+        assert!(graph.files[&other].is_empty());
+
+        let dependents = graph.dependents_of(&shared);
+        assert_eq!(
+            dependents,
+            vec![app.clone(), util.clone()].into_iter().collect()
+        );
+        assert_eq!(
+            graph.dependents_of(&data),
+            vec![app.clone()].into_iter().collect()
+        );
+        assert!(graph.cycles().is_empty());
+
+        let json = graph.to_json(&dir);
+        assert_eq!(json["files"]["app.ucg"]["imports"][0], "lib/util.ucg");
+        assert_eq!(json["files"]["app.ucg"]["includes"][0]["path"], "data.json");
+        assert_eq!(
+            json["files"]["lib/util.ucg"]["imports"][0],
+            "lib/shared.ucg"
+        );
+
+        let mut dot = Vec::new();
+        graph.write_dot(&dir, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(
+            dot.contains("    \"app.ucg\" -> \"lib/util.ucg\";\n"),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains("    \"app.ucg\" -> \"data.json\" [style=dashed, label=\"json\"];\n"),
+            "{}",
+            dot
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_cycles() {
+        let root = std::env::current_dir().unwrap().join("example_errors");
+        let mut graph = DependencyGraph::new(&Vec::new());
+        graph
+            .add_path(root.join("import_cycle1.ucg"), false)
+            .unwrap();
+        let cycles = graph.cycles();
+        assert_eq!(cycles.len(), 1);
+        let cycle: Vec<String> = cycles[0]
+            .iter()
+            .map(|p| super::display_path(&root, p))
+            .collect();
+        assert_eq!(
+            cycle,
+            vec![
+                "import_cycle1.ucg",
+                "import_cycle2.ucg",
+                "import_cycle3.ucg",
+                "import_cycle1.ucg"
+            ]
+        );
+        let mut dot = Vec::new();
+        graph.write_dot(&root, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(
+            dot.contains("\"import_cycle3.ucg\" -> \"import_cycle1.ucg\" [color=red];"),
+            "{}",
+            dot
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_are_recorded() {
+        let dir = test_dir("errors");
+        let broken = write(&dir, "broken.ucg", "let x = ;\n");
+        let mut graph = DependencyGraph::new(&Vec::new());
+        graph.add_path(&dir, false).unwrap();
+        assert!(graph.errors.contains_key(&broken));
+        assert!(!graph.files.contains_key(&broken));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -20,8 +20,18 @@ use std::fmt::Debug;
 use crate::ast::*;
 use crate::iter::FilePositioned;
 
+/// How errors and warnings get reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    /// Rendered with a snippet of the source for people to read.
+    Human,
+    /// One JSON object per line as returned by `diagnostic_json`.
+    Json,
+}
+
 /// ErrorType defines the various types of errors that can result from compiling UCG into an
 /// output format.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorType {
     // Build Errors
     TypeFail,
@@ -154,7 +164,10 @@ where
 {
     fn from(e: &'a abortable_parser::Error<C>) -> BuildError {
         let ctx = e.get_context();
-        let position = Position::new(ctx.line(), ctx.column(), ctx.get_offset());
+        let mut position = Position::new(ctx.line(), ctx.column(), ctx.get_offset());
+        if let Some(file) = ctx.file() {
+            position = position.with_file(file.clone());
+        }
         let err = BuildError::with_pos(e.get_msg(), ErrorType::ParseError, position);
         match e.get_cause() {
             None => err,
@@ -162,3 +175,282 @@ where
         }
     }
 }
+
+/// FileBuildError is returned when building a ucg file fails. It keeps the
+/// underlying error around so it can be rendered in different formats.
+pub struct FileBuildError {
+    pub file: std::path::PathBuf,
+    pub cause: Box<dyn error::Error>,
+}
+
+impl FileBuildError {
+    pub fn new<P: Into<std::path::PathBuf>>(file: P, cause: Box<dyn error::Error>) -> Self {
+        FileBuildError {
+            file: file.into(),
+            cause,
+        }
+    }
+
+    fn render(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            w,
+            "Error building file: {}\n{}",
+            self.file.to_string_lossy(),
+            render_diagnostic(self.cause.as_ref()).trim_end()
+        )
+    }
+}
+
+impl Debug for FileBuildError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl fmt::Display for FileBuildError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl error::Error for FileBuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
+}
+
+/// Renders an error as a diagnostic with the source lines its positions point
+/// at, any errors that caused it, and the calls and imports that led to it.
+///
+/// Errors that don't carry positions are rendered with their Display impl.
+pub fn render_diagnostic(err: &(dyn error::Error + 'static)) -> String {
+    let mut sources = SourceCache::new();
+    let mut out = String::new();
+    write_diagnostic(err, "error", &mut sources, &mut out);
+    out
+}
+
+/// Renders a warning like a lint the same way render_diagnostic renders
+/// errors.
+pub fn render_warning(err: &(dyn error::Error + 'static)) -> String {
+    let mut sources = SourceCache::new();
+    let mut out = String::new();
+    write_diagnostic(err, "warning", &mut sources, &mut out);
+    out
+}
+
+fn write_diagnostic(
+    err: &(dyn error::Error + 'static),
+    level: &str,
+    sources: &mut SourceCache,
+    out: &mut String,
+) {
+    if let Some(e) = err.downcast_ref::<BuildError>() {
+        out.push_str(&format!("{}: {}: {}\n", level, e.err_type, e.msg));
+        if let Some(ref pos) = e.pos {
+            sources.write_snippet(pos, out);
+        }
+        if let Some(ref cause) = e.cause {
+            write_diagnostic(cause.as_ref(), "caused by", sources, out);
+        }
+    } else if let Some(e) = err.downcast_ref::<crate::build::opcode::Error>() {
+        out.push_str(&format!("{}: {}: {}\n", level, e.err_type(), e.message()));
+        if let Some(pos) = e.pos() {
+            sources.write_snippet(pos, out);
+        }
+        if let Some(cause) = e.cause() {
+            write_diagnostic(cause, "caused by", sources, out);
+        }
+        for pos in e.call_stack() {
+            out.push_str("note: via\n");
+            sources.write_snippet(pos, out);
+        }
+    } else if let Some(e) = err.downcast_ref::<crate::lint::Lint>() {
+        out.push_str(&format!("{}: {}: {}\n", level, e.rule, e.msg));
+        sources.write_snippet(&e.pos, out);
+    } else {
+        out.push_str(&format!("{}: {}\n", level, err));
+    }
+}
+
+/// Renders an error as a JSON diagnostic object with the error type, message,
+/// location, call stack and cause chain of the error.
+pub fn diagnostic_json(err: &(dyn error::Error + 'static)) -> serde_json::Value {
+    if let Some(e) = err.downcast_ref::<FileBuildError>() {
+        let mut diagnostic = diagnostic_json(e.cause.as_ref());
+        if diagnostic["file"].is_null() {
+            diagnostic["file"] = e.file.to_string_lossy().to_string().into();
+        }
+        return diagnostic;
+    }
+    let (mut diagnostic, mut next) = json_diagnostic_entry(err);
+    let mut causes = Vec::new();
+    while let Some(cause) = next {
+        let (entry, cause) = json_diagnostic_entry(cause);
+        causes.push(entry);
+        next = cause;
+    }
+    diagnostic["causes"] = serde_json::Value::Array(causes);
+    diagnostic
+}
+
+fn json_diagnostic_entry<'a>(
+    err: &'a (dyn error::Error + 'static),
+) -> (serde_json::Value, Option<&'a (dyn error::Error + 'static)>) {
+    let (err_type, msg, pos, cause, call_stack): (_, String, _, _, &[Position]) =
+        if let Some(e) = err.downcast_ref::<BuildError>() {
+            (
+                Some(e.err_type.to_string()),
+                e.msg.clone(),
+                e.pos.as_ref(),
+                e.cause.as_ref().map(|c| c.as_ref()),
+                &[],
+            )
+        } else if let Some(e) = err.downcast_ref::<crate::build::opcode::Error>() {
+            (
+                Some(e.err_type().to_string()),
+                e.message().to_string(),
+                e.pos(),
+                e.cause(),
+                e.call_stack(),
+            )
+        } else if let Some(e) = err.downcast_ref::<crate::lint::Lint>() {
+            (
+                Some(e.rule.to_string()),
+                e.msg.clone(),
+                Some(&e.pos),
+                None,
+                &[],
+            )
+        } else {
+            (None, err.to_string(), None, None, &[])
+        };
+    let mut entry = json_position(pos);
+    entry["type"] = match err_type {
+        Some(t) => serde_json::Value::String(t),
+        None => serde_json::Value::Null,
+    };
+    entry["message"] = serde_json::Value::String(msg);
+    entry["call_stack"] = call_stack
+        .iter()
+        .map(|p| json_position(Some(p)))
+        .collect::<Vec<_>>()
+        .into();
+    (entry, cause)
+}
+
+fn json_position(pos: Option<&Position>) -> serde_json::Value {
+    let (file, line, column) = match pos {
+        // Positions on line 0 are synthesized and don't point at real source.
+        Some(pos) if pos.line == 0 => (
+            pos.file.as_ref().map(|f| f.to_string_lossy().to_string()),
+            None,
+            None,
+        ),
+        Some(pos) => (
+            pos.file.as_ref().map(|f| f.to_string_lossy().to_string()),
+            Some(pos.line),
+            Some(pos.column),
+        ),
+        None => (None, None, None),
+    };
+    serde_json::json!({
+        "file": file,
+        "line": line,
+        "column": column,
+    })
+}
+
+/// Caches the lines of the source files we render snippets from.
+struct SourceCache {
+    files: std::collections::BTreeMap<std::path::PathBuf, Option<Vec<String>>>,
+}
+
+impl SourceCache {
+    fn new() -> Self {
+        SourceCache {
+            files: std::collections::BTreeMap::new(),
+        }
+    }
+
+    fn get_line(&mut self, file: &std::path::Path, line: usize) -> Option<&str> {
+        let lines = self.files.entry(file.to_path_buf()).or_insert_with(|| {
+            std::fs::read_to_string(file)
+                .ok()
+                .map(|s| s.lines().map(|l| l.to_string()).collect())
+        });
+        match lines {
+            Some(lines) if line > 0 => lines.get(line - 1).map(|l| l.as_str()),
+            _ => None,
+        }
+    }
+
+    fn write_snippet(&mut self, pos: &Position, out: &mut String) {
+        // Positions on line 0 are synthesized and don't point at real source.
+        if pos.line == 0 {
+            return;
+        }
+        let line_num = pos.line.to_string();
+        let gutter = " ".repeat(line_num.len());
+        let file = match pos.file {
+            Some(ref file) => file,
+            None => {
+                out.push_str(&format!(
+                    "{}--> <input>:{}:{}\n",
+                    gutter, pos.line, pos.column
+                ));
+                return;
+            }
+        };
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter,
+            file.to_string_lossy(),
+            pos.line,
+            pos.column
+        ));
+        let line = match self.get_line(file, pos.line) {
+            Some(line) => line,
+            None => return,
+        };
+        // Columns count bytes so we need the char they start at.
+        let mut byte_col = pos.column.saturating_sub(1).min(line.len());
+        while !line.is_char_boundary(byte_col) {
+            byte_col -= 1;
+        }
+        let start = line[..byte_col].chars().count();
+        let chars: Vec<char> = line.chars().collect();
+        // Keep tabs so the caret lines up with the source line.
+        let padding: String = chars[..start]
+            .iter()
+            .map(|c| if *c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat(span_len(&chars[start..]));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line_num, line));
+        out.push_str(&format!("{} | {}{}\n", gutter, padding, underline));
+    }
+}
+
+/// Guesses the length of the token at the start of the chars so we can
+/// underline all of it.
+pub(crate) fn span_len(chars: &[char]) -> usize {
+    match chars.first() {
+        Some('"') => {
+            let mut escaped = false;
+            for (i, c) in chars.iter().enumerate().skip(1) {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => return i + 1,
+                    _ => escaped = false,
+                }
+            }
+            chars.len()
+        }
+        Some(c) if c.is_alphanumeric() || *c == '_' => chars
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '_')
+            .count(),
+        _ => 1,
+    }
+}
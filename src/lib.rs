@@ -37,9 +37,12 @@ pub mod ast;
 pub mod tokenizer;
 pub mod build;
 pub mod convert;
+pub mod deps;
 pub mod error;
 pub mod io;
 pub mod iter;
+pub mod lint;
+pub mod lsp;
 pub mod parse;
 
 pub use crate::ast::Expression;
@@ -0,0 +1,613 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static checks that find likely mistakes in ucg files without building them.
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::ast::printer::AstPrinter;
+use crate::ast::walk::Walker;
+use crate::ast::{BinaryExprType, Expression, Position, Statement, Token, Value};
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+/// The checks the linter can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    /// let bindings that are never used and are not exported.
+    UnusedLet,
+    /// Function arguments that hide a binding from an enclosing scope.
+    ShadowedName,
+    /// Imports bound with let that are never used.
+    UnusedImport,
+    /// select expressions with the same key more than once.
+    DuplicateSelectKey,
+    /// Comparisons of an expression to itself.
+    SelfComparison,
+    /// assert statements in files that aren't tests.
+    AssertOutsideTest,
+}
+
+impl Rule {
+    /// Every rule the linter knows about.
+    pub fn all() -> Vec<Rule> {
+        vec![
+            Rule::UnusedLet,
+            Rule::ShadowedName,
+            Rule::UnusedImport,
+            Rule::DuplicateSelectKey,
+            Rule::SelfComparison,
+            Rule::AssertOutsideTest,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedLet => "unused-let",
+            Rule::ShadowedName => "shadowed-name",
+            Rule::UnusedImport => "unused-import",
+            Rule::DuplicateSelectKey => "duplicate-select-key",
+            Rule::SelfComparison => "self-comparison",
+            Rule::AssertOutsideTest => "assert-outside-test",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Self::all().into_iter().find(|r| r.name() == name)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.write_str(self.name())
+    }
+}
+
+/// A problem the linter found.
+#[derive(Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub msg: String,
+    pub pos: Position,
+}
+
+impl Lint {
+    fn render(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}: {} at {}", self.rule, self.msg, self.pos)
+    }
+}
+
+impl fmt::Debug for Lint {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl Error for Lint {}
+
+#[derive(PartialEq)]
+enum BindingKind {
+    Let,
+    Import,
+    Arg,
+}
+
+struct Binding {
+    name: String,
+    pos: Position,
+    kind: BindingKind,
+    used: bool,
+}
+
+struct Scope {
+    bindings: Vec<Binding>,
+    // Whether the bindings are visible outside of the scope so not using them
+    // here is fine.
+    exported: bool,
+    // Modules don't close over their environment so lookups stop at them.
+    isolated: bool,
+}
+
+impl Scope {
+    fn new(exported: bool, isolated: bool) -> Self {
+        Scope {
+            bindings: Vec::new(),
+            exported,
+            isolated,
+        }
+    }
+}
+
+/// Linter runs the enabled rules over the statements of a file.
+pub struct Linter {
+    rules: BTreeSet<Rule>,
+    is_test: bool,
+    scopes: Vec<Scope>,
+    lints: Vec<Lint>,
+    // Each name that was bound or used paired with where it was bound.
+    definitions: Vec<(Position, Position)>,
+}
+
+impl Linter {
+    pub fn new<I: IntoIterator<Item = Rule>>(rules: I) -> Self {
+        Linter {
+            rules: rules.into_iter().collect(),
+            is_test: false,
+            scopes: Vec::new(),
+            lints: Vec::new(),
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Lints the statements of the file at path returning what was found in
+    /// the order it appears in the file.
+    pub fn lint(&mut self, path: &Path, stmts: &mut [Statement]) -> Vec<Lint> {
+        self.is_test = path.to_string_lossy().ends_with("_test.ucg");
+        self.lints = Vec::new();
+        self.definitions = Vec::new();
+        // Every binding at the top of a file is exported by import.
+        self.scopes = vec![Scope::new(true, true)];
+        self.walk_statement_list(stmts.iter_mut().collect());
+        self.pop_scope();
+        let mut lints = std::mem::take(&mut self.lints);
+        lints.sort_by_key(|l| (l.pos.line, l.pos.column));
+        lints
+    }
+
+    /// Resolves the names in the statements through their scopes. Returns
+    /// the position of every binding and every use of a bound name paired
+    /// with the position of the binding.
+    pub fn definitions(&mut self, stmts: &mut [Statement]) -> Vec<(Position, Position)> {
+        self.lint(Path::new(""), stmts);
+        std::mem::take(&mut self.definitions)
+    }
+
+    /// Parses and lints the file at path.
+    pub fn lint_file(&mut self, path: &Path) -> Result<Vec<Lint>, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut stmts = parse(OffsetStrIter::new(&contents).with_src_file(path), None)?;
+        Ok(self.lint(path, &mut stmts))
+    }
+
+    fn report<S: Into<String>>(&mut self, rule: Rule, msg: S, pos: &Position) {
+        if self.rules.contains(&rule) {
+            self.lints.push(Lint {
+                rule,
+                msg: msg.into(),
+                pos: pos.clone(),
+            });
+        }
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        for b in scope.bindings.iter().filter(|b| !b.used) {
+            match b.kind {
+                BindingKind::Import => self.report(
+                    Rule::UnusedImport,
+                    format!("Import bound to {} is never used", b.name),
+                    &b.pos,
+                ),
+                BindingKind::Let if !scope.exported => self.report(
+                    Rule::UnusedLet,
+                    format!("Binding {} is never used", b.name),
+                    &b.pos,
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    // Finds the binding a name refers to from the innermost scope out.
+    fn lookup(&mut self, name: &str, skip_innermost: bool) -> Option<&mut Binding> {
+        for (i, scope) in self.scopes.iter_mut().rev().enumerate() {
+            if !(skip_innermost && i == 0) {
+                if let Some(b) = scope.bindings.iter_mut().rev().find(|b| b.name == name) {
+                    return Some(b);
+                }
+            }
+            if scope.isolated {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bind(&mut self, name: &str, pos: &Position, kind: BindingKind) {
+        // Only function arguments can shadow anything. Everything else is
+        // bound at the top of a file or module which can't see past itself.
+        if let Some(outer) = self.lookup(name, true).map(|b| b.pos.clone()) {
+            self.report(
+                Rule::ShadowedName,
+                format!(
+                    "{} shadows the binding at line {} column {}",
+                    name, outer.line, outer.column
+                ),
+                pos,
+            );
+        }
+        self.definitions.push((pos.clone(), pos.clone()));
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.bindings.push(Binding {
+                name: name.to_string(),
+                pos: pos.clone(),
+                kind,
+                used: false,
+            });
+        }
+    }
+
+    fn use_name(&mut self, name: &str, pos: &Position) {
+        let def = self.lookup(name, false).map(|b| {
+            b.used = true;
+            b.pos.clone()
+        });
+        if let Some(def) = def {
+            self.definitions.push((pos.clone(), def));
+        }
+    }
+
+    fn check_select_keys(&mut self, keys: &[Token]) {
+        let mut seen = BTreeSet::new();
+        for key in keys {
+            if !seen.insert(key.fragment.as_str()) {
+                self.report(
+                    Rule::DuplicateSelectKey,
+                    format!("Duplicate select key {}", key.fragment),
+                    &key.pos,
+                );
+            }
+        }
+    }
+
+    fn check_self_comparison(&mut self, left: &Expression, right: &Expression, pos: &Position) {
+        // Positions differ so we compare how the expressions print.
+        if let Some(left) = render(left) {
+            if Some(&left) == render(right).as_ref() {
+                self.report(
+                    Rule::SelfComparison,
+                    format!("{} is compared to itself", left),
+                    pos,
+                );
+            }
+        }
+    }
+}
+
+fn render(expr: &Expression) -> Option<String> {
+    let mut buf = Vec::new();
+    AstPrinter::new(0, &mut buf)
+        .with_max_width(usize::MAX)
+        .render_expr(expr)
+        .ok()?;
+    String::from_utf8(buf).ok()
+}
+
+impl Walker for Linter {
+    fn walk_statement(&mut self, stmt: &mut Statement) {
+        self.visit_statement(stmt);
+        match stmt {
+            Statement::Let(ref mut def) => {
+                // The value can't refer to the binding it is bound to.
+                self.walk_expression(&mut def.value);
+                let kind = match def.value {
+                    Expression::Import(_) => BindingKind::Import,
+                    _ => BindingKind::Let,
+                };
+                self.bind(&def.name.fragment, &def.name.pos, kind);
+            }
+            _ => self.walk_statement_children(stmt),
+        }
+    }
+
+    fn walk_expression(&mut self, expr: &mut Expression) {
+        self.visit_expression(expr);
+        match expr {
+            Expression::Binary(ref mut def) if def.kind == BinaryExprType::DOT => {
+                self.walk_expression(&mut def.left);
+                // The right side of a selector names a field not a binding.
+                match *def.right {
+                    Expression::Simple(_) => {}
+                    Expression::Copy(ref mut copy) => self.walk_fieldset(&mut copy.fields),
+                    Expression::Call(ref mut call) => {
+                        for arg in call.arglist.iter_mut() {
+                            self.walk_expression(arg);
+                        }
+                    }
+                    ref mut right => self.walk_expression(right),
+                }
+            }
+            Expression::Func(ref mut def) => {
+                self.scopes.push(Scope::new(true, false));
+                for arg in def.argdefs.iter() {
+                    self.bind(&arg.val, &arg.pos, BindingKind::Arg);
+                }
+                self.walk_expression(&mut def.fields);
+                self.pop_scope();
+            }
+            Expression::Module(ref mut def) => {
+                self.walk_fieldset(&mut def.arg_set);
+                // With an out expression only what it returns is exported.
+                self.scopes.push(Scope::new(def.out_expr.is_none(), true));
+                for stmt in def.statements.iter_mut() {
+                    self.walk_statement(stmt);
+                }
+                if let Some(ref mut out) = def.out_expr {
+                    self.walk_expression(out);
+                }
+                self.pop_scope();
+            }
+            _ => self.walk_expression_children(expr),
+        }
+    }
+
+    fn visit_value(&mut self, val: &mut Value) {
+        if let Value::Symbol(ref sym) = val {
+            self.use_name(&sym.val, &sym.pos);
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Select(ref def) => {
+                let keys: Vec<Token> = def.tuple.iter().map(|(k, _)| k.clone()).collect();
+                self.check_select_keys(&keys);
+            }
+            Expression::Binary(ref def) => match def.kind {
+                BinaryExprType::Equal
+                | BinaryExprType::NotEqual
+                | BinaryExprType::GT
+                | BinaryExprType::LT
+                | BinaryExprType::GTEqual
+                | BinaryExprType::LTEqual => {
+                    self.check_self_comparison(&def.left, &def.right, &def.pos)
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn visit_statement(&mut self, stmt: &mut Statement) {
+        if let Statement::Assert(ref pos, _) = stmt {
+            if !self.is_test {
+                self.report(
+                    Rule::AssertOutsideTest,
+                    "assert outside of a _test.ucg file",
+                    pos,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod lint_test {
+    use super::*;
+
+    fn lint_with(file: &str, input: &str, rules: Vec<Rule>) -> Vec<(Rule, String, usize)> {
+        let mut stmts = parse(OffsetStrIter::new(input), None).unwrap();
+        Linter::new(rules)
+            .lint(Path::new(file), &mut stmts)
+            .into_iter()
+            .map(|l| (l.rule, l.msg, l.pos.line))
+            .collect()
+    }
+
+    fn lint(input: &str) -> Vec<(Rule, String, usize)> {
+        lint_with("foo.ucg", input, Rule::all())
+    }
+
+    #[test]
+    fn test_unused_let_only_when_not_exported() {
+        let input = "let a = 1;
+let m = module {} => (b) {
+    let b = 1;
+    let c = 2;
+};
+let n = module {} => {
+    let d = 1;
+};
+";
+        assert_eq!(
+            lint(input),
+            vec![(Rule::UnusedLet, "Binding c is never used".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn test_unused_import() {
+        let input = "let a = import \"std/lists.ucg\";
+let b = import \"std/tuples.ucg\";
+let c = b.fields{tpl={}};
+let m = module {} => (d) {
+    let l = import \"std/lists.ucg\";
+    let d = a;
+};
+";
+        assert_eq!(
+            lint(input),
+            vec![
+                (
+                    Rule::UnusedImport,
+                    "Import bound to a is never used".to_string(),
+                    1
+                ),
+                (
+                    Rule::UnusedImport,
+                    "Import bound to l is never used".to_string(),
+                    5
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selector_fields_are_not_uses() {
+        let input = "let m = module {} => (out) {
+    let lib = import \"std/lists.ucg\";
+    let a = 1;
+    let out = lib.a;
+};
+";
+        assert_eq!(
+            lint(input),
+            vec![(Rule::UnusedLet, "Binding a is never used".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_shadowed_name() {
+        let input = "let x = 1;
+let f = func (x, y) => func (y) => x + y;
+let m = module {} => {
+    let x = 2;
+};
+";
+        assert_eq!(
+            lint(input),
+            vec![
+                (
+                    Rule::ShadowedName,
+                    "x shadows the binding at line 1 column 5".to_string(),
+                    2
+                ),
+                (
+                    Rule::ShadowedName,
+                    "y shadows the binding at line 2 column 18".to_string(),
+                    2
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_select_key() {
+        let input = "let a = select (true) => {true = 1, false = 2, true = 3};";
+        assert_eq!(
+            lint(input),
+            vec![(
+                Rule::DuplicateSelectKey,
+                "Duplicate select key true".to_string(),
+                1
+            )]
+        );
+    }
+
+    #[test]
+    fn test_self_comparison() {
+        let input = "let a = 1;
+let b = a == a;
+let c = [1, a] != [1, a];
+let d = a == 1;
+";
+        assert_eq!(
+            lint(input),
+            vec![
+                (
+                    Rule::SelfComparison,
+                    "a is compared to itself".to_string(),
+                    2
+                ),
+                (
+                    Rule::SelfComparison,
+                    "[1, a] is compared to itself".to_string(),
+                    3
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assert_outside_test() {
+        let input = "assert {ok = true, desc = \"ok\"};";
+        assert_eq!(
+            lint(input),
+            vec![(
+                Rule::AssertOutsideTest,
+                "assert outside of a _test.ucg file".to_string(),
+                1
+            )]
+        );
+        assert_eq!(lint_with("foo_test.ucg", input, Rule::all()), vec![]);
+    }
+
+    #[test]
+    fn test_only_enabled_rules_are_reported() {
+        let input = "let a = 1 == 1;\nassert {ok = a, desc = \"a\"};";
+        assert_eq!(
+            lint_with("foo.ucg", input, vec![Rule::AssertOutsideTest]),
+            vec![(
+                Rule::AssertOutsideTest,
+                "assert outside of a _test.ucg file".to_string(),
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn test_definitions_follow_scopes() {
+        let input = "let x = 1;
+let f = func (x) => x;
+let m = module {} => (y) {
+    let x = 2;
+    let y = x;
+};
+let z = x;
+";
+        let mut stmts = parse(OffsetStrIter::new(input), None).unwrap();
+        let defs: Vec<((usize, usize), (usize, usize))> = Linter::new(vec![])
+            .definitions(&mut stmts)
+            .into_iter()
+            .filter(|(used, def)| used != def)
+            .map(|(used, def)| ((used.line, used.column), (def.line, def.column)))
+            .collect();
+        assert_eq!(
+            defs,
+            vec![
+                ((2, 21), (2, 15)),
+                ((5, 13), (4, 9)),
+                ((3, 23), (5, 9)),
+                ((7, 9), (1, 5))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_diagnostics() {
+        let lint = Lint {
+            rule: Rule::UnusedImport,
+            msg: "Import bound to a is never used".to_string(),
+            pos: Position::new(1, 5, 4),
+        };
+        assert_eq!(
+            crate::error::render_warning(&lint),
+            "warning: unused-import: Import bound to a is never used\n --> <input>:1:5\n"
+        );
+        let json = crate::error::diagnostic_json(&lint);
+        assert_eq!(json["type"], "unused-import");
+        assert_eq!(json["line"], 1);
+    }
+}
@@ -0,0 +1,673 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A Language Server Protocol server for ucg that speaks JSON-RPC over stdio.
+//!
+//! The server supports diagnostics when a document is opened or saved,
+//! go to definition for bindings and `import` or `include` paths,
+//! hover for the type and value of top level bindings and document
+//! formatting.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::ast::printer::{AstPrinter, FmtConfig};
+use crate::ast::{Position, Token, TokenType};
+use crate::build::opcode::Environment;
+use crate::build::{FileBuilder, Val};
+use crate::error;
+use crate::iter::OffsetStrIter;
+use crate::lint::Linter;
+use crate::parse::parse;
+use crate::tokenizer::tokenize;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+type RpcResult = Result<Value, (i64, String)>;
+
+/// An open document and the values from the last time it built successfully.
+struct Document {
+    text: String,
+    values: Option<Rc<Val>>,
+}
+
+/// The token under the cursor in a document.
+struct TokenAt<'a> {
+    uri: &'a str,
+    doc: &'a Document,
+    tokens: Vec<Token>,
+    idx: usize,
+}
+
+/// Server handles the LSP messages for a single client.
+pub struct Server<W: Write> {
+    out: W,
+    import_paths: Vec<PathBuf>,
+    env_vars: BTreeMap<String, String>,
+    strict: bool,
+    documents: BTreeMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    /// Constructs a new Server that writes its messages to out.
+    pub fn new(out: W, import_paths: Vec<PathBuf>) -> Self {
+        Server {
+            out,
+            import_paths,
+            env_vars: BTreeMap::new(),
+            strict: true,
+            documents: BTreeMap::new(),
+            shutdown: false,
+        }
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The variables to expose through the env symbol when building documents.
+    pub fn with_env_vars(mut self, env_vars: BTreeMap<String, String>) -> Self {
+        self.env_vars = env_vars;
+        self
+    }
+
+    /// Handles messages from input until the client tells us to exit.
+    ///
+    /// Returns whether the client asked us to shut down before exiting.
+    pub fn serve<R: BufRead>(&mut self, mut input: R) -> Result<bool, Box<dyn Error>> {
+        while let Some(body) = read_message(&mut input)? {
+            let msg: Value = match serde_json::from_slice(&body) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    self.send_error(Value::Null, PARSE_ERROR, e.to_string())?;
+                    continue;
+                }
+            };
+            if !self.handle(msg)? {
+                break;
+            }
+        }
+        Ok(self.shutdown)
+    }
+
+    /// Handles a single message. Returns false when the server should exit.
+    fn handle(&mut self, msg: Value) -> io::Result<bool> {
+        let method = match msg["method"].as_str() {
+            Some(method) => method.to_string(),
+            // Responses to requests we never send.
+            None => return Ok(true),
+        };
+        let params = &msg["params"];
+        match msg.get("id") {
+            Some(id) => {
+                let result = if self.shutdown {
+                    Err((INVALID_REQUEST, "The server is shutting down".to_string()))
+                } else {
+                    self.handle_request(&method, params)
+                };
+                match result {
+                    Ok(result) => {
+                        self.send(json!({"jsonrpc": "2.0", "id": id, "result": result}))?
+                    }
+                    Err((code, message)) => self.send_error(id.clone(), code, message)?,
+                }
+            }
+            None => {
+                if method == "exit" {
+                    return Ok(false);
+                }
+                self.handle_notification(&method, params)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> RpcResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": {
+                        "openClose": true,
+                        "change": 1,
+                        "save": {"includeText": true},
+                    },
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": {"name": "ucg", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method {}", method))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => return Ok(()),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(
+                    uri.clone(),
+                    Document {
+                        text: text.to_string(),
+                        values: None,
+                    },
+                );
+                self.publish_diagnostics(&uri)?;
+            }
+            "textDocument/didChange" => {
+                // We only ask for full document syncs so the last change
+                // is the whole document.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    if let Some(doc) = self.documents.get_mut(&uri) {
+                        doc.text = text.to_string();
+                    }
+                }
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    if let Some(doc) = self.documents.get_mut(&uri) {
+                        doc.text = text.to_string();
+                    }
+                }
+                self.publish_diagnostics(&uri)?;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": []},
+                }))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Builds the document and reports any errors to the client.
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let path = match uri_to_path(uri) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text = match self.documents.get(uri) {
+            Some(doc) => doc.text.clone(),
+            None => return Ok(()),
+        };
+        let diagnostics = match self.build(&path, &text) {
+            Ok(values) => {
+                if let Some(doc) = self.documents.get_mut(uri) {
+                    doc.values = Some(values);
+                }
+                Vec::new()
+            }
+            Err(e) => vec![to_lsp_diagnostic(&path, &text, e.as_ref())],
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    fn build(&self, path: &Path, text: &str) -> Result<Rc<Val>, Box<dyn Error>> {
+        // Anything the document outputs is discarded so it can't interfere
+        // with our messages on stdout.
+        let env = RefCell::new(Environment::new_with_vars(
+            Vec::new(),
+            Vec::new(),
+            self.env_vars.clone(),
+        ));
+        env.borrow_mut().write_out_files = false;
+        env.borrow_mut().warnings = false;
+        let working_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut builder = FileBuilder::new(working_dir, &self.import_paths, &env);
+        builder.set_strict(self.strict);
+        let input = OffsetStrIter::new(text).with_src_file(path.to_path_buf());
+        builder.eval_input(input, Some(path.to_path_buf()))
+    }
+
+    fn definition(&self, params: &Value) -> RpcResult {
+        let TokenAt {
+            uri,
+            doc,
+            tokens,
+            idx,
+        } = match self.token_at(params)? {
+            Some(found) => found,
+            None => return Ok(Value::Null),
+        };
+        let tok = &tokens[idx];
+        match tok.typ {
+            TokenType::QUOTED if is_import_path(&tokens, idx) => {
+                let dir = match uri_to_path(uri) {
+                    Some(path) => path.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+                    None => return Ok(Value::Null),
+                };
+                Ok(match self.resolve_import(&dir, &tok.fragment) {
+                    Some(path) => json!({
+                        "uri": path_to_uri(&path),
+                        "range": lsp_range(&[], 0, 0, 0),
+                    }),
+                    None => Value::Null,
+                })
+            }
+            TokenType::BAREWORD if !is_field(&tokens, idx) => {
+                let lines: Vec<&str> = doc.text.lines().collect();
+                Ok(match find_definition(&doc.text, &tokens, idx) {
+                    Some(def) => json!({
+                        "uri": uri,
+                        "range": token_range(def, &lines),
+                    }),
+                    None => Value::Null,
+                })
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn hover(&self, params: &Value) -> RpcResult {
+        let TokenAt {
+            doc, tokens, idx, ..
+        } = match self.token_at(params)? {
+            Some(found) => found,
+            None => return Ok(Value::Null),
+        };
+        let tok = &tokens[idx];
+        if tok.typ != TokenType::BAREWORD || is_field(&tokens, idx) {
+            return Ok(Value::Null);
+        }
+        let val = match doc.values.as_ref().map(|v| v.as_ref()) {
+            Some(Val::Tuple(flds)) => flds.iter().find(|(k, _)| k == &tok.fragment),
+            _ => None,
+        };
+        let lines: Vec<&str> = doc.text.lines().collect();
+        Ok(match val {
+            Some((name, val)) => json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!("`{}`: {}\n\n```\n{}\n```", name, val.type_name(), val),
+                },
+                "range": token_range(tok, &lines),
+            }),
+            None => Value::Null,
+        })
+    }
+
+    fn formatting(&self, params: &Value) -> RpcResult {
+        let doc = self.document(params)?;
+        // A .ucgfmt.toml takes precedence over the editor's settings so that
+        // we format the same way ucg fmt does.
+        let config_path = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(uri_to_path)
+            .and_then(|p| FmtConfig::find(&p).ok())
+            .and_then(|found| found);
+        let config = match config_path {
+            Some(path) => FmtConfig::load(&path).map_err(|e| (REQUEST_FAILED, e.to_string()))?,
+            None => {
+                let mut config = FmtConfig::default();
+                if let Some(indent) = params["options"]["tabSize"].as_u64() {
+                    config.indent = indent as usize;
+                }
+                config
+            }
+        };
+        let mut comment_map = BTreeMap::new();
+        let stmts = match parse(OffsetStrIter::new(&doc.text), Some(&mut comment_map)) {
+            Ok(stmts) => stmts,
+            Err(e) => return Err((REQUEST_FAILED, e.to_string())),
+        };
+        let mut buf = Vec::new();
+        AstPrinter::from_config(&config, &mut buf)
+            .with_comment_map(&comment_map)
+            .with_source(&doc.text)
+            .render(&stmts)
+            .map_err(|e| (REQUEST_FAILED, e.to_string()))?;
+        let formatted = String::from_utf8_lossy(&buf).to_string();
+        if formatted == doc.text {
+            return Ok(json!([]));
+        }
+        let end_line = doc.text.lines().count() + 1;
+        Ok(json!([{
+            "range": {
+                "start": {"line": 0, "character": 0},
+                "end": {"line": end_line, "character": 0},
+            },
+            "newText": formatted,
+        }]))
+    }
+
+    fn document(&self, params: &Value) -> Result<&Document, (i64, String)> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri,
+            None => return Err((INVALID_PARAMS, "Missing textDocument uri".to_string())),
+        };
+        match self.documents.get(uri) {
+            Some(doc) => Ok(doc),
+            None => Err((INVALID_PARAMS, format!("Unknown document {}", uri))),
+        }
+    }
+
+    /// Finds the token under the position in a textDocument/positionParams.
+    fn token_at<'a>(&'a self, params: &'a Value) -> Result<Option<TokenAt<'a>>, (i64, String)> {
+        let doc = self.document(params)?;
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        // Documents that don't tokenize have nothing for us to look up.
+        let tokens = match tokenize(OffsetStrIter::new(&doc.text), None) {
+            Ok(tokens) => tokens,
+            Err(_) => return Ok(None),
+        };
+        let lines: Vec<&str> = doc.text.lines().collect();
+        let column = from_utf16(lines.get(line).cloned().unwrap_or_default(), character);
+        let idx = tokens.iter().position(|tok| {
+            if tok.typ != TokenType::BAREWORD && tok.typ != TokenType::QUOTED {
+                return false;
+            }
+            let (tok_line, start, end) = token_span(tok, &lines);
+            tok_line == line && start <= column && column <= end
+        });
+        Ok(idx.map(|idx| TokenAt {
+            uri,
+            doc,
+            tokens,
+            idx,
+        }))
+    }
+
+    /// Resolves an import path the same way the VM does.
+    fn resolve_import(&self, dir: &Path, path: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(path);
+        // stdlib paths are compiled into ucg and have no file to go to.
+        if path.starts_with("std") {
+            return None;
+        }
+        if path.is_absolute() {
+            return if path.exists() { Some(path) } else { None };
+        }
+        let candidates =
+            std::iter::once(dir.to_path_buf()).chain(self.import_paths.iter().cloned());
+        candidates.map(|p| p.join(&path)).find(|p| p.exists())
+    }
+
+    fn send(&mut self, msg: Value) -> io::Result<()> {
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn send_error(&mut self, id: Value, code: i64, message: String) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }))
+    }
+}
+
+/// Reads the body of the next message from input. Returns None when the
+/// input is closed.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<usize>().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid Content-Length: {}", e),
+                )
+            })?);
+        }
+    }
+    let len = match content_length {
+        Some(len) => len,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message is missing a Content-Length header",
+            ))
+        }
+    };
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Converts a build error into an LSP diagnostic for the document at path.
+///
+/// Errors in imported files are reported on the import that led to them.
+fn to_lsp_diagnostic(path: &Path, text: &str, err: &(dyn Error + 'static)) -> Value {
+    let diagnostic = error::diagnostic_json(err);
+    let mut message = diagnostic["message"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if let Some(causes) = diagnostic["causes"].as_array() {
+        for cause in causes {
+            message.push_str("\ncaused by: ");
+            message.push_str(cause["message"].as_str().unwrap_or_default());
+        }
+    }
+    let doc_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let in_document = |loc: &Value| match loc["file"].as_str() {
+        Some(file) => {
+            let file = Path::new(file);
+            file == path || file.canonicalize().map(|f| f == doc_path).unwrap_or(false)
+        }
+        None => true,
+    };
+    let location = if in_document(&diagnostic) {
+        Some(&diagnostic)
+    } else {
+        if let Some(file) = diagnostic["file"].as_str() {
+            message.push_str(&format!("\nin {}", file));
+        }
+        diagnostic["call_stack"]
+            .as_array()
+            .and_then(|stack| stack.iter().find(|loc| in_document(loc)))
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    let range = match location {
+        Some(loc) => match (loc["line"].as_u64(), loc["column"].as_u64()) {
+            (Some(line), Some(column)) if line > 0 => {
+                let line = line as usize - 1;
+                let start = (column as usize).saturating_sub(1);
+                let len = span_len(lines.get(line).cloned().unwrap_or_default(), start);
+                lsp_range(&lines, line, start, start + len)
+            }
+            _ => lsp_range(&lines, 0, 0, 0),
+        },
+        None => lsp_range(&lines, 0, 0, 0),
+    };
+    let mut lsp_diagnostic = json!({
+        "range": range,
+        "severity": 1,
+        "source": "ucg",
+        "message": message,
+    });
+    if let Some(err_type) = diagnostic["type"].as_str() {
+        lsp_diagnostic["code"] = err_type.into();
+    }
+    lsp_diagnostic
+}
+
+/// Converts a byte offset in a line to the UTF-16 code units LSP positions
+/// count.
+fn to_utf16(line: &str, offset: usize) -> usize {
+    line.char_indices()
+        .take_while(|(i, _)| *i < offset)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+/// Converts an LSP position in UTF-16 code units to a byte offset in a line.
+fn from_utf16(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// The range between two byte offsets on a line.
+fn lsp_range(lines: &[&str], line: usize, start: usize, end: usize) -> Value {
+    let text = lines.get(line).cloned().unwrap_or_default();
+    json!({
+        "start": {"line": line, "character": to_utf16(text, start)},
+        "end": {"line": line, "character": to_utf16(text, end)},
+    })
+}
+
+/// The length in bytes of the token that starts at the byte offset in a line.
+fn span_len(line: &str, start: usize) -> usize {
+    let chars: Vec<char> = line.get(start..).unwrap_or_default().chars().collect();
+    let len = error::span_len(&chars);
+    chars[..len.min(chars.len())]
+        .iter()
+        .map(|c| c.len_utf8())
+        .sum()
+}
+
+/// The line and the byte offsets of the start and end of a token.
+///
+/// Token positions count bytes from the start of the line.
+fn token_span(tok: &Token, lines: &[&str]) -> (usize, usize, usize) {
+    let line = tok.pos.line.saturating_sub(1);
+    let start = tok.pos.column.saturating_sub(1);
+    let len = match tok.typ {
+        // The fragment of a quoted string has its quotes and escapes removed.
+        TokenType::QUOTED => span_len(lines.get(line).cloned().unwrap_or_default(), start),
+        _ => tok.fragment.len(),
+    };
+    (line, start, start + len)
+}
+
+/// The range in the source text a token covers.
+fn token_range(tok: &Token, lines: &[&str]) -> Value {
+    let (line, start, end) = token_span(tok, lines);
+    lsp_range(lines, line, start, end)
+}
+
+fn is_keyword(tok: &Token, keyword: &str) -> bool {
+    tok.typ == TokenType::BAREWORD && tok.fragment == keyword
+}
+
+/// Whether the string at idx is the path of an `import` or `include`.
+fn is_import_path(tokens: &[Token], idx: usize) -> bool {
+    (idx >= 1 && is_keyword(&tokens[idx - 1], "import"))
+        || (idx >= 2 && is_keyword(&tokens[idx - 2], "include"))
+}
+
+/// Whether the bareword at idx is a field selected with a dot.
+fn is_field(tokens: &[Token], idx: usize) -> bool {
+    idx >= 1 && tokens[idx - 1].typ == TokenType::PUNCT && tokens[idx - 1].fragment == "."
+}
+
+/// Finds the token that binds the bareword at idx by resolving it through
+/// the scopes of the document.
+fn find_definition<'a>(text: &str, tokens: &'a [Token], idx: usize) -> Option<&'a Token> {
+    let mut stmts = parse(OffsetStrIter::new(text), None).ok()?;
+    let at = |pos: &Position| (pos.line, pos.column);
+    let used = at(&tokens[idx].pos);
+    let (_, def) = Linter::new(vec![])
+        .definitions(&mut stmts)
+        .into_iter()
+        .find(|(pos, _)| at(pos) == used)?;
+    tokens.iter().find(|tok| at(&tok.pos) == at(&def))
+}
+
+/// Converts a file:// uri into a path.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    let bytes = &uri.as_bytes()["file://".len()..];
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let decoded = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(b) = decoded {
+                path.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        path.push(bytes[i]);
+        i += 1;
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&path).to_string()))
+}
+
+/// Converts a path into a file:// uri.
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,322 @@
+// Copyright 2019 Jeremy Wall
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::io::{BufRead, Cursor, Read};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use super::{path_to_uri, uri_to_path, Server};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ucg_lsp_test_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs a scripted client session against the server and returns the
+/// messages the server sent back.
+fn run_session(msgs: Vec<Value>) -> (bool, Vec<Value>) {
+    let mut input = String::new();
+    for msg in msgs {
+        let body = msg.to_string();
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+    let mut out = Vec::new();
+    let shutdown = Server::new(&mut out, Vec::new())
+        .serve(Cursor::new(input))
+        .unwrap();
+    let mut out = Cursor::new(out);
+    let mut responses = Vec::new();
+    loop {
+        let mut header = String::new();
+        if out.read_line(&mut header).unwrap() == 0 {
+            break;
+        }
+        let len: usize = header
+            .trim_start_matches("Content-Length:")
+            .trim()
+            .parse()
+            .unwrap();
+        let mut blank = String::new();
+        out.read_line(&mut blank).unwrap();
+        let mut body = vec![0; len];
+        out.read_exact(&mut body).unwrap();
+        responses.push(serde_json::from_slice(&body).unwrap());
+    }
+    (shutdown, responses)
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+fn open(uri: &str, text: &str) -> Value {
+    notification(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri, "languageId": "ucg", "version": 1, "text": text}}),
+    )
+}
+
+fn position(uri: &str, line: u64, character: u64) -> Value {
+    json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}})
+}
+
+fn response(responses: &[Value], id: u64) -> &Value {
+    responses.iter().find(|r| r["id"] == id).unwrap()
+}
+
+fn diagnostics(responses: &[Value]) -> Vec<&Value> {
+    responses
+        .iter()
+        .filter(|r| r["method"] == "textDocument/publishDiagnostics")
+        .collect()
+}
+
+#[test]
+fn test_initialize_and_shutdown() {
+    let (shutdown, responses) = run_session(vec![
+        request(1, "initialize", json!({"capabilities": {}})),
+        notification("initialized", json!({})),
+        request(2, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+    assert!(shutdown);
+    let capabilities = &response(&responses, 1)["result"]["capabilities"];
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["documentFormattingProvider"], true);
+    assert_eq!(response(&responses, 2)["result"], Value::Null);
+}
+
+#[test]
+fn test_exit_without_shutdown() {
+    let (shutdown, _) = run_session(vec![notification("exit", Value::Null)]);
+    assert!(!shutdown);
+}
+
+#[test]
+fn test_unknown_method() {
+    let (_, responses) = run_session(vec![request(1, "workspace/symbol", json!({}))]);
+    assert_eq!(response(&responses, 1)["error"]["code"], -32601);
+}
+
+#[test]
+fn test_diagnostics_on_open_and_save() {
+    let dir = test_dir("diagnostics");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(&uri, "let ok = 1;\nlet broken = 1 + \"two\";\n"),
+        notification(
+            "textDocument/didChange",
+            json!({"textDocument": {"uri": uri, "version": 2}, "contentChanges": [{"text": "let ok = 1;\n"}]}),
+        ),
+        notification(
+            "textDocument/didSave",
+            json!({"textDocument": {"uri": uri}}),
+        ),
+    ]);
+    let published = diagnostics(&responses);
+    assert_eq!(published.len(), 2);
+    let first = &published[0]["params"]["diagnostics"][0];
+    assert_eq!(first["message"], "Expected Int but got String(two)");
+    assert_eq!(
+        first["range"],
+        json!({"start": {"line": 1, "character": 17}, "end": {"line": 1, "character": 22}})
+    );
+    assert_eq!(first["severity"], 1);
+    assert_eq!(published[1]["params"]["diagnostics"], json!([]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_diagnostics_for_imported_file() {
+    let dir = test_dir("imported_diagnostics");
+    std::fs::write(dir.join("lib.ucg"), "let broken = {foo = };\n").unwrap();
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![open(&uri, "let lib = import \"lib.ucg\";\n")]);
+    let diagnostic = &diagnostics(&responses)[0]["params"]["diagnostics"][0];
+    assert_eq!(diagnostic["code"], "ParseError");
+    assert_eq!(
+        diagnostic["range"],
+        json!({"start": {"line": 0, "character": 17}, "end": {"line": 0, "character": 26}})
+    );
+    let message = diagnostic["message"].as_str().unwrap();
+    assert!(message.contains("caused by: Not a Bareword"), "{}", message);
+    assert!(message.contains("lib.ucg"), "{}", message);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_definition_of_let_binding() {
+    let dir = test_dir("definition");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(
+            &uri,
+            "let foo = 1;\nlet bar = {foo = foo};\nlet baz = bar.foo;\n",
+        ),
+        request(1, "textDocument/definition", position(&uri, 1, 18)),
+        request(2, "textDocument/definition", position(&uri, 2, 15)),
+        request(3, "textDocument/definition", position(&uri, 2, 11)),
+    ]);
+    assert_eq!(
+        response(&responses, 1)["result"],
+        json!({"uri": uri, "range": {"start": {"line": 0, "character": 4}, "end": {"line": 0, "character": 7}}})
+    );
+    // Fields selected with a dot aren't bindings.
+    assert_eq!(response(&responses, 2)["result"], Value::Null);
+    assert_eq!(
+        response(&responses, 3)["result"]["range"]["start"],
+        json!({"line": 1, "character": 4})
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_definition_follows_scopes() {
+    let dir = test_dir("definition_scopes");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(
+            &uri,
+            "let x = 1;\nlet f = func (x) => x;\nlet m = module {} => (y) {\n    let x = 2;\n    let y = x;\n};\n",
+        ),
+        request(1, "textDocument/definition", position(&uri, 1, 20)),
+        request(2, "textDocument/definition", position(&uri, 4, 12)),
+    ]);
+    // Function parameters and module bindings hide the top level binding.
+    assert_eq!(
+        response(&responses, 1)["result"]["range"],
+        json!({"start": {"line": 1, "character": 14}, "end": {"line": 1, "character": 15}})
+    );
+    assert_eq!(
+        response(&responses, 2)["result"]["range"],
+        json!({"start": {"line": 3, "character": 8}, "end": {"line": 3, "character": 9}})
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_positions_count_utf16_code_units() {
+    let dir = test_dir("utf16");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(&uri, "let foo = \"\u{e9}\u{1f600}\"; let bar = foo;\n"),
+        // \u{e9} is one code unit and \u{1f600} is two.
+        request(1, "textDocument/hover", position(&uri, 0, 28)),
+    ]);
+    assert_eq!(
+        response(&responses, 1)["result"]["range"],
+        json!({"start": {"line": 0, "character": 27}, "end": {"line": 0, "character": 30}})
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_definition_of_import_path() {
+    let dir = test_dir("import_definition");
+    std::fs::write(dir.join("lib.ucg"), "let value = 1;\n").unwrap();
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(
+            &uri,
+            "let lib = import \"lib.ucg\";\nlet std = import \"std/lists.ucg\";\n",
+        ),
+        request(1, "textDocument/definition", position(&uri, 0, 20)),
+        request(2, "textDocument/definition", position(&uri, 1, 20)),
+    ]);
+    assert_eq!(
+        response(&responses, 1)["result"]["uri"],
+        json!(path_to_uri(&dir.join("lib.ucg")))
+    );
+    assert_eq!(response(&responses, 2)["result"], Value::Null);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hover_shows_type_and_value() {
+    let dir = test_dir("hover");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(&uri, "let foo = 1 + 1;\nlet bar = foo;\n"),
+        request(1, "textDocument/hover", position(&uri, 1, 11)),
+        request(2, "textDocument/hover", position(&uri, 0, 12)),
+    ]);
+    let hover = &response(&responses, 1)["result"];
+    assert_eq!(hover["contents"]["value"], "`foo`: Integer\n\n```\n2\n```");
+    assert_eq!(
+        hover["range"],
+        json!({"start": {"line": 1, "character": 10}, "end": {"line": 1, "character": 13}})
+    );
+    assert_eq!(response(&responses, 2)["result"], Value::Null);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_formatting() {
+    let dir = test_dir("formatting");
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(&uri, "let foo = {a=1,b=2};\n"),
+        request(
+            1,
+            "textDocument/formatting",
+            json!({"textDocument": {"uri": uri}, "options": {"tabSize": 2, "insertSpaces": true}}),
+        ),
+        request(
+            2,
+            "textDocument/formatting",
+            json!({"textDocument": {"uri": "file:///missing.ucg"}, "options": {"tabSize": 2}}),
+        ),
+    ]);
+    let edits = &response(&responses, 1)["result"];
+    assert_eq!(edits[0]["newText"], "let foo = {\n  a = 1,\n  b = 2,\n};\n");
+    assert_eq!(
+        edits[0]["range"]["start"],
+        json!({"line": 0, "character": 0})
+    );
+    assert_eq!(response(&responses, 2)["error"]["code"], -32602);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_formatting_uses_fmt_config() {
+    let dir = test_dir("formatting_config");
+    std::fs::write(dir.join(".ucgfmt.toml"), "indent = 3\nmax_width = 80\n").unwrap();
+    let uri = path_to_uri(&dir.join("main.ucg"));
+    let (_, responses) = run_session(vec![
+        open(&uri, "let foo = {a=1,b=[1, 2]};\n"),
+        request(
+            1,
+            "textDocument/formatting",
+            json!({"textDocument": {"uri": uri}, "options": {"tabSize": 2, "insertSpaces": true}}),
+        ),
+    ]);
+    let edits = &response(&responses, 1)["result"];
+    assert_eq!(edits[0]["newText"], "let foo = {a = 1, b = [1, 2]};\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_uri_round_trip() {
+    let path = PathBuf::from("/tmp/some dir/ücg.ucg");
+    let uri = path_to_uri(&path);
+    assert_eq!(uri, "file:///tmp/some%20dir/%C3%BCcg.ucg");
+    assert_eq!(uri_to_path(&uri), Some(path));
+    assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+}
@@ -18,46 +18,94 @@ extern crate rustyline;
 extern crate ucglib;
 
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
+use regex::Regex;
+use similar::TextDiff;
+use ucglib::ast::printer::{AstPrinter, FmtConfig};
 use ucglib::build;
+use ucglib::build::Val;
 use ucglib::build::opcode::Environment;
+use ucglib::build::report::{self, FileReport};
+use ucglib::convert::env::EnvConverter;
+use ucglib::convert::traits::Importer;
 use ucglib::convert::{ConverterRegistry, ImporterRegistry};
+use ucglib::error::{BuildError, ErrorType, FileBuildError, MessageFormat};
 use ucglib::iter::OffsetStrIter;
+use ucglib::lint::{Linter, Rule};
 use ucglib::parse::parse;
 
-fn do_flags<'a, 'b>() -> clap::App<'a, 'b> {
+fn do_flags<'a>(rule_names: &'a [&'a str]) -> clap::App<'a, 'a> {
     clap_app!(
         ucg =>
             (version: crate_version!())
             (author: crate_authors!())
             (about: "Universal Configuration Grammar compiler.")
             (@arg nostrict: --("no-strict") "Turn off strict checking.")
+            (@arg nocache: --("no-cache") "Don't use the on disk cache of compiled files.")
+            (@arg nowarnings: --("no-warnings") "Don't warn about unused bindings and imports.")
+            (@arg env_var: --("env-var") +takes_value +multiple number_of_values(1) "Only expose the named environment variable through the env symbol. Can be repeated.")
+            (@arg message_format: --("message-format") +takes_value possible_values(&["human", "json"]) "How to report errors. Either human or json. json reports one JSON object per error on stderr. Defaults to human.")
+            (@arg env_file: --("env-file") +takes_value +multiple number_of_values(1) "Read the variables for the env symbol from a dotenv file instead of the process environment. Can be repeated.")
             (@subcommand repl =>
                 (about: "Start the ucg repl for interactive evaluation.")
             )
+            (@subcommand eval =>
+             (about: "Evaluate a selector against the result of building a ucg file.")
+             (@arg format: -f --format +takes_value "The converter to output the result with. Defaults to json. Run `ucg converters` to see the available formats.")
+             (@arg INPUT: +required "Input ucg file to build.")
+             (@arg SELECTOR: "Selector for the value to output. ie: services.web.ports.0 If not provided then the whole result of the file is output.")
+            )
             (@subcommand build =>
              (about: "Build a list of ucg files.")
              (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg watch: -w --watch conflicts_with[jobs] "Keep running and rebuild files when they or the files they import or include change.")
+             (@arg jobs: -j --jobs +takes_value "How many files to build in parallel. Defaults to 1.")
              (@arg INPUT: ... "Input ucg files or directories to build. If not provided then build the contents of the current directory.")
             )
             (@subcommand test =>
              (about: "Check a list of ucg files for errors and run test assertions.")
              (@arg recurse: -r "Whether we should recurse or not.")
+             (@arg jobs: -j --jobs +takes_value "How many files to test in parallel. Defaults to 1.")
+             (@arg filter: --filter +takes_value "Only check the assertions whose desc matches this regular expression.")
+             (@arg fail_fast: --("fail-fast") "Stop testing after the first file that fails.")
+             (@arg report: --report +takes_value +multiple number_of_values(1) "Write a report of every assertion as FORMAT or FORMAT=FILE where FORMAT is junit or tap. Without a FILE the report goes to stdout and the usual output to stderr. Can be repeated.")
              (@arg INPUT: ... "Input ucg files or directories to run test assertions for. If not provided it will scan the current directory for files with _test.ucg")
             )
             (@subcommand fmt =>
              (about: "Format ucg files automatically.")
              (@arg recurse: -r "Whether we should recurse or not.")
-             (@arg indent: -i --indent "How many spaces to indent by. Defaults to 4")
+             (@arg indent: -i --indent +takes_value "How many spaces to indent by. Overrides .ucgfmt.toml. Defaults to 4")
+             (@arg width: --width +takes_value "The line width to keep lines under where possible. Overrides .ucgfmt.toml. Without it tuples, lists and argument lists are always broken across lines.")
              (@arg INPUT: ... "Input ucg files or directories to format")
              (@arg write: -w --overwrite "Whether to overwrite the with the formatted form")
+             (@arg check: --check "Don't change any files. Print a diff of the changes formatting would make and exit with an error if there are any.")
+            )
+            (@subcommand deps =>
+             (about: "Show the imports and includes between ucg files without building them.")
+             (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg format: -f --format +takes_value possible_values(&["dot", "json"]) "The format to output the dependency graph in. Either dot or json. Defaults to dot.")
+             (@arg reverse: --reverse +takes_value "List the files that import or include this file instead of the graph.")
+             (@arg INPUT: ... "Input ucg files or directories to find dependencies for. If not provided then use the contents of the current directory.")
+            )
+            (@subcommand lint =>
+             (about: "Check ucg files for likely mistakes without building them.")
+             (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg rule: --rule +takes_value +multiple number_of_values(1) possible_values(rule_names) "Only check this rule. Can be repeated. Defaults to every rule.")
+             (@arg allow: --allow +takes_value +multiple number_of_values(1) possible_values(rule_names) "Don't check this rule. Can be repeated.")
+             (@arg INPUT: ... "Input ucg files or directories to lint. If not provided then lint the contents of the current directory.")
             )
             (@subcommand converters =>
              (about: "list the available converters")
@@ -66,6 +114,9 @@ fn do_flags<'a, 'b>() -> clap::App<'a, 'b> {
             (@subcommand importers =>
              (about: "list the available importers for includes")
             )
+            (@subcommand lsp =>
+             (about: "Start a language server that speaks the Language Server Protocol over stdio.")
+            )
             (@subcommand env =>
              (about: "Describe the environment variables ucg uses.")
             )
@@ -96,31 +147,58 @@ impl Clone for StdoutWrapper {
     }
 }
 
-struct StderrWrapper(io::Stderr);
+/// Writes to stderr unless it is buffering the messages of a build worker.
+#[derive(Clone)]
+struct StderrWrapper(Option<Rc<RefCell<Vec<u8>>>>);
 
 impl StderrWrapper {
     fn new() -> Self {
-        Self(io::stderr())
+        Self(None)
+    }
+
+    /// Constructs a StderrWrapper that keeps what is written to it until it
+    /// gets taken.
+    fn buffered() -> Self {
+        Self(Some(Rc::new(RefCell::new(Vec::new()))))
+    }
+
+    /// Takes what has been buffered so far.
+    fn take(&self) -> Vec<u8> {
+        match self.0 {
+            Some(ref buf) => std::mem::take(&mut *buf.borrow_mut()),
+            None => Vec::new(),
+        }
     }
 }
 
 impl io::Write for StderrWrapper {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        match self.0 {
+            Some(ref buffer) => buffer.borrow_mut().write(buf),
+            None => io::stderr().write(buf),
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+        match self.0 {
+            Some(_) => Ok(()),
+            None => io::stderr().flush(),
+        }
     }
 }
 
-impl Clone for StderrWrapper {
-    fn clone(&self) -> Self {
-        Self(io::stderr())
-    }
+// TODO(jwall): Build sharable stdout stderr providers.
+fn report_error(err: &(dyn Error + 'static), format: MessageFormat) {
+    write_error(&mut io::stderr(), err, format);
+}
+
+fn write_error(w: &mut dyn Write, err: &(dyn Error + 'static), format: MessageFormat) {
+    let _ = match format {
+        MessageFormat::Human => writeln!(w, "{}", err),
+        MessageFormat::Json => writeln!(w, "{}", ucglib::error::diagnostic_json(err)),
+    };
 }
 
-// TODO(jwall): Build sharable stdout stderr providers.
 fn build_file<'a>(
     file: &'a str,
     validate: bool,
@@ -132,15 +210,12 @@ fn build_file<'a>(
     if file_path_buf.is_relative() {
         file_path_buf = std::env::current_dir()?.join(file_path_buf);
     }
-let mut builder = build::FileBuilder::new(std::env::current_dir()?, import_paths, env);
+    let mut builder = build::FileBuilder::new(std::env::current_dir()?, import_paths, env);
     builder.set_strict(strict);
     if validate {
         builder.enable_validate_mode();
     }
     builder.build(file_path_buf)?;
-    if validate {
-        println!("{}", builder.assert_summary());
-    }
     Ok(builder)
 }
 
@@ -149,23 +224,39 @@ fn do_validate<'a>(
     strict: bool,
     import_paths: &'a Vec<PathBuf>,
     env: &'a RefCell<Environment<StdoutWrapper, StderrWrapper>>,
+    format: MessageFormat,
+    out: &mut dyn Write,
+    err_out: &mut dyn Write,
+    reports: &mut Vec<FileReport>,
 ) -> bool {
-    println!("Validating {}", file);
-    match build_file(file, true, strict, import_paths, env) {
+    let _ = writeln!(out, "Validating {}", file);
+    // Every file gets its own assertion results.
+    env.borrow_mut().assert_results.reset();
+    let mut report = FileReport::new(file);
+    let result = build_file(file, true, strict, import_paths, env);
+    report.results = env.borrow_mut().assert_results.results.drain(..).collect();
+    let passed = match result {
         Ok(b) => {
+            let _ = writeln!(out, "{}", b.assert_summary());
             if b.assert_results() {
-                println!("File {} Pass\n", file);
+                let _ = writeln!(out, "File {} Pass\n", file);
+                true
             } else {
-                println!("File {} Fail\n", file);
-                return false;
+                let _ = writeln!(out, "File {} Fail\n", file);
+                false
             }
         }
-        Err(msg) => {
-            eprintln!("Err: {}", msg);
-            return false;
+        Err(err) => {
+            if format == MessageFormat::Human {
+                let _ = write!(err_out, "Err: ");
+            }
+            write_error(err_out, err.as_ref(), format);
+            report.error = Some(err.to_string());
+            false
         }
-    }
-    return true;
+    };
+    reports.push(report);
+    passed
 }
 
 fn do_compile<'a>(
@@ -173,113 +264,397 @@ fn do_compile<'a>(
     strict: bool,
     import_paths: &'a Vec<PathBuf>,
     env: &'a RefCell<Environment<StdoutWrapper, StderrWrapper>>,
+    format: MessageFormat,
+    out: &mut dyn Write,
+    err_out: &mut dyn Write,
 ) -> bool {
-    println!("Building {}", file);
+    let _ = writeln!(out, "Building {}", file);
     let builder = match build_file(file, false, strict, import_paths, env) {
         Ok(builder) => builder,
         Err(err) => {
-            eprintln!("{}", err);
+            write_error(err_out, err.as_ref(), format);
             return false;
         }
     };
     if builder.out.is_none() {
-        eprintln!("Build results in no artifacts.");
+        write_no_artifacts(err_out, file, format);
     }
     return true;
 }
 
-fn visit_ucg_files(
+fn write_no_artifacts(w: &mut dyn Write, file: &str, format: MessageFormat) {
+    let msg = "Build results in no artifacts.";
+    let _ = match format {
+        MessageFormat::Human => writeln!(w, "{}", msg),
+        MessageFormat::Json => {
+            let err = BuildError::new(msg, ErrorType::NoSuchSymbol).to_boxed();
+            let err = FileBuildError::new(file, err);
+            writeln!(w, "{}", ucglib::error::diagnostic_json(&err))
+        }
+    };
+}
+
+fn build_command(
+    matches: &clap::ArgMatches,
+    import_paths: &Vec<PathBuf>,
+    strict: bool,
+    env_opts: &EnvOptions,
+    format: MessageFormat,
+) {
+    if matches.is_present("watch") {
+        let inputs = match matches.values_of("INPUT") {
+            Some(files) => files.map(PathBuf::from).collect(),
+            None => vec![std::env::current_dir().unwrap()],
+        };
+        let env = RefCell::new(new_environment(env_opts, StderrWrapper::new()));
+        let recurse = matches.is_present("recurse");
+        watch_command(&inputs, recurse, strict, import_paths, &env, format);
+    }
+    run_files_command(matches, false, import_paths, strict, env_opts, format);
+}
+
+/// Collects the ucg files to build or test from an input path.
+fn collect_ucg_files(
     path: &Path,
     recurse: bool,
     validate: bool,
+    files: &mut Vec<PathBuf>,
+) -> std::result::Result<(), Box<dyn Error>> {
+    let is_test = |p: &Path| p.to_string_lossy().ends_with("_test.ucg");
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let next_path = entry?.path();
+            if next_path.is_dir() {
+                if recurse {
+                    collect_ucg_files(&next_path, recurse, validate, files)?;
+                }
+            } else if validate && is_test(&next_path) {
+                files.push(next_path);
+            } else if !validate && next_path.extension() == Some(OsStr::new("ucg")) {
+                files.push(next_path);
+            }
+        }
+    } else if !validate || is_test(path) {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Builds the inputs and then rebuilds the ones affected by a change to any
+/// file they read until we get killed.
+fn watch_command(
+    inputs: &[PathBuf],
+    recurse: bool,
     strict: bool,
     import_paths: &Vec<PathBuf>,
     env: &RefCell<Environment<StdoutWrapper, StderrWrapper>>,
-) -> Result<bool, Box<dyn Error>> {
-    let our_path = String::from(path.to_string_lossy());
-    let mut result = true;
-    let mut summary = String::new();
-    if path.is_dir() {
-        let mut dir_iter = std::fs::read_dir(path)?.peekable();
-        loop {
-            let entry = match dir_iter.next() {
-                Some(e) => e,
-                None => {
-                    break;
-                }
-            };
-            let next_item = entry?;
-            let next_path = next_item.path();
-            let path_as_string = String::from(next_path.to_string_lossy());
-            if next_path.is_dir() && recurse {
-                if let Err(e) = visit_ucg_files(&next_path, recurse, validate, strict, import_paths, env)
-                {
-                    eprintln!("{}", e);
-                    result = false;
-                }
-            } else {
-                if validate && path_as_string.ends_with("_test.ucg") {
-                    if !do_validate(&path_as_string, strict, import_paths, env) {
-                        result = false;
-                        summary.push_str(format!("{} - FAIL\n", path_as_string).as_str())
-                    } else {
-                        summary.push_str(format!("{} - PASS\n", path_as_string).as_str())
-                    }
-                } else if !validate && path_as_string.ends_with(".ucg") {
-                    if !do_compile(&path_as_string, strict, import_paths, env) {
-                        result = false;
-                    }
+    format: MessageFormat,
+) -> ! {
+    let mut mtimes: BTreeMap<PathBuf, Option<SystemTime>> = BTreeMap::new();
+    let mut entries = BTreeSet::new();
+    loop {
+        // Rescan the inputs so new files get built too.
+        let mut found = Vec::new();
+        for input in inputs {
+            if let Err(e) = collect_ucg_files(input, recurse, false, &mut found) {
+                report_error(e.as_ref(), format);
+            }
+        }
+        let mut current = BTreeSet::new();
+        for path in found {
+            match path.canonicalize() {
+                Ok(path) => {
+                    current.insert(path);
                 }
+                Err(e) => report_error(&e, format),
             }
         }
-    } else if validate && our_path.ends_with("_test.ucg") {
-        if !do_validate(&our_path, strict, import_paths, env) {
-            result = false;
-            summary.push_str(format!("{} - FAIL\n", our_path).as_str());
-        } else {
-            summary.push_str(format!("{} - PASS\n", &our_path).as_str());
+        let mut changed = BTreeSet::new();
+        for (path, mtime) in mtimes.iter_mut() {
+            let now = modified_time(path);
+            if now != *mtime {
+                *mtime = now;
+                changed.insert(path.clone());
+            }
         }
-    } else if !validate {
-        if !do_compile(&our_path, strict, import_paths, env) {
-            result = false;
+        let stale = env.borrow_mut().invalidate_paths(&changed);
+        let to_build: Vec<PathBuf> = current
+            .iter()
+            .filter(|p| !entries.contains(*p) || stale.contains(*p))
+            .cloned()
+            .collect();
+        for entry in to_build.iter() {
+            {
+                let mut env = env.borrow_mut();
+                env.clear_dependencies(entry);
+                env.reset_out_lock_for_path(entry);
+            }
+            do_compile(
+                &entry.to_string_lossy(),
+                strict,
+                import_paths,
+                env,
+                format,
+                &mut io::stdout(),
+                &mut io::stderr(),
+            );
+            let deps = env.borrow().get_dependencies(entry);
+            for path in std::iter::once(entry.clone()).chain(deps) {
+                let mtime = modified_time(&path);
+                mtimes.entry(path).or_insert(mtime);
+            }
+        }
+        if !to_build.is_empty() {
+            println!("Watching {} files for changes.", mtimes.len());
         }
+        entries = current;
+        thread::sleep(Duration::from_millis(500));
     }
-    if validate && !summary.is_empty() {
-        println!("RESULTS:");
-        println!("{}", summary);
+}
+
+/// The global flags that configure every Environment.
+#[derive(Debug, Clone)]
+struct EnvOptions {
+    env_vars: BTreeMap<String, String>,
+    use_cache: bool,
+    warnings: bool,
+    format: MessageFormat,
+}
+
+fn new_environment(
+    opts: &EnvOptions,
+    stderr: StderrWrapper,
+) -> Environment<StdoutWrapper, StderrWrapper> {
+    let mut env = Environment::new_with_vars(StdoutWrapper::new(), stderr, opts.env_vars.clone());
+    if opts.use_cache {
+        env.set_op_cache_dir(cache_dir().join("ops"));
     }
-    Ok(result)
+    env.warnings = opts.warnings;
+    env.message_format = opts.format;
+    env
 }
 
-fn build_command(
+fn jobs_flag(matches: &clap::ArgMatches) -> usize {
+    match matches.value_of("jobs").map(str::parse::<usize>) {
+        None => 1,
+        Some(Ok(n)) if n > 0 => n,
+        _ => {
+            eprintln!("--jobs must be a number greater than 0");
+            process::exit(1);
+        }
+    }
+}
+
+fn filter_flag(matches: &clap::ArgMatches) -> Option<Regex> {
+    match matches.value_of("filter").map(Regex::new) {
+        None => None,
+        Some(Ok(re)) => Some(re),
+        Some(Err(e)) => {
+            eprintln!("Invalid --filter: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Builds or tests the inputs on `jobs` worker threads.
+///
+/// The VM can't be shared between threads so every worker gets its own
+/// Environment. They share the on disk op cache and the set of files that
+/// have been output to so every output file still only gets written once.
+/// A single job runs on the current thread.
+fn run_files_command(
     matches: &clap::ArgMatches,
+    validate: bool,
     import_paths: &Vec<PathBuf>,
     strict: bool,
-    env: &RefCell<Environment<StdoutWrapper, StderrWrapper>>,
+    env_opts: &EnvOptions,
+    format: MessageFormat,
 ) {
-    let files = matches.values_of("INPUT");
     let recurse = matches.is_present("recurse");
+    let jobs = jobs_flag(matches);
+    let inputs: Vec<PathBuf> = match matches.values_of("INPUT") {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => vec![std::env::current_dir().unwrap()],
+    };
     let mut ok = true;
-    if files.is_none() {
-        let curr_dir = std::env::current_dir().unwrap();
-        let ok = visit_ucg_files(curr_dir.as_path(), recurse, false, strict, import_paths, env);
-        if let Ok(false) = ok {
-            process::exit(1)
+    let mut files = Vec::new();
+    for input in inputs.iter() {
+        if let Err(e) = collect_ucg_files(input, recurse, validate, &mut files) {
+            report_error(e.as_ref(), format);
+            ok = false;
+        }
+    }
+    let (reports, filter) = if validate {
+        (report_flags(matches), filter_flag(matches))
+    } else {
+        (Vec::new(), None)
+    };
+    let fail_fast = matches.is_present("fail_fast");
+    let failed = AtomicBool::new(false);
+    let next_file = Mutex::new(files.iter().enumerate());
+    // The files that didn't get run because of --fail-fast stay None.
+    let results = Mutex::new(vec![None; files.len()]);
+    let out_lock = Arc::new(Mutex::new(BTreeSet::new()));
+    let work = || {
+        // Warnings and TRACE output are buffered so they get written with
+        // the rest of the messages for the file they came from.
+        let stderr = StderrWrapper::buffered();
+        let env = RefCell::new(new_environment(env_opts, stderr.clone()));
+        env.borrow_mut().set_shared_out_lock(out_lock.clone());
+        if let Some(ref filter) = filter {
+            env.borrow_mut().assert_results.set_filter(filter.clone());
+        }
+        loop {
+            if fail_fast && failed.load(Ordering::SeqCst) {
+                break;
+            }
+            let (i, file) = match next_file.lock().unwrap().next() {
+                Some(next) => next,
+                None => break,
+            };
+            let file = file.to_string_lossy();
+            // Buffer the messages for a file so they don't get
+            // interleaved with the ones from other workers.
+            let mut out = Vec::new();
+            let mut err_out = Vec::new();
+            let mut file_reports = Vec::new();
+            let passed = if validate {
+                do_validate(
+                    &file,
+                    strict,
+                    import_paths,
+                    &env,
+                    format,
+                    &mut out,
+                    &mut err_out,
+                    &mut file_reports,
+                )
+            } else {
+                do_compile(
+                    &file,
+                    strict,
+                    import_paths,
+                    &env,
+                    format,
+                    &mut out,
+                    &mut err_out,
+                )
+            };
+            if !passed {
+                failed.store(true, Ordering::SeqCst);
+            }
+            results.lock().unwrap()[i] = Some((passed, file_reports));
+            let _ = test_output(&reports).write_all(&out);
+            let mut messages = stderr.take();
+            messages.extend(err_out);
+            let _ = io::stderr().lock().write_all(&messages);
         }
-        process::exit(0);
+    };
+    if jobs == 1 {
+        work();
+    } else {
+        thread::scope(|s| {
+            for _ in 0..jobs.min(files.len()) {
+                s.spawn(&work);
+            }
+        });
     }
-    for file in files.unwrap() {
-        let pb = PathBuf::from(file);
-        if let Ok(false) = visit_ucg_files(&pb, recurse, false, strict, import_paths, env) {
+    let results = results.into_inner().unwrap();
+    if validate && !files.is_empty() {
+        let mut out = test_output(&reports);
+        let _ = writeln!(out, "RESULTS:");
+        for (file, result) in files.iter().zip(results.iter()) {
+            if let Some((passed, _)) = result {
+                let _ = writeln!(
+                    out,
+                    "{} - {}",
+                    file.to_string_lossy(),
+                    if *passed { "PASS" } else { "FAIL" }
+                );
+            }
+        }
+        let _ = writeln!(out, "");
+        let file_reports: Vec<FileReport> = results
+            .iter()
+            .flatten()
+            .flat_map(|(_, reports)| reports.iter().cloned())
+            .collect();
+        if !write_reports(&reports, &file_reports) {
             ok = false;
         }
     }
-    if !ok {
-        process::exit(1)
+    if !ok || failed.load(Ordering::SeqCst) {
+        process::exit(1);
     }
+    process::exit(0);
+}
+
+fn eval_command(
+    matches: &clap::ArgMatches,
+    import_paths: &Vec<PathBuf>,
+    strict: bool,
+    env: &RefCell<Environment<StdoutWrapper, StderrWrapper>>,
+    registry: &ConverterRegistry,
+    format: MessageFormat,
+) {
+    let file = matches.value_of("INPUT").unwrap();
+    let selector = matches.value_of("SELECTOR").unwrap_or("");
+    let typ = matches.value_of("format").unwrap_or("json");
+    let converter = match registry.get_converter(typ) {
+        Some(c) => c,
+        None => {
+            eprintln!(
+                "No such format {}\nrun `ucg converters` to see available formats.",
+                typ
+            );
+            process::exit(1);
+        }
+    };
+    let builder = match build_file(file, false, strict, import_paths, env) {
+        Ok(builder) => builder,
+        Err(err) => {
+            report_error(err.as_ref(), format);
+            process::exit(1);
+        }
+    };
+    let val = match builder.get_out_by_selector(selector) {
+        Ok(val) => val,
+        Err(err) => {
+            report_error(err.as_ref(), format);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = converter.convert(val, &mut StdoutWrapper::new()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// What `ucg fmt` does with the formatted form of a file.
+#[derive(Clone, Copy, PartialEq)]
+enum FmtMode {
+    Print,
+    Overwrite,
+    Check,
 }
 
-fn fmt_file(p: &Path, indent: usize, overwrite: bool) -> std::result::Result<(), Box<dyn Error>> {
+/// The settings given on the command line which override any `.ucgfmt.toml`.
+#[derive(Clone, Copy)]
+struct FmtOptions {
+    indent: Option<usize>,
+    width: Option<usize>,
+}
+
+/// Formats a file returning whether it was already formatted.
+fn fmt_file(
+    p: &Path,
+    opts: FmtOptions,
+    mode: FmtMode,
+) -> std::result::Result<bool, Box<dyn Error>> {
     let mut contents = String::new();
     {
         let mut f = File::open(p)?;
@@ -287,41 +662,78 @@ fn fmt_file(p: &Path, indent: usize, overwrite: bool) -> std::result::Result<(),
     }
     let mut comment_map = BTreeMap::new();
     let stmts = parse(OffsetStrIter::new(&contents), Some(&mut comment_map))?;
-    if overwrite {
-        let mut printer = ucglib::ast::printer::AstPrinter::new(indent, File::create(p)?)
-            .with_comment_map(&comment_map);
-        printer.render(&stmts)?;
-    } else {
-        let mut printer = ucglib::ast::printer::AstPrinter::new(indent, std::io::stdout())
-            .with_comment_map(&comment_map);
-        printer.render(&stmts)?;
+    let mut config = FmtConfig::for_file(p)?;
+    if let Some(indent) = opts.indent {
+        config.indent = indent;
     }
-    Ok(())
+    if let Some(width) = opts.width {
+        config.max_width = Some(width);
+    }
+    let mut formatted = Vec::new();
+    let mut printer = AstPrinter::from_config(&config, &mut formatted)
+        .with_comment_map(&comment_map)
+        .with_source(&contents);
+    printer.render(&stmts)?;
+    let formatted = String::from_utf8(formatted)?;
+    let unchanged = formatted == contents;
+    match mode {
+        FmtMode::Print => io::stdout().write_all(formatted.as_bytes())?,
+        FmtMode::Overwrite => {
+            if !unchanged {
+                std::fs::write(p, &formatted)?;
+            }
+        }
+        FmtMode::Check => {
+            if !unchanged {
+                let name = p.to_string_lossy();
+                let diff = TextDiff::from_lines(&contents, &formatted);
+                print!("{}", diff.unified_diff().header(&name, &name));
+            }
+        }
+    }
+    Ok(unchanged)
 }
 
-fn fmt_dir(p: &Path, recurse: bool, indent: usize) -> std::result::Result<(), Box<dyn Error>> {
+fn fmt_dir(
+    p: &Path,
+    recurse: bool,
+    opts: FmtOptions,
+    mode: FmtMode,
+) -> std::result::Result<bool, Box<dyn Error>> {
     // TODO(jwall): We should handle this error more gracefully
     // for the user here.
+    let mut unchanged = true;
     let dir_iter = std::fs::read_dir(p)?.peekable();
     for entry in dir_iter {
         let next_item = entry.unwrap();
         let path = next_item.path();
-        if path.is_dir() && recurse {
-            fmt_dir(&path, recurse, indent)?;
-        } else {
-            fmt_file(&path, indent, true)?;
+        if path.is_dir() {
+            if recurse {
+                unchanged &= fmt_dir(&path, recurse, opts, mode)?;
+            }
+        } else if path.extension() == Some(OsStr::new("ucg")) {
+            unchanged &= fmt_file(&path, opts, mode)?;
         }
     }
-    Ok(())
+    Ok(unchanged)
 }
 
-fn fmt_command(matches: &clap::ArgMatches) -> std::result::Result<(), Box<dyn Error>> {
+/// Formats the inputs. Returns false if `--check` found a file that isn't
+/// formatted.
+fn fmt_command(matches: &clap::ArgMatches) -> std::result::Result<bool, Box<dyn Error>> {
     let files = matches.values_of("INPUT");
     let recurse = matches.is_present("recurse");
+    let check = matches.is_present("check");
     let overwrite = matches.is_present("write");
-    let indent = match matches.value_of("indent") {
-        Some(s) => s.parse::<usize>()?,
-        None => 4,
+    let opts = FmtOptions {
+        indent: match matches.value_of("indent") {
+            Some(s) => Some(s.parse::<usize>()?),
+            None => None,
+        },
+        width: match matches.value_of("width") {
+            Some(s) => Some(s.parse::<usize>()?),
+            None => None,
+        },
     };
 
     let mut paths = Vec::new();
@@ -332,44 +744,204 @@ fn fmt_command(matches: &clap::ArgMatches) -> std::result::Result<(), Box<dyn Er
             paths.push(PathBuf::from(f));
         }
     }
+    let mut unchanged = true;
     for p in paths {
         if p.is_dir() {
-            fmt_dir(&p, recurse, indent)?;
+            let mode = if check {
+                FmtMode::Check
+            } else {
+                FmtMode::Overwrite
+            };
+            unchanged &= fmt_dir(&p, recurse, opts, mode)?;
         } else {
-            fmt_file(&p, indent, overwrite)?;
+            let mode = if check {
+                FmtMode::Check
+            } else if overwrite {
+                FmtMode::Overwrite
+            } else {
+                FmtMode::Print
+            };
+            unchanged &= fmt_file(&p, opts, mode)?;
         }
     }
-    Ok(())
+    Ok(unchanged || !check)
 }
 
-fn test_command(
-    matches: &clap::ArgMatches,
-    import_paths: &Vec<PathBuf>,
-    strict: bool,
-    env: &RefCell<Environment<StdoutWrapper, StderrWrapper>>,
-) {
-    let files = matches.values_of("INPUT");
+#[derive(Clone, Copy, PartialEq)]
+enum ReportFormat {
+    Junit,
+    Tap,
+}
+
+/// Where to write a report of the test results and in what format.
+struct Report {
+    format: ReportFormat,
+    path: Option<PathBuf>,
+}
+
+fn report_flags(matches: &clap::ArgMatches) -> Vec<Report> {
+    let mut reports = Vec::new();
+    for spec in matches.values_of("report").into_iter().flatten() {
+        let mut parts = spec.splitn(2, '=');
+        let format = match parts.next() {
+            Some("junit") => ReportFormat::Junit,
+            Some("tap") => ReportFormat::Tap,
+            _ => {
+                eprintln!("Unknown report format in {}. Expected junit or tap.", spec);
+                process::exit(1);
+            }
+        };
+        reports.push(Report {
+            format: format,
+            path: parts.next().map(PathBuf::from),
+        });
+    }
+    reports
+}
+
+/// The usual test output goes to stderr when a report goes to stdout so the
+/// report can be parsed.
+fn test_output(reports: &[Report]) -> Box<dyn Write> {
+    if reports.iter().any(|r| r.path.is_none()) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+fn write_reports(reports: &[Report], file_reports: &[FileReport]) -> bool {
+    let mut ok = true;
+    for r in reports {
+        let w: Box<dyn Write> = match r.path {
+            Some(ref p) => match File::create(p) {
+                Ok(f) => Box::new(io::BufWriter::new(f)),
+                Err(e) => {
+                    eprintln!("Unable to create report {}: {}", p.to_string_lossy(), e);
+                    ok = false;
+                    continue;
+                }
+            },
+            None => Box::new(io::stdout()),
+        };
+        let result = match r.format {
+            ReportFormat::Junit => report::write_junit(file_reports, w),
+            ReportFormat::Tap => report::write_tap(file_reports, w),
+        };
+        if let Err(e) = result {
+            eprintln!("Unable to write report: {}", e);
+            ok = false;
+        }
+    }
+    ok
+}
+
+fn lint_command(matches: &clap::ArgMatches, format: MessageFormat) {
     let recurse = matches.is_present("recurse");
-    if files.is_none() {
-        let curr_dir = std::env::current_dir().unwrap();
-        let ok = visit_ucg_files(curr_dir.as_path(), recurse, true, strict, import_paths, env);
-        if let Ok(false) = ok {
-            process::exit(1)
+    let inputs: Vec<PathBuf> = match matches.values_of("INPUT") {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => vec![std::env::current_dir().unwrap()],
+    };
+    let mut rules = match matches.values_of("rule") {
+        Some(names) => names.filter_map(Rule::from_name).collect(),
+        None => Rule::all(),
+    };
+    if let Some(names) = matches.values_of("allow") {
+        let allowed: Vec<Rule> = names.filter_map(Rule::from_name).collect();
+        rules.retain(|r| !allowed.contains(r));
+    }
+    let mut files = Vec::new();
+    for input in inputs {
+        if let Err(e) = collect_ucg_files(&input, recurse, false, &mut files) {
+            eprintln!("{}: {}", input.to_string_lossy(), e);
+            process::exit(1);
         }
-    } else {
-        let mut ok = true;
-        for file in files.unwrap() {
-            let pb = PathBuf::from(file);
-            //if pb.is_dir() {
-            if let Ok(false) = visit_ucg_files(pb.as_path(), recurse, true, strict, import_paths, env) {
+    }
+    let mut linter = Linter::new(rules);
+    let mut ok = true;
+    for file in files {
+        match linter.lint_file(&file) {
+            Ok(lints) => {
+                for lint in lints {
+                    ok = false;
+                    match format {
+                        MessageFormat::Human => {
+                            eprint!("{}", ucglib::error::render_warning(&lint))
+                        }
+                        MessageFormat::Json => {
+                            eprintln!("{}", ucglib::error::diagnostic_json(&lint))
+                        }
+                    }
+                }
+            }
+            Err(e) => {
                 ok = false;
+                report_error(&ucglib::error::FileBuildError::new(&file, e), format);
             }
         }
-        if !ok {
-            process::exit(1)
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn deps_command(matches: &clap::ArgMatches, import_paths: &Vec<PathBuf>) {
+    let recurse = matches.is_present("recurse");
+    let curr_dir = std::env::current_dir().unwrap();
+    let inputs: Vec<PathBuf> = match matches.values_of("INPUT") {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => vec![curr_dir.clone()],
+    };
+    let mut graph = ucglib::deps::DependencyGraph::new(import_paths);
+    for input in inputs {
+        if let Err(e) = graph.add_path(&input, recurse) {
+            eprintln!("{}: {}", input.to_string_lossy(), e);
+            process::exit(1);
         }
     }
-    process::exit(0);
+    let root = curr_dir.canonicalize().unwrap_or(curr_dir);
+    let json = matches.value_of("format") == Some("json");
+    if let Some(target) = matches.value_of("reverse") {
+        let dependents: Vec<String> = graph
+            .dependents_of(target)
+            .iter()
+            .map(|p| ucglib::deps::display_path(&root, p))
+            .collect();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&dependents).unwrap());
+        } else {
+            for p in dependents {
+                println!("{}", p);
+            }
+        }
+    } else if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&graph.to_json(&root)).unwrap()
+        );
+    } else if let Err(e) = graph.write_dot(&root, &mut io::stdout()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    let mut ok = true;
+    for (path, err) in graph.errors.iter() {
+        eprintln!(
+            "Error parsing {}: {}",
+            ucglib::deps::display_path(&root, path),
+            err
+        );
+        ok = false;
+    }
+    for cycle in graph.cycles() {
+        let cycle: Vec<String> = cycle
+            .iter()
+            .map(|p| ucglib::deps::display_path(&root, p))
+            .collect();
+        eprintln!("Import cycle detected: {}", cycle.join(" -> "));
+        ok = false;
+    }
+    if !ok {
+        process::exit(1);
+    }
 }
 
 fn converters_command(matches: &clap::ArgMatches, registry: &ConverterRegistry) {
@@ -409,6 +981,21 @@ fn importers_command(registry: &ImporterRegistry) {
     }
 }
 
+fn lsp_command(import_paths: &Vec<PathBuf>, strict: bool, env_vars: BTreeMap<String, String>) {
+    let stdin = io::stdin();
+    let mut server = ucglib::lsp::Server::new(io::stdout(), import_paths.clone())
+        .with_strict(strict)
+        .with_env_vars(env_vars);
+    match server.serve(stdin.lock()) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
 fn env_help() {
     println!(
         include_str!("help/env.txt"),
@@ -416,7 +1003,48 @@ fn env_help() {
     );
 }
 
-fn do_repl(import_paths: &Vec<PathBuf>, strict: bool) -> std::result::Result<(), Box<dyn Error>> {
+fn load_env_vars(matches: &clap::ArgMatches) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let mut vars = BTreeMap::new();
+    if let Some(files) = matches.values_of("env_file") {
+        // Later files override earlier ones.
+        for file in files {
+            let contents = std::fs::read(file)?;
+            let tpl = EnvConverter::new()
+                .import(&contents)
+                .map_err(|e| format!("Invalid env file {}: {}", file, e))?;
+            if let Val::Tuple(ref flds) = tpl.as_ref() {
+                for (name, val) in flds.iter() {
+                    // Dotenv files only ever import strings.
+                    if let Val::Str(ref s) = val.as_ref() {
+                        vars.insert(name.clone(), s.clone());
+                    }
+                }
+            }
+        }
+    } else {
+        vars.extend(std::env::vars());
+    }
+    if let Some(allowed) = matches.values_of("env_var") {
+        let allowed: BTreeSet<&str> = allowed.collect();
+        vars.retain(|name, _| allowed.contains(name.as_str()));
+    }
+    Ok(vars)
+}
+
+fn cache_dir() -> PathBuf {
+    let path_home = dirs::home_dir().unwrap_or(std::env::temp_dir());
+    let cache_home = std::env::var("XDG_CACHE_HOME")
+        .unwrap_or_else(|_| format!("{}/.cache", path_home.to_string_lossy()));
+    let mut cache_home = PathBuf::from(cache_home);
+    cache_home.push("ucg");
+    cache_home
+}
+
+fn do_repl(
+    import_paths: &Vec<PathBuf>,
+    strict: bool,
+    env_opts: &EnvOptions,
+) -> std::result::Result<(), Box<dyn Error>> {
     let config = rustyline::Config::builder();
     let mut editor = rustyline::Editor::<()>::with_config(
         config
@@ -424,11 +1052,7 @@ fn do_repl(import_paths: &Vec<PathBuf>, strict: bool) -> std::result::Result<(),
             .history_ignore_dups(false)
             .build(),
     );
-    let path_home = dirs::home_dir().unwrap_or(std::env::temp_dir());
-    let config_home = std::env::var("XDG_CACHE_HOME")
-        .unwrap_or_else(|_| format!("{}/.cache", path_home.to_string_lossy()));
-    let mut config_home = PathBuf::from(config_home);
-    config_home.push("ucg");
+    let mut config_home = cache_dir();
     config_home.push("line_hist");
     if editor.load_history(&config_home).is_err() {
         eprintln!(
@@ -448,32 +1072,50 @@ fn do_repl(import_paths: &Vec<PathBuf>, strict: bool) -> std::result::Result<(),
             }
         }
     }
-    let env = std::cell::RefCell::new(build::opcode::Environment::new(StdoutWrapper::new(), StderrWrapper::new()));
-    let mut builder = build::FileBuilder::new(
-        std::env::current_dir()?,
-        import_paths,
-        &env,
-    );
+    let env = RefCell::new(new_environment(env_opts, StderrWrapper::new()));
+    let mut builder = build::FileBuilder::new(std::env::current_dir()?, import_paths, &env);
     builder.set_strict(strict);
 
     builder.repl(editor, config_home)?;
     Ok(())
 }
 
-fn repl(import_paths: &Vec<PathBuf>, strict: bool) {
-    if let Err(e) = do_repl(import_paths, strict) {
+fn repl(
+    import_paths: &Vec<PathBuf>,
+    strict: bool,
+    env_opts: &EnvOptions,
+) {
+    if let Err(e) = do_repl(import_paths, strict, env_opts) {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
 
 fn main() {
-    let mut app = do_flags();
+    let rule_names: Vec<&str> = Rule::all().iter().map(Rule::name).collect();
+    let mut app = do_flags(&rule_names);
     let app_matches = app.clone().get_matches();
     // FIXME(jwall): Do we want these to be shared or not?
     let registry = ConverterRegistry::make_registry();
     let mut import_paths = Vec::new();
-    let env = RefCell::new(Environment::new(StdoutWrapper::new(), StderrWrapper::new()));
+    let env_vars = match load_env_vars(&app_matches) {
+        Ok(vars) => vars,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let format = match app_matches.value_of("message_format") {
+        Some("json") => MessageFormat::Json,
+        _ => MessageFormat::Human,
+    };
+    let env_opts = EnvOptions {
+        env_vars,
+        use_cache: !app_matches.is_present("nocache"),
+        warnings: !app_matches.is_present("nowarnings"),
+        format,
+    };
+    let env = RefCell::new(new_environment(&env_opts, StderrWrapper::new()));
     if let Some(mut p) = dirs::home_dir() {
         p.push(".ucg");
         // Attempt to create directory if it doesn't exist.
@@ -492,10 +1134,23 @@ fn main() {
     } else {
         true
     };
-    if let Some(matches) = app_matches.subcommand_matches("build") {
-        build_command(matches, &import_paths, strict, &env);
+    if let Some(matches) = app_matches.subcommand_matches("eval") {
+        eval_command(matches, &import_paths, strict, &env, &registry, format);
+    } else if let Some(matches) = app_matches.subcommand_matches("build") {
+        build_command(matches, &import_paths, strict, &env_opts, format);
     } else if let Some(matches) = app_matches.subcommand_matches("test") {
-        test_command(matches, &import_paths, strict, &env);
+        run_files_command(
+            matches,
+            true,
+            &import_paths,
+            strict,
+            &env_opts,
+            format,
+        );
+    } else if let Some(matches) = app_matches.subcommand_matches("deps") {
+        deps_command(matches, &import_paths)
+    } else if let Some(matches) = app_matches.subcommand_matches("lint") {
+        lint_command(matches, format)
     } else if let Some(matches) = app_matches.subcommand_matches("converters") {
         converters_command(matches, &registry)
     } else if let Some(_) = app_matches.subcommand_matches("importers") {
@@ -503,15 +1158,101 @@ fn main() {
         importers_command(&registry)
     } else if let Some(_) = app_matches.subcommand_matches("env") {
         env_help()
+    } else if let Some(_) = app_matches.subcommand_matches("lsp") {
+        lsp_command(&import_paths, strict, env_opts.env_vars)
     } else if let Some(_) = app_matches.subcommand_matches("repl") {
-        repl(&import_paths, strict)
+        repl(&import_paths, strict, &env_opts)
     } else if let Some(matches) = app_matches.subcommand_matches("fmt") {
-        if let Err(e) = fmt_command(matches) {
-            eprintln!("{}", e);
-            process::exit(1);
+        match fmt_command(matches) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
         }
     } else {
         app.print_help().unwrap();
         println!("");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{do_flags, load_env_vars, write_no_artifacts};
+    use ucglib::error::MessageFormat;
+    use ucglib::lint::Rule;
+
+    #[test]
+    fn test_lint_accepts_every_rule_name() {
+        let rule_names: Vec<&str> = Rule::all().iter().map(Rule::name).collect();
+        for name in rule_names.iter() {
+            let args = vec!["ucg", "lint", "--rule", name, "--allow", name];
+            assert!(do_flags(&rule_names).get_matches_from_safe(args).is_ok());
+        }
+        let args = vec!["ucg", "lint", "--rule", "no-such-rule"];
+        assert!(do_flags(&rule_names).get_matches_from_safe(args).is_err());
+    }
+
+    #[test]
+    fn test_fmt_indent_takes_a_value() {
+        let matches = do_flags(&[]).get_matches_from(vec!["ucg", "fmt", "-i", "2", "foo.ucg"]);
+        let matches = matches.subcommand_matches("fmt").unwrap();
+        assert_eq!(matches.value_of("indent"), Some("2"));
+        assert_eq!(
+            matches.values_of("INPUT").unwrap().collect::<Vec<_>>(),
+            vec!["foo.ucg"]
+        );
+    }
+
+    #[test]
+    fn test_no_artifacts_message_is_json() {
+        let mut err_out = Vec::new();
+        write_no_artifacts(&mut err_out, "main.ucg", MessageFormat::Json);
+        let err_out = String::from_utf8(err_out).unwrap();
+        let diagnostic: serde_json::Value = serde_json::from_str(err_out.trim_end()).unwrap();
+        assert_eq!(diagnostic["message"], "Build results in no artifacts.");
+        assert_eq!(diagnostic["file"], "main.ucg");
+        let mut err_out = Vec::new();
+        write_no_artifacts(&mut err_out, "main.ucg", MessageFormat::Human);
+        assert_eq!(err_out, b"Build results in no artifacts.\n");
+    }
+
+    #[test]
+    fn test_env_file_is_read_as_dotenv() {
+        let dir = std::env::temp_dir().join(format!("ucg_env_file_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.env");
+        let second = dir.join("second.env");
+        std::fs::write(
+            &first,
+            "# A comment\nUSER=me # trailing\nMULTI=\"one\ntwo\"\nESCAPED=\"a \\\"b\\\"\\n\"\n",
+        )
+        .unwrap();
+        std::fs::write(&second, "export USER='you'\n").unwrap();
+        let (first, second) = (first.to_string_lossy(), second.to_string_lossy());
+        let args = vec!["ucg", "--env-file", &first, "--env-file", &second, "build"];
+        let vars = load_env_vars(&do_flags(&[]).get_matches_from(args)).unwrap();
+        let vars: Vec<(&str, &str)> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            vars,
+            vec![
+                ("ESCAPED", "a \"b\"\n"),
+                ("MULTI", "one\ntwo"),
+                ("USER", "you")
+            ]
+        );
+        std::fs::write(dir.join("bad.env"), "A=\"open\n").unwrap();
+        let bad = dir.join("bad.env").to_string_lossy().to_string();
+        let args = vec!["ucg", "--env-file", &bad, "build"];
+        let err = load_env_vars(&do_flags(&[]).get_matches_from(args)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Invalid env file {}: ParseError: Unterminated quoted value at line: 1",
+                bad
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}